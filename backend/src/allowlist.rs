@@ -0,0 +1,123 @@
+use crate::config::AllowlistSource;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/// User IDs let in during a closed beta
+/// A file-backed list can be reloaded while running (see `reload_if_changed`);
+/// until a reload succeeds, the last good list stays in force.
+pub struct Allowlist {
+    ids: RwLock<HashSet<String>>,
+    /// The file the IDs came from and its modification time when last read
+    file: Option<(PathBuf, RwLock<Option<SystemTime>>)>,
+}
+
+impl Allowlist {
+    pub fn from_source(source: &AllowlistSource) -> Result<Self> {
+        match source {
+            AllowlistSource::Ids(ids) => Ok(Self {
+                ids: RwLock::new(ids.iter().cloned().collect()),
+                file: None,
+            }),
+            AllowlistSource::File(path) => {
+                let (ids, modified) = read_file(path)?;
+                Ok(Self {
+                    ids: RwLock::new(ids),
+                    file: Some((path.clone(), RwLock::new(modified))),
+                })
+            }
+        }
+    }
+
+    pub fn allows(&self, user_id: &str) -> bool {
+        self.ids.read().unwrap().contains(user_id)
+    }
+
+    /// Number of allowed user IDs
+    pub fn count(&self) -> usize {
+        self.ids.read().unwrap().len()
+    }
+
+    /// Re-read the file if it changed since it was last read
+    /// Returns whether the list was replaced
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let Some((path, last_modified)) = &self.file else {
+            return Ok(false);
+        };
+        let modified = std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified.is_some() && modified == *last_modified.read().unwrap() {
+            return Ok(false);
+        }
+        let (ids, modified) = read_file(path)?;
+        *self.ids.write().unwrap() = ids;
+        *last_modified.write().unwrap() = modified;
+        Ok(true)
+    }
+}
+
+/// One user ID per line; blank lines and `#` comments are skipped
+fn read_file(path: &Path) -> Result<(HashSet<String>, Option<SystemTime>)> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("reading allowlist {}", path.display()))?;
+    let modified = std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok();
+    let ids = contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .collect();
+    Ok((ids, modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn only_listed_ids_are_allowed() {
+        let source = AllowlistSource::Ids(vec!["alice".to_string(), "bob".to_string()]);
+        let allowlist = Allowlist::from_source(&source).unwrap();
+        assert!(allowlist.allows("alice"));
+        assert!(allowlist.allows("bob"));
+        assert!(!allowlist.allows("mallory"));
+        assert_eq!(allowlist.count(), 2);
+        // Nothing to reload without a file
+        assert!(!allowlist.reload_if_changed().unwrap());
+    }
+
+    #[test]
+    fn file_is_reloaded_when_it_changes() {
+        let path = std::env::temp_dir().join(format!("allowlist-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "# beta testers\nalice\n\n  bob  # team\n").unwrap();
+        let allowlist = Allowlist::from_source(&AllowlistSource::File(path.clone())).unwrap();
+        assert!(allowlist.allows("alice"));
+        assert!(allowlist.allows("bob"));
+        assert!(!allowlist.allows("carol"));
+        assert!(!allowlist.reload_if_changed().unwrap());
+
+        std::fs::write(&path, "carol\n").unwrap();
+        // Make sure the change shows even on coarse-grained file times
+        let later = SystemTime::now() + Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(allowlist.reload_if_changed().unwrap());
+        assert!(allowlist.allows("carol"));
+        assert!(!allowlist.allows("alice"));
+
+        // A file that can't be read keeps the last good list
+        std::fs::remove_file(&path).unwrap();
+        assert!(allowlist.reload_if_changed().is_err());
+        assert!(allowlist.allows("carol"));
+    }
+}
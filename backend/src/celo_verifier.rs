@@ -1,16 +1,63 @@
+use crate::location_store::now_secs;
 use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// How long a verification result is reused before asking Celo again
+const VERIFY_CACHE_TTL_SECS: i64 = 600;
 
 /// Celo UID verifier for Self Protocol authentication
 /// Verifies that the Celo UID from Self app matches the user ID
-pub struct CeloVerifier {}
+pub struct CeloVerifier {
+    // (Celo UID, user ID) -> (verified, when it was checked)
+    cache: RwLock<HashMap<(String, String), (bool, i64)>>,
+    /// Canned answers by Celo UID; `None` fails as if Celo were unreachable
+    #[cfg(test)]
+    answers: RwLock<HashMap<String, Option<bool>>>,
+}
 
 impl CeloVerifier {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            cache: RwLock::new(HashMap::new()),
+            #[cfg(test)]
+            answers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Make Celo answer `answer` for `celo_uid` (`None` for an error)
+    #[cfg(test)]
+    pub fn set_answer(&self, celo_uid: &str, answer: Option<bool>) {
+        self.answers
+            .write()
+            .unwrap()
+            .insert(celo_uid.to_string(), answer);
     }
 
     /// Verify that Celo UID matches user ID
+    /// Results are cached briefly; errors are not cached
     pub async fn verify_uid(&self, celo_uid: &str, user_id: &str) -> Result<bool> {
+        let key = (celo_uid.to_string(), user_id.to_string());
+        let now = now_secs();
+
+        if let Some(&(verified, checked_at)) = self.cache.read().unwrap().get(&key) {
+            if now - checked_at < VERIFY_CACHE_TTL_SECS {
+                return Ok(verified);
+            }
+        }
+
+        let verified = self.query_uid(celo_uid, user_id).await?;
+        self.cache.write().unwrap().insert(key, (verified, now));
+        Ok(verified)
+    }
+
+    /// Ask Celo whether the UID matches the user ID, bypassing the cache
+    async fn query_uid(&self, celo_uid: &str, user_id: &str) -> Result<bool> {
+        #[cfg(test)]
+        if let Some(&answer) = self.answers.read().unwrap().get(celo_uid) {
+            return answer.ok_or_else(|| anyhow::anyhow!("Celo node unreachable"));
+        }
+
         // TODO: Implement Celo UID verification
         //
         // This should:
@@ -0,0 +1,558 @@
+use crate::history;
+use anyhow::{anyhow, Result};
+use serde::{Serialize, Serializer};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// What to do with a location update that moved less than the threshold
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MovementMode {
+    /// Keep the previous coordinates but refresh the timestamp
+    Touch,
+    /// Ignore the update entirely
+    Skip,
+}
+
+impl FromStr for MovementMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "touch" => Ok(Self::Touch),
+            "skip" => Ok(Self::Skip),
+            other => Err(anyhow!("expected `touch` or `skip`, got `{}`", other)),
+        }
+    }
+}
+
+/// Log output format
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    Pretty,
+    /// One JSON object per line, for log pipelines
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            other => Err(anyhow!("expected `pretty` or `json`, got `{}`", other)),
+        }
+    }
+}
+
+/// How location history is kept in memory
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryEncoding {
+    /// Every point in full
+    Plain,
+    /// Deltas from the previous point: smaller, but decoded on every read
+    Delta,
+}
+
+impl FromStr for HistoryEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "plain" => Ok(Self::Plain),
+            "delta" => Ok(Self::Delta),
+            other => Err(anyhow!("expected `plain` or `delta`, got `{}`", other)),
+        }
+    }
+}
+
+/// What the friendship reconciler does with one-sided friendships
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconcileMode {
+    /// Add the missing side, making the friendship mutual
+    Repair,
+    /// Only log them, for a human to look at
+    Log,
+}
+
+impl FromStr for ReconcileMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "repair" => Ok(Self::Repair),
+            "log" => Ok(Self::Log),
+            other => Err(anyhow!("expected `repair` or `log`, got `{}`", other)),
+        }
+    }
+}
+
+/// Where the location store is persisted
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "location", rename_all = "snake_case")]
+pub enum StoreBackend {
+    /// Nothing is persisted; state is lost on restart
+    Memory,
+    /// JSON snapshot at this path
+    File(PathBuf),
+    /// JSON snapshot in Redis at this connection URL (may hold a password)
+    Redis(#[serde(serialize_with = "redact")] String),
+}
+
+/// Where the closed beta allowlist of user IDs comes from
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum AllowlistSource {
+    /// Comma-separated IDs from `BETA_ALLOWLIST`
+    Ids(Vec<String>),
+    /// One ID per line in this file, reloaded when it changes
+    File(PathBuf),
+}
+
+/// Dotted numeric client version like `2.4.1`, compared part by part
+/// (missing trailing parts count as 0, so `2.4` == `2.4.0`)
+#[derive(Debug, Clone)]
+pub struct AppVersion(Vec<u64>);
+
+impl FromStr for AppVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.trim()
+            .split('.')
+            .map(|part| part.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map(AppVersion)
+            .map_err(|_| anyhow!("expected a version like `1.2.3`, got `{}`", s))
+    }
+}
+
+impl std::fmt::Display for AppVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(u64::to_string).collect();
+        f.write_str(&parts.join("."))
+    }
+}
+
+impl Serialize for AppVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl PartialEq for AppVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for AppVersion {}
+
+impl PartialOrd for AppVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AppVersion {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let len = self.0.len().max(other.0.len());
+        let part = |v: &AppVersion, i: usize| v.0.get(i).copied().unwrap_or(0);
+        (0..len)
+            .map(|i| part(self, i).cmp(&part(other, i)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }
+}
+
+/// Client version gating, based on the `X-App-Version` header
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionPolicy {
+    /// Older clients are rejected with `426`
+    pub min_version: Option<AppVersion>,
+    /// Older clients still work but get `Deprecation` (and `Sunset`) headers
+    pub deprecated_below: Option<AppVersion>,
+    /// HTTP date after which deprecated clients will stop working
+    pub sunset: Option<String>,
+}
+
+/// Minimum-movement filtering for GPS jitter
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MovementConfig {
+    /// Moves shorter than this many meters are not recorded (disabled when `None`)
+    pub min_meters: Option<f64>,
+    pub mode: MovementMode,
+}
+
+/// Periodic scan for one-sided friendships on Sapphire
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconcileConfig {
+    /// Seconds between scans (no scans when `None`)
+    pub interval_secs: Option<u64>,
+    pub mode: ReconcileMode,
+}
+
+/// Fading out ageing locations instead of showing them at full precision
+/// until they're gone
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrivacyDecay {
+    /// Fixes older than this many seconds start losing precision
+    pub start_secs: i64,
+    /// Fixes older than this many seconds are hidden
+    pub hide_secs: i64,
+}
+
+/// Write-ahead logging for the `file` store backend
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WalConfig {
+    /// Append each write to a log instead of rewriting the whole snapshot
+    pub enabled: bool,
+    /// Fold the log into the snapshot once it grows past this many bytes
+    pub compact_bytes: u64,
+}
+
+/// Caps on pending friend requests, to stop inbox flooding
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestLimits {
+    /// Max pending requests a user may have sent
+    pub max_outgoing_pending: usize,
+    /// Max pending requests a user may have received
+    pub max_incoming_pending: usize,
+}
+
+/// Most decimal places `COORDINATE_DECIMALS` may keep; beyond this, f64
+/// rounding itself is no longer exact
+const MAX_COORDINATE_DECIMALS: u32 = 10;
+
+/// Server configuration loaded from the environment at startup
+/// Serializes with secrets redacted, for `GET /admin/config`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    /// Bearer token for `/admin` and other operator endpoints (disabled when unset)
+    #[serde(serialize_with = "redact_opt")]
+    pub admin_token: Option<String>,
+    /// How long a location fix counts as fresh, in seconds
+    pub location_ttl_secs: i64,
+    /// How old a fix may be for realtime features (ETA, convergence, radar),
+    /// in seconds; much tighter than the general TTL
+    pub realtime_freshness_secs: i64,
+    /// City-level ground resolution in meters, corrected for latitude
+    /// (plain 2-decimal rounding when `None`)
+    pub privacy_rounding_meters: Option<f64>,
+    /// Coarsen locations as they age (disabled when `None`)
+    pub privacy_decay: Option<PrivacyDecay>,
+    pub movement: MovementConfig,
+    pub request_limits: RequestLimits,
+    /// Reject friend requests to users the server has never seen with `404`
+    pub strict_friend_requests: bool,
+    /// Batch friend request events into one digest per receiver over this
+    /// many seconds (one event per request when unset)
+    pub friend_request_digest_secs: Option<u64>,
+    pub friend_reconcile: ReconcileConfig,
+    /// Reject location updates not signed by a registered device key
+    pub require_signed_location: bool,
+    /// Decimal places stored coordinates are rounded to (6 is ~0.1m)
+    pub coordinate_decimals: u32,
+    /// Longest user name accepted, in characters
+    pub max_user_name_chars: usize,
+    /// Reject user names already taken by someone else (ignoring case)
+    pub unique_user_names: bool,
+    /// Only let these users verify or create a profile (everyone when `None`)
+    pub allowlist: Option<AllowlistSource>,
+    /// Reject location updates for users without a profile with `404`
+    /// instead of creating them
+    pub require_existing_user: bool,
+    /// Store unrecognized country values as-is instead of rejecting them
+    pub country_lenient: bool,
+    /// Requests running longer than this are aborted with `408`, in seconds
+    pub request_timeout_secs: u64,
+    pub log_format: LogFormat,
+    /// Take the client IP from `X-Forwarded-For` / `X-Real-IP` (only safe behind a proxy)
+    pub trust_proxy: bool,
+    /// How long an SOS stays active unless cleared, in seconds
+    pub sos_duration_secs: i64,
+    /// How long a login nonce from `/auth/nonce` stays valid, in seconds
+    pub auth_nonce_ttl_secs: i64,
+    /// Drop location history older than this, in seconds (count cap only when unset)
+    pub history_max_age_secs: Option<i64>,
+    pub history_encoding: HistoryEncoding,
+    /// Most event streams one user may have open at once
+    pub max_streams_per_user: usize,
+    /// Most event streams open at once across all users
+    pub max_streams: usize,
+    /// Events a slow stream may fall behind by before it must resync
+    pub event_buffer: usize,
+    /// Send each stream at most one location update per friend every this
+    /// many milliseconds, always the latest (every update when unset)
+    pub stream_location_interval_ms: Option<u64>,
+    /// How long a guest user lives before being purged, in seconds
+    pub guest_ttl_secs: i64,
+    /// Radius used for users who turned on "nearby only", in kilometers
+    pub nearby_radius_km: f64,
+    /// Index locations on a grid for radius queries (plain scans when off)
+    pub geo_index: bool,
+    pub store_backend: StoreBackend,
+    pub store_wal: WalConfig,
+    /// JSON file of development data loaded into the stores at startup
+    pub seed_file: Option<PathBuf>,
+    pub version_policy: VersionPolicy,
+    /// Start in read-only mode (can be toggled later through the admin API)
+    pub maintenance_mode: bool,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        let config = Self {
+            admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            location_ttl_secs: env_or("LOCATION_TTL_SECS", 3600)?,
+            realtime_freshness_secs: env_or("REALTIME_FRESHNESS_SECS", 30)?,
+            privacy_rounding_meters: env_opt("PRIVACY_ROUNDING_METERS")?,
+            privacy_decay: privacy_decay_from_env()?,
+            movement: MovementConfig {
+                min_meters: env_opt("MIN_MOVEMENT_METERS")?,
+                mode: env_or("MIN_MOVEMENT_MODE", MovementMode::Touch)?,
+            },
+            request_limits: RequestLimits {
+                max_outgoing_pending: env_or("MAX_OUTGOING_PENDING_REQUESTS", 50)?,
+                max_incoming_pending: env_or("MAX_INCOMING_PENDING_REQUESTS", 100)?,
+            },
+            strict_friend_requests: env_or("STRICT_FRIEND_REQUESTS", false)?,
+            friend_request_digest_secs: env_opt("FRIEND_REQUEST_DIGEST_SECS")?,
+            friend_reconcile: ReconcileConfig {
+                interval_secs: env_opt("FRIEND_RECONCILE_SECS")?,
+                mode: env_or("FRIEND_RECONCILE_MODE", ReconcileMode::Repair)?,
+            },
+            require_signed_location: env_or("REQUIRE_SIGNED_LOCATION", false)?,
+            coordinate_decimals: env_or("COORDINATE_DECIMALS", 6)?,
+            max_user_name_chars: env_or("MAX_USER_NAME_CHARS", 32)?,
+            unique_user_names: env_or("UNIQUE_USER_NAMES", false)?,
+            require_existing_user: env_or("REQUIRE_EXISTING_USER", false)?,
+            allowlist: allowlist_from_env()?,
+            country_lenient: env_or("COUNTRY_LENIENT", false)?,
+            request_timeout_secs: env_or("REQUEST_TIMEOUT_SECS", 5)?,
+            log_format: env_or("LOG_FORMAT", LogFormat::Pretty)?,
+            trust_proxy: env_or("TRUST_PROXY", false)?,
+            sos_duration_secs: env_or("SOS_DURATION_SECS", 3600)?,
+            auth_nonce_ttl_secs: env_or("AUTH_NONCE_TTL_SECS", 300)?,
+            nearby_radius_km: env_or("NEARBY_RADIUS_KM", 50.0)?,
+            geo_index: env_or("GEO_INDEX", true)?,
+            history_max_age_secs: env_opt("HISTORY_MAX_AGE_SECS")?,
+            history_encoding: env_or("HISTORY_ENCODING", HistoryEncoding::Plain)?,
+            guest_ttl_secs: env_or("GUEST_TTL_SECS", 3600)?,
+            max_streams_per_user: env_or("MAX_STREAMS_PER_USER", 5)?,
+            max_streams: env_or("MAX_STREAMS", 10_000)?,
+            event_buffer: env_or("EVENT_BUFFER", 256)?,
+            stream_location_interval_ms: env_opt("STREAM_LOCATION_INTERVAL_MS")?,
+            store_backend: store_backend_from_env()?,
+            store_wal: WalConfig {
+                enabled: env_or("STORE_WAL", false)?,
+                compact_bytes: env_or("STORE_WAL_COMPACT_BYTES", 4 * 1024 * 1024)?,
+            },
+            seed_file: env_opt("SEED_FILE")?,
+            version_policy: VersionPolicy {
+                min_version: env_opt("MIN_APP_VERSION")?,
+                deprecated_below: env_opt("DEPRECATED_BELOW_APP_VERSION")?,
+                sunset: env_opt("APP_SUNSET")?,
+            },
+            maintenance_mode: env_or("MAINTENANCE_MODE", false)?,
+        };
+
+        if config.privacy_rounding_meters.is_some_and(|m| m <= 0.0) {
+            return Err(anyhow!("PRIVACY_ROUNDING_METERS must be positive"));
+        }
+
+        if config
+            .privacy_decay
+            .is_some_and(|decay| decay.start_secs < 0 || decay.hide_secs <= decay.start_secs)
+        {
+            return Err(anyhow!(
+                "PRIVACY_DECAY_HIDE_SECS must be greater than PRIVACY_DECAY_START_SECS"
+            ));
+        }
+
+        if config.coordinate_decimals > MAX_COORDINATE_DECIMALS {
+            return Err(anyhow!(
+                "COORDINATE_DECIMALS must be at most {}",
+                MAX_COORDINATE_DECIMALS
+            ));
+        }
+
+        if config.history_encoding == HistoryEncoding::Delta
+            && config.coordinate_decimals > history::DELTA_DECIMALS
+        {
+            return Err(anyhow!(
+                "HISTORY_ENCODING=delta keeps {} decimal places, lower COORDINATE_DECIMALS to match",
+                history::DELTA_DECIMALS
+            ));
+        }
+
+        if config.store_wal.enabled && !matches!(config.store_backend, StoreBackend::File(_)) {
+            return Err(anyhow!("STORE_WAL needs STORE_BACKEND=file"));
+        }
+
+        if config.store_wal.compact_bytes == 0 {
+            return Err(anyhow!("STORE_WAL_COMPACT_BYTES must be positive"));
+        }
+
+        if config.max_user_name_chars == 0 {
+            return Err(anyhow!("MAX_USER_NAME_CHARS must be positive"));
+        }
+
+        if config.realtime_freshness_secs <= 0 {
+            return Err(anyhow!("REALTIME_FRESHNESS_SECS must be positive"));
+        }
+
+        if config.sos_duration_secs <= 0 {
+            return Err(anyhow!("SOS_DURATION_SECS must be positive"));
+        }
+
+        if config.auth_nonce_ttl_secs <= 0 {
+            return Err(anyhow!("AUTH_NONCE_TTL_SECS must be positive"));
+        }
+
+        if config.history_max_age_secs.is_some_and(|secs| secs <= 0) {
+            return Err(anyhow!("HISTORY_MAX_AGE_SECS must be positive"));
+        }
+
+        if config.max_streams_per_user == 0 || config.max_streams == 0 {
+            return Err(anyhow!(
+                "MAX_STREAMS_PER_USER and MAX_STREAMS must be positive"
+            ));
+        }
+
+        if config.event_buffer == 0 {
+            return Err(anyhow!("EVENT_BUFFER must be positive"));
+        }
+
+        if config.stream_location_interval_ms == Some(0) {
+            return Err(anyhow!("STREAM_LOCATION_INTERVAL_MS must be positive"));
+        }
+
+        if config.guest_ttl_secs <= 0 {
+            return Err(anyhow!("GUEST_TTL_SECS must be positive"));
+        }
+
+        if !config.nearby_radius_km.is_finite() || config.nearby_radius_km <= 0.0 {
+            return Err(anyhow!("NEARBY_RADIUS_KM must be positive"));
+        }
+
+        if config.friend_request_digest_secs == Some(0) {
+            return Err(anyhow!("FRIEND_REQUEST_DIGEST_SECS must be positive"));
+        }
+
+        if config.friend_reconcile.interval_secs == Some(0) {
+            return Err(anyhow!("FRIEND_RECONCILE_SECS must be positive"));
+        }
+
+        if config.request_timeout_secs == 0 {
+            return Err(anyhow!("REQUEST_TIMEOUT_SECS must be positive"));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Serialize a secret as `***`
+fn redact<S: Serializer>(_: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("***")
+}
+
+/// Serialize a secret as `***`, keeping `null` so it's clear whether it is set
+fn redact_opt<S: Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(secret) => redact(secret, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Pick the store backend from `STORE_BACKEND`, along with its location
+fn store_backend_from_env() -> Result<StoreBackend> {
+    let backend: String = env_or("STORE_BACKEND", "memory".to_string())?;
+    match backend.to_lowercase().as_str() {
+        "memory" => Ok(StoreBackend::Memory),
+        "file" => Ok(StoreBackend::File(env_or(
+            "STORE_PATH",
+            PathBuf::from("data/store.json"),
+        )?)),
+        "redis" => env_opt("REDIS_URL")?
+            .map(StoreBackend::Redis)
+            .ok_or_else(|| anyhow!("REDIS_URL is required when STORE_BACKEND=redis")),
+        other => Err(anyhow!(
+            "Invalid STORE_BACKEND: expected `memory`, `file` or `redis`, got `{}`",
+            other
+        )),
+    }
+}
+
+/// Read the beta allowlist from `BETA_ALLOWLIST` or `BETA_ALLOWLIST_FILE`
+fn allowlist_from_env() -> Result<Option<AllowlistSource>> {
+    let ids: Option<String> = env_opt("BETA_ALLOWLIST")?;
+    let file: Option<PathBuf> = env_opt("BETA_ALLOWLIST_FILE")?;
+    match (ids, file) {
+        (Some(_), Some(_)) => Err(anyhow!(
+            "Set only one of BETA_ALLOWLIST and BETA_ALLOWLIST_FILE"
+        )),
+        (Some(ids), None) => Ok(Some(AllowlistSource::Ids(
+            ids.split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(str::to_string)
+                .collect(),
+        ))),
+        (None, Some(path)) => Ok(Some(AllowlistSource::File(path))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Read privacy decay thresholds, which must be set together
+fn privacy_decay_from_env() -> Result<Option<PrivacyDecay>> {
+    match (
+        env_opt("PRIVACY_DECAY_START_SECS")?,
+        env_opt("PRIVACY_DECAY_HIDE_SECS")?,
+    ) {
+        (Some(start_secs), Some(hide_secs)) => Ok(Some(PrivacyDecay {
+            start_secs,
+            hide_secs,
+        })),
+        (None, None) => Ok(None),
+        _ => Err(anyhow!(
+            "PRIVACY_DECAY_START_SECS and PRIVACY_DECAY_HIDE_SECS must be set together"
+        )),
+    }
+}
+
+/// Parse an optional environment variable, failing on malformed values
+fn env_opt<T>(key: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow!("Invalid {}: {}", key, e)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Parse an environment variable with a default, failing on malformed values
+fn env_or<T>(key: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    Ok(env_opt(key)?.unwrap_or(default))
+}
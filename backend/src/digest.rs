@@ -0,0 +1,85 @@
+use crate::events::{Event, EventHub};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+/// Friend requests received since the last digest, per receiver
+/// Senders are kept as a set, so someone who asks repeatedly within one
+/// window is only counted once
+pub struct RequestDigest {
+    pending: Mutex<HashMap<String, BTreeSet<String>>>,
+}
+
+impl RequestDigest {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Note a request from `sender_id` to `receiver_id` for the next digest
+    pub fn record(&self, receiver_id: &str, sender_id: &str) {
+        self.pending
+            .lock()
+            .unwrap()
+            .entry(receiver_id.to_string())
+            .or_default()
+            .insert(sender_id.to_string());
+    }
+
+    /// Take everything recorded so far, as (receiver, sorted senders) pairs
+    fn drain(&self) -> Vec<(String, Vec<String>)> {
+        self.pending
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(receiver, senders)| (receiver, senders.into_iter().collect()))
+            .collect()
+    }
+
+    /// Send each receiver one digest event covering everything recorded
+    /// since the last one
+    pub fn publish(&self, events: &EventHub) {
+        for (receiver_id, sender_ids) in self.drain() {
+            events.publish(Event::FriendRequestDigest {
+                count: sender_ids.len(),
+                sender_ids,
+                recipients: vec![receiver_id],
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_in_one_window_make_one_digest() {
+        let events = EventHub::new(16);
+        let mut received = events.subscribe();
+        let digest = RequestDigest::new();
+        for sender_id in ["carol", "alice", "dave"] {
+            digest.record("bob", sender_id);
+        }
+        // Asking again within the window doesn't count twice
+        digest.record("bob", "alice");
+
+        digest.publish(&events);
+        let Ok(Event::FriendRequestDigest {
+            count,
+            sender_ids,
+            recipients,
+        }) = received.try_recv()
+        else {
+            panic!("expected a digest");
+        };
+        assert_eq!(count, 3);
+        assert_eq!(sender_ids, ["alice", "carol", "dave"]);
+        assert_eq!(recipients, ["bob"]);
+        assert!(received.try_recv().is_err());
+
+        // The next window starts empty
+        digest.publish(&events);
+        assert!(received.try_recv().is_err());
+    }
+}
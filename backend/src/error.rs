@@ -0,0 +1,109 @@
+use crate::ApiResponse;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use thiserror::Error;
+
+/// Handler result: `Ok` becomes a `200` success envelope, `Err` an error
+/// envelope with the status matching the error kind
+pub type ApiResult<T> = Result<ApiResponse<T>, StoreError>;
+
+/// Why a store operation failed
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("{0} not found")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Invalid(String),
+    /// A backing service (e.g. Sapphire) couldn't be reached
+    #[error("{0}")]
+    Unavailable(String),
+}
+
+impl StoreError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Invalid(_) => StatusCode::BAD_REQUEST,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl IntoResponse for StoreError {
+    fn into_response(self) -> Response {
+        (
+            self.status(),
+            Json(ApiResponse::<()>::err(self.to_string())),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use serde_json::Value;
+
+    async fn respond(result: ApiResult<&'static str>) -> (StatusCode, Value) {
+        let response = result.into_response();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn ok_is_a_success_envelope() {
+        let (status, body) = respond(Ok(ApiResponse::ok("done"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"], "done");
+    }
+
+    #[tokio::test]
+    async fn each_error_gets_its_status_and_an_error_envelope() {
+        let cases = [
+            (
+                StoreError::NotFound("User"),
+                StatusCode::NOT_FOUND,
+                "User not found",
+            ),
+            (
+                StoreError::Forbidden("Not friends".to_string()),
+                StatusCode::FORBIDDEN,
+                "Not friends",
+            ),
+            (
+                StoreError::Conflict("Stale".to_string()),
+                StatusCode::CONFLICT,
+                "Stale",
+            ),
+            (
+                StoreError::Invalid("Bad level".to_string()),
+                StatusCode::BAD_REQUEST,
+                "Bad level",
+            ),
+            (
+                StoreError::Unavailable("Down".to_string()),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Down",
+            ),
+        ];
+        for (error, expected_status, message) in cases {
+            let (status, body) = respond(Err(error)).await;
+            assert_eq!(status, expected_status);
+            assert_eq!(body["success"], false);
+            assert_eq!(body["error"], message);
+            assert!(body["data"].is_null());
+        }
+    }
+}
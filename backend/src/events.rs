@@ -0,0 +1,203 @@
+use crate::LocationData;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+/// Something that happened which friends may want to react to
+/// Most events name the users they are meant for, and subscribers must only
+/// deliver them to those recipients. Location updates instead are checked
+/// against the friend graph as they are delivered, so friendship changes
+/// apply to open streams right away.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A user raised (or cleared) an SOS
+    Sos {
+        #[serde(rename = "userId")]
+        user_id: String,
+        active: bool,
+        /// Precise location at the time of the SOS, regardless of sharing level
+        location: Option<LocationData>,
+        #[serde(skip)]
+        recipients: Vec<String>,
+    },
+    /// A user changed their sharing level, so what friends can see may have
+    /// changed; carries no location, recipients should refetch
+    VisibilityChanged {
+        #[serde(rename = "userId")]
+        user_id: String,
+        #[serde(skip)]
+        recipients: Vec<String>,
+    },
+    /// Someone sent the recipient a friend request (digest mode off)
+    FriendRequestReceived {
+        #[serde(rename = "requestId")]
+        request_id: String,
+        #[serde(rename = "senderId")]
+        sender_id: String,
+        #[serde(skip)]
+        recipients: Vec<String>,
+    },
+    /// The friend requests the recipient got over the last digest window
+    /// (digest mode on); `count` is the number of distinct senders
+    FriendRequestDigest {
+        count: usize,
+        #[serde(rename = "senderIds")]
+        sender_ids: Vec<String>,
+        #[serde(skip)]
+        recipients: Vec<String>,
+    },
+    /// A user moved; carries no location, since what each viewer may see
+    /// depends on the viewer
+    LocationUpdated {
+        #[serde(rename = "userId")]
+        user_id: String,
+    },
+}
+
+impl Event {
+    /// Who the event is meant for, or `None` if subscribers should check
+    /// the current friend graph instead
+    pub fn recipients(&self) -> Option<&[String]> {
+        match self {
+            Event::Sos { recipients, .. }
+            | Event::VisibilityChanged { recipients, .. }
+            | Event::FriendRequestReceived { recipients, .. }
+            | Event::FriendRequestDigest { recipients, .. } => Some(recipients),
+            Event::LocationUpdated { .. } => None,
+        }
+    }
+}
+
+/// In-process broadcast channel for events
+pub struct EventHub {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventHub {
+    /// `capacity` is how many undelivered events a slow subscriber may lag
+    /// behind before it starts missing them
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// Publish an event to all current subscribers
+    /// Events published while nobody is subscribed are dropped
+    pub fn publish(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    /// Receive every event published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// How many subscribers are listening right now
+    #[cfg(test)]
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+/// Spaces out one stream's location updates to at most one per friend per
+/// `interval`
+///
+/// An update that comes too soon after the last one sent for that friend is
+/// held back, and later ones for the same friend fold into it. Once the
+/// interval has passed it is due, and the stream sends the friend's location
+/// as it is then, so the latest position always goes out.
+pub struct LocationThrottle {
+    interval: Duration,
+    /// Friend ID -> when their last update was sent
+    last_sent: HashMap<String, Instant>,
+    /// Friend ID -> when their held-back update is due
+    held: HashMap<String, Instant>,
+}
+
+impl LocationThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_sent: HashMap::new(),
+            held: HashMap::new(),
+        }
+    }
+
+    /// Whether an update for `friend_id` may be sent at `now`, recording it
+    /// as sent if so and holding it back otherwise
+    pub fn admit(&mut self, friend_id: &str, now: Instant) -> bool {
+        if let Some(last) = self.last_sent.get(friend_id) {
+            let due = *last + self.interval;
+            if now < due {
+                self.held.insert(friend_id.to_string(), due);
+                return false;
+            }
+        }
+        self.held.remove(friend_id);
+        self.last_sent.insert(friend_id.to_string(), now);
+        true
+    }
+
+    /// When the next held-back update is due, if any
+    pub fn next_due(&self) -> Option<Instant> {
+        self.held.values().min().copied()
+    }
+
+    /// Friends whose held-back update is due at `now`, recorded as sent
+    pub fn take_due(&mut self, now: Instant) -> Vec<String> {
+        let due: Vec<String> = self
+            .held
+            .iter()
+            .filter(|(_, due)| **due <= now)
+            .map(|(friend_id, _)| friend_id.clone())
+            .collect();
+        for friend_id in &due {
+            self.held.remove(friend_id);
+            self.last_sent.insert(friend_id.clone(), now);
+        }
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_is_coalesced_to_one_update_per_interval() {
+        let start = Instant::now();
+        let mut throttle = LocationThrottle::new(Duration::from_millis(500));
+        let mut sent = 0;
+
+        // Ten updates 100ms apart, then wait for whatever was held back
+        for tick in 0..10 {
+            let now = start + Duration::from_millis(tick * 100);
+            sent += throttle.take_due(now).len();
+            if throttle.admit("friend", now) {
+                sent += 1;
+            }
+        }
+        assert_eq!(sent, 2);
+        let due = throttle.next_due().expect("the last update is held back");
+        assert_eq!(due, start + Duration::from_millis(1000));
+        sent += throttle.take_due(due).len();
+
+        // Sent at 0ms and 500ms, plus the latest at 1000ms
+        assert_eq!(sent, 3);
+        assert_eq!(throttle.next_due(), None);
+    }
+
+    #[test]
+    fn friends_are_throttled_separately() {
+        let now = Instant::now();
+        let mut throttle = LocationThrottle::new(Duration::from_secs(1));
+        assert!(throttle.admit("alice", now));
+        assert!(throttle.admit("bob", now));
+        assert!(!throttle.admit("alice", now + Duration::from_millis(10)));
+        assert!(throttle.admit("alice", now + Duration::from_secs(1)));
+        assert_eq!(throttle.next_due(), None);
+    }
+}
@@ -0,0 +1,586 @@
+use crate::{ApiResponse, AppState, SharingLevel};
+use axum::{
+    async_trait,
+    extract::{
+        rejection::JsonRejection, ConnectInfo, FromRequest, FromRequestParts, Query, Request,
+    },
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use utoipa::{IntoParams, ToSchema};
+
+/// Largest page size accepted by paginated endpoints
+pub const MAX_PAGE_LIMIT: i64 = 100;
+
+/// Largest radius accepted by geo queries (half the Earth's circumference)
+pub const MAX_RADIUS_KM: f64 = 20_037.5;
+
+/// Query parameters that can check their own values
+pub trait Validate {
+    fn validate(&self) -> Result<(), String>;
+}
+
+/// Query extractor that deserializes and validates parameters,
+/// rejecting bad input with a descriptive `400` JSON error
+pub struct ValidatedQuery<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<ApiResponse<()>>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::err(format!(
+                        "Invalid query parameters: {}",
+                        e.body_text()
+                    ))),
+                )
+            })?;
+
+        params
+            .validate()
+            .map_err(|e| (StatusCode::BAD_REQUEST, Json(ApiResponse::err(e))))?;
+
+        Ok(ValidatedQuery(params))
+    }
+}
+
+/// JSON body extractor whose rejections use the `ApiResponse` envelope,
+/// naming the field or position that failed to parse
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<ApiResponse<()>>);
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::<T>::from_request(req, state).await {
+            Ok(Json(value)) => Ok(ApiJson(value)),
+            Err(rejection) => {
+                let status = match rejection {
+                    JsonRejection::MissingJsonContentType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+                Err((
+                    status,
+                    Json(ApiResponse::err(format!(
+                        "Invalid JSON body: {}",
+                        rejection.body_text()
+                    ))),
+                ))
+            }
+        }
+    }
+}
+
+/// Guard for operator endpoints: requires `Authorization: Bearer <ADMIN_TOKEN>`
+/// Admin endpoints are disabled entirely when no token is configured
+pub struct AdminAuth;
+
+#[async_trait]
+impl FromRequestParts<AppState> for AdminAuth {
+    type Rejection = (StatusCode, Json<ApiResponse<()>>);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(expected) = state.config.admin_token.as_deref() else {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ApiResponse::err("Admin endpoints are disabled".to_string())),
+            ));
+        };
+
+        let provided = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        if provided == Some(expected) {
+            Ok(AdminAuth)
+        } else {
+            Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse::err("Invalid admin token".to_string())),
+            ))
+        }
+    }
+}
+
+/// Optional `If-Match: <version>` precondition on user updates
+/// The version may be quoted like an ETag (`"5"`); a missing header or `*`
+/// means no precondition
+pub struct IfMatch(pub Option<u64>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<ApiResponse<()>>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(header::IF_MATCH) else {
+            return Ok(IfMatch(None));
+        };
+
+        let value = value.to_str().unwrap_or_default().trim();
+        if value == "*" {
+            return Ok(IfMatch(None));
+        }
+        value
+            .trim_matches('"')
+            .parse()
+            .map(|version| IfMatch(Some(version)))
+            .map_err(|_| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::err(format!(
+                        "Invalid If-Match header: expected a user version, got `{}`",
+                        value
+                    ))),
+                )
+            })
+    }
+}
+
+/// Who is making the request, from the `X-User-Id` header, if sent
+/// The header is the client's own claim until authentication middleware
+/// lands, so it only keeps users from reading each other's private data by
+/// accident; anything that must be secure needs `AdminAuth`
+pub struct Caller(pub Option<String>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Caller
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_id = parts
+            .headers
+            .get("x-user-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_string);
+        Ok(Caller(user_id))
+    }
+}
+
+/// The client's IP address
+/// `X-Forwarded-For` / `X-Real-IP` are only honored when `TRUST_PROXY` is set,
+/// since otherwise any client could spoof them; the socket address is used instead
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = (StatusCode, Json<ApiResponse<()>>);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if state.config.trust_proxy {
+            if let Some(ip) = forwarded_ip(&parts.headers) {
+                return Ok(ClientIp(ip));
+            }
+        }
+
+        parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| ClientIp(addr.ip()))
+            .ok_or_else(|| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ApiResponse::err("Client address unavailable".to_string())),
+                )
+            })
+    }
+}
+
+/// Original client IP from proxy headers: the leftmost `X-Forwarded-For`
+/// entry, falling back to `X-Real-IP`
+fn forwarded_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    let forwarded_for = headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .and_then(|ip| ip.trim().parse().ok());
+
+    forwarded_for.or_else(|| {
+        headers
+            .get("x-real-ip")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|ip| ip.trim().parse().ok())
+    })
+}
+
+/// `?limit=&offset=` pagination
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PaginationParams {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+impl Validate for PaginationParams {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(limit) = self.limit {
+            if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+                return Err(format!(
+                    "limit must be between 1 and {}, got {}",
+                    MAX_PAGE_LIMIT, limit
+                ));
+            }
+        }
+        if let Some(offset) = self.offset {
+            if offset < 0 {
+                return Err(format!("offset must not be negative, got {}", offset));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PaginationParams {
+    /// Apply the page window to a list, returning everything when no limit is set
+    pub fn paginate<T>(&self, items: Vec<T>) -> Vec<T> {
+        let (offset, limit) = self.window();
+        items.into_iter().skip(offset).take(limit).collect()
+    }
+
+    /// `(offset, limit)` of the page, for sources that page themselves
+    /// The limit is `usize::MAX` when unset
+    pub fn window(&self) -> (usize, usize) {
+        (
+            self.offset.unwrap_or(0) as usize,
+            self.limit.map(|l| l as usize).unwrap_or(usize::MAX),
+        )
+    }
+}
+
+/// `?radius_km=` geo filtering
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GeoParams {
+    pub radius_km: Option<f64>,
+}
+
+impl Validate for GeoParams {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(radius_km) = self.radius_km {
+            if !radius_km.is_finite() || radius_km <= 0.0 || radius_km > MAX_RADIUS_KM {
+                return Err(format!(
+                    "radius_km must be greater than 0 and at most {}, got {}",
+                    MAX_RADIUS_KM, radius_km
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `?online=&level=` presence filtering for friend lists
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PresenceParams {
+    /// Only friends whose visible location is fresh (`true`) or stale or
+    /// missing (`false`)
+    pub online: Option<bool>,
+    /// Only friends effectively sharing this level with the viewer
+    pub level: Option<SharingLevel>,
+}
+
+impl Validate for PresenceParams {
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Order for time-sorted lists
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+/// `?sort=newest|oldest` ordering
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SortParams {
+    pub sort: Option<SortOrder>,
+}
+
+impl Validate for SortParams {
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// `?older_than_secs=` age cutoff for clearing old items
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AgeParams {
+    pub older_than_secs: Option<i64>,
+}
+
+impl Validate for AgeParams {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(secs) = self.older_than_secs {
+            if secs < 0 {
+                return Err(format!(
+                    "older_than_secs must not be negative, got {}",
+                    secs
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `?before=&limit=` cursor paging for time-ordered lists
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CursorParams {
+    /// Only return items older than this timestamp (the previous page's cursor)
+    pub before: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl Validate for CursorParams {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(limit) = self.limit {
+            if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+                return Err(format!(
+                    "limit must be between 1 and {}, got {}",
+                    MAX_PAGE_LIMIT, limit
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CursorParams {
+    /// Page size, defaulting to the largest allowed
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(MAX_PAGE_LIMIT) as usize
+    }
+}
+
+/// Destination for ETA estimates
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EtaParams {
+    pub to_lat: f64,
+    pub to_lng: f64,
+}
+
+impl Validate for EtaParams {
+    fn validate(&self) -> Result<(), String> {
+        if !(-90.0..=90.0).contains(&self.to_lat) {
+            return Err(format!(
+                "to_lat must be between -90 and 90, got {}",
+                self.to_lat
+            ));
+        }
+        if !(-180.0..=180.0).contains(&self.to_lng) {
+            return Err(format!(
+                "to_lng must be between -180 and 180, got {}",
+                self.to_lng
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `?fields=` sparse fieldsets: a comma-separated list of JSON field names
+/// to keep in each returned object (all fields when unset)
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FieldsParams {
+    /// e.g. `id,userName,location`
+    pub fields: Option<String>,
+}
+
+impl Validate for FieldsParams {
+    fn validate(&self) -> Result<(), String> {
+        if self.fields.as_deref().is_some_and(|f| f.trim().is_empty()) {
+            return Err("fields must name at least one field".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl FieldsParams {
+    /// The requested field names, checked against `allowed` (the JSON field
+    /// names of the returned type), or `None` to keep everything
+    pub fn selection(&self, allowed: &[&str]) -> Result<Option<Vec<String>>, String> {
+        let Some(fields) = &self.fields else {
+            return Ok(None);
+        };
+        let mut selected = Vec::new();
+        for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            if !allowed.contains(&field) {
+                return Err(format!(
+                    "Unknown field: {} (expected one of {})",
+                    field,
+                    allowed.join(", ")
+                ));
+            }
+            selected.push(field.to_string());
+        }
+        Ok(Some(selected))
+    }
+}
+
+/// Serialize `value`, keeping only `fields` when a selection was made
+pub fn select_fields<T: Serialize>(value: &T, fields: Option<&[String]>) -> serde_json::Value {
+    let mut json = serde_json::to_value(value).unwrap_or_default();
+    if let (Some(fields), Some(object)) = (fields, json.as_object_mut()) {
+        object.retain(|key, _| fields.contains(key));
+    }
+    json
+}
+
+/// `?since=` time window
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SinceParams {
+    /// Unix timestamp; only data from this time on is considered (everything when unset)
+    pub since: Option<i64>,
+}
+
+impl Validate for SinceParams {
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    #[derive(Debug, Deserialize)]
+    struct Profile {
+        #[serde(rename = "userName")]
+        user_name: String,
+    }
+
+    fn json_request(body: &'static str) -> Request {
+        Request::builder()
+            .method("POST")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn well_formed_body_is_parsed() {
+        let ApiJson(profile) =
+            ApiJson::<Profile>::from_request(json_request(r#"{"userName":"ada"}"#), &())
+                .await
+                .unwrap();
+        assert_eq!(profile.user_name, "ada");
+    }
+
+    #[tokio::test]
+    async fn malformed_body_gets_a_descriptive_400() {
+        let Err((status, Json(response))) =
+            ApiJson::<Profile>::from_request(json_request(r#"{"userName":}"#), &()).await
+        else {
+            panic!("malformed JSON was accepted");
+        };
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(!response.success);
+        let error = response.error.unwrap();
+        assert!(error.starts_with("Invalid JSON body"), "{}", error);
+        assert!(error.contains("line 1 column 13"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn wrong_field_type_names_the_field() {
+        let Err((status, Json(response))) =
+            ApiJson::<Profile>::from_request(json_request(r#"{"userName":7}"#), &()).await
+        else {
+            panic!("a number was accepted as a name");
+        };
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(response.error.unwrap().contains("userName"));
+    }
+
+    /// The error a query string is rejected with, or `None` if it is accepted
+    async fn query_error<T: DeserializeOwned + Validate>(query: &str) -> Option<String> {
+        let request = Request::get(format!("/?{}", query))
+            .body(Body::empty())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+        match ValidatedQuery::<T>::from_request_parts(&mut parts, &()).await {
+            Ok(_) => None,
+            Err((status, Json(response))) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert!(!response.success);
+                response.error
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn bad_pagination_gets_a_descriptive_400() {
+        assert_eq!(
+            query_error::<PaginationParams>("limit=10&offset=20").await,
+            None
+        );
+        assert_eq!(
+            query_error::<PaginationParams>("limit=0").await.unwrap(),
+            "limit must be between 1 and 100, got 0"
+        );
+        assert_eq!(
+            query_error::<PaginationParams>("limit=101").await.unwrap(),
+            "limit must be between 1 and 100, got 101"
+        );
+        assert_eq!(
+            query_error::<PaginationParams>("offset=-1").await.unwrap(),
+            "offset must not be negative, got -1"
+        );
+        let error = query_error::<PaginationParams>("limit=lots").await.unwrap();
+        assert!(error.starts_with("Invalid query parameters"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn bad_radius_gets_a_descriptive_400() {
+        assert_eq!(query_error::<GeoParams>("radius_km=25").await, None);
+        for radius in ["0", "-5", "20038", "NaN", "inf"] {
+            let error = query_error::<GeoParams>(&format!("radius_km={}", radius))
+                .await
+                .unwrap();
+            assert!(
+                error.starts_with("radius_km must be greater than 0 and at most 20037.5"),
+                "{}",
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use crate::LocationData;
+
+/// Mean Earth radius in kilometers
+pub const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great-circle distance between two points in kilometers (haversine formula)
+pub fn haversine_km(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lng = (lng2 - lng1).to_radians();
+
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lng / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Round a point's coordinates to `decimals` places
+/// Clients sometimes send 15+ places, far beyond what any fix can resolve
+pub fn round_coordinates(location: &mut LocationData, decimals: u32) {
+    let factor = 10f64.powi(decimals as i32);
+    location.latitude = (location.latitude * factor).round() / factor;
+    location.longitude = (location.longitude * factor).round() / factor;
+}
+
+/// Total length of a path through `points`, in order, in kilometers
+/// Fewer than two points cover no distance
+pub fn path_length_km(points: &[LocationData]) -> f64 {
+    points
+        .windows(2)
+        .map(|pair| {
+            haversine_km(
+                pair[0].latitude,
+                pair[0].longitude,
+                pair[1].latitude,
+                pair[1].longitude,
+            )
+        })
+        .sum()
+}
+
+/// Average speed between two timestamped points, in meters per second
+/// `None` unless both have timestamps and `to` is strictly later
+pub fn speed_mps(from: &LocationData, to: &LocationData) -> Option<f64> {
+    let elapsed = to.timestamp? - from.timestamp?;
+    if elapsed <= 0 {
+        return None;
+    }
+    let meters = haversine_km(from.latitude, from.longitude, to.latitude, to.longitude) * 1000.0;
+    Some(meters / elapsed as f64)
+}
+
+/// Seconds needed to cover `distance_km` at `speed_mps`
+/// `None` when not moving (zero, negative or non-finite speed)
+pub fn eta_secs(distance_km: f64, speed_mps: f64) -> Option<i64> {
+    if !speed_mps.is_finite() || speed_mps <= 0.0 {
+        return None;
+    }
+    Some((distance_km * 1000.0 / speed_mps).round() as i64)
+}
+
+/// Initial bearing from one point towards another, in degrees clockwise from north
+pub fn bearing_deg(lat1: f64, lng1: f64, lat2: f64, lng2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lng = (lng2 - lng1).to_radians();
+    let y = d_lng.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lng.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// A point moving in a straight line at constant speed
+pub struct Track {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Meters per second; zero for a stationary point
+    pub speed_mps: f64,
+    /// Degrees clockwise from north; ignored when stationary
+    pub heading: f64,
+}
+
+/// How two tracks move relative to each other
+pub struct Approach {
+    /// Rate at which the distance currently shrinks; negative when moving apart
+    pub closing_speed_mps: f64,
+    /// Smallest distance the tracks reach from now on
+    pub closest_km: f64,
+    /// Seconds until the tracks are within the threshold (0 if they already
+    /// are); `None` if they never get that close
+    pub secs_to_within: Option<i64>,
+}
+
+/// Project two tracks forward and see if and when they come within
+/// `threshold_km` of each other
+/// Uses a flat-earth approximation around the first point, which holds for
+/// the few kilometers a meetup spans
+pub fn approach(a: &Track, b: &Track, threshold_km: f64) -> Approach {
+    let m_per_deg = EARTH_RADIUS_KM * 1000.0 * std::f64::consts::PI / 180.0;
+    let d_lng = (b.longitude - a.longitude + 180.0).rem_euclid(360.0) - 180.0;
+    let (rx, ry) = (
+        d_lng * m_per_deg * a.latitude.to_radians().cos(),
+        (b.latitude - a.latitude) * m_per_deg,
+    );
+    let velocity = |track: &Track| {
+        let heading = track.heading.to_radians();
+        (
+            track.speed_mps * heading.sin(),
+            track.speed_mps * heading.cos(),
+        )
+    };
+    let ((ax, ay), (bx, by)) = (velocity(a), velocity(b));
+    let (wx, wy) = (bx - ax, by - ay);
+
+    let distance = (rx * rx + ry * ry).sqrt();
+    let dot = rx * wx + ry * wy;
+    let w2 = wx * wx + wy * wy;
+    let closing_speed_mps = if distance > 0.0 && dot != 0.0 {
+        -dot / distance
+    } else {
+        0.0
+    };
+
+    // Closest approach is now unless the tracks are still closing in
+    let t_closest = if w2 > 0.0 { (-dot / w2).max(0.0) } else { 0.0 };
+    let closest_km =
+        ((rx + wx * t_closest).powi(2) + (ry + wy * t_closest).powi(2)).sqrt() / 1000.0;
+
+    let threshold = threshold_km * 1000.0;
+    let secs_to_within = if distance <= threshold {
+        Some(0)
+    } else if closest_km * 1000.0 > threshold {
+        None
+    } else {
+        // Earliest root of |r + w·t| = threshold
+        let c = distance * distance - threshold * threshold;
+        let t = (-dot - (dot * dot - w2 * c).max(0.0).sqrt()) / w2;
+        Some(t.round() as i64)
+    };
+
+    Approach {
+        closing_speed_mps,
+        closest_km,
+        secs_to_within,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(latitude: f64, longitude: f64, timestamp: i64) -> LocationData {
+        LocationData {
+            latitude,
+            longitude,
+            city: None,
+            country: None,
+            timestamp: Some(timestamp),
+            speed: None,
+            heading: None,
+        }
+    }
+
+    /// One degree of arc along a great circle
+    const DEGREE_KM: f64 = EARTH_RADIUS_KM * std::f64::consts::PI / 180.0;
+
+    #[test]
+    fn path_length_adds_up_each_leg() {
+        let path = [
+            point(0.0, 0.0, 0),
+            point(0.0, 1.0, 60),
+            point(1.0, 1.0, 120),
+        ];
+        assert!((path_length_km(&path) - 2.0 * DEGREE_KM).abs() < 1e-9);
+    }
+
+    #[test]
+    fn path_of_fewer_than_two_points_is_empty() {
+        assert_eq!(path_length_km(&[]), 0.0);
+        assert_eq!(path_length_km(&[point(41.0, 29.0, 0)]), 0.0);
+    }
+
+    #[test]
+    fn speed_is_distance_over_elapsed_time() {
+        let (from, to) = (point(0.0, 0.0, 1_000), point(0.0, 1.0, 2_000));
+        let speed = speed_mps(&from, &to).unwrap();
+        assert!((speed - DEGREE_KM).abs() < 1e-9, "{}", speed);
+
+        // No speed without time passing forward, or without timestamps
+        assert_eq!(speed_mps(&to, &from), None);
+        assert_eq!(speed_mps(&from, &point(0.0, 1.0, 1_000)), None);
+        let untimed = LocationData {
+            timestamp: None,
+            ..to.clone()
+        };
+        assert_eq!(speed_mps(&from, &untimed), None);
+    }
+
+    #[test]
+    fn eta_is_distance_over_speed() {
+        assert_eq!(eta_secs(10.0, 5.0), Some(2_000));
+        assert_eq!(eta_secs(0.0, 5.0), Some(0));
+        assert_eq!(eta_secs(1.0, 3.0), Some(333));
+        for stopped in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(eta_secs(10.0, stopped), None, "{}", stopped);
+        }
+    }
+
+    #[test]
+    fn coordinates_round_to_the_configured_places() {
+        let mut location = point(41.008238123456, -28.978359876543, 0);
+        round_coordinates(&mut location, 6);
+        assert_eq!(
+            (location.latitude, location.longitude),
+            (41.008238, -28.97836)
+        );
+        round_coordinates(&mut location, 2);
+        assert_eq!((location.latitude, location.longitude), (41.01, -28.98));
+    }
+}
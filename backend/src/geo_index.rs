@@ -0,0 +1,194 @@
+use crate::geo::EARTH_RADIUS_KM;
+use std::collections::{HashMap, HashSet};
+
+/// Cell size in degrees (~11km of latitude)
+const CELL_DEG: f64 = 0.1;
+
+/// Number of longitude cells around the globe
+const LNG_CELLS: i32 = (360.0 / CELL_DEG) as i32;
+
+type Cell = (i32, i32);
+
+/// Grid of users' last known positions, bucketed by cell
+/// Used to prune candidates for radius queries before the exact haversine
+/// check; it never decides on its own whether a point is in range.
+#[derive(Default)]
+pub struct GridIndex {
+    cells: HashMap<Cell, HashSet<String>>,
+    positions: HashMap<String, Cell>,
+}
+
+impl GridIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `user_id` at a position, moving them out of their old cell
+    pub fn insert(&mut self, user_id: &str, latitude: f64, longitude: f64) {
+        let cell = cell_of(latitude, longitude);
+        if self.positions.get(user_id) == Some(&cell) {
+            return;
+        }
+        self.remove(user_id);
+        self.cells
+            .entry(cell)
+            .or_default()
+            .insert(user_id.to_string());
+        self.positions.insert(user_id.to_string(), cell);
+    }
+
+    pub fn remove(&mut self, user_id: &str) {
+        let Some(cell) = self.positions.remove(user_id) else {
+            return;
+        };
+        if let Some(ids) = self.cells.get_mut(&cell) {
+            ids.remove(user_id);
+            if ids.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    /// Users that may be within `radius_km` of a point: a superset of the
+    /// exact answer, to be checked with `geo::haversine_km`
+    pub fn candidates_within(&self, latitude: f64, longitude: f64, radius_km: f64) -> Vec<String> {
+        // Bounding box of the spherical cap around the point
+        let angle = radius_km / EARTH_RADIUS_KM;
+        let lat_min = latitude - angle.to_degrees();
+        let lat_max = latitude + angle.to_degrees();
+        let cos_lat = latitude.to_radians().cos();
+        let lng_span = if lat_min <= -90.0 || lat_max >= 90.0 || angle.sin() >= cos_lat {
+            None
+        } else {
+            Some((angle.sin() / cos_lat).asin().to_degrees())
+        };
+
+        let lat_cells = lat_cell(lat_min.max(-90.0))..=lat_cell(lat_max.min(90.0));
+        let lng_cells: Vec<i32> = match lng_span {
+            None => (0..LNG_CELLS).collect(),
+            Some(span) => {
+                let first = ((longitude - span + 180.0) / CELL_DEG).floor() as i32;
+                let last = ((longitude + span + 180.0) / CELL_DEG).floor() as i32;
+                let count = (last - first + 1).min(LNG_CELLS);
+                (first..first + count)
+                    .map(|i| i.rem_euclid(LNG_CELLS))
+                    .collect()
+            }
+        };
+
+        // Walking more cells than there are entries is slower than a scan
+        let cell_count = lat_cells.clone().count() * lng_cells.len();
+        if cell_count > self.cells.len() {
+            let lat_range = lat_cells;
+            let lng_set: HashSet<i32> = lng_cells.into_iter().collect();
+            return self
+                .cells
+                .iter()
+                .filter(|((lat, lng), _)| lat_range.contains(lat) && lng_set.contains(lng))
+                .flat_map(|(_, ids)| ids.iter().cloned())
+                .collect();
+        }
+
+        let mut candidates = Vec::new();
+        for lat in lat_cells {
+            for lng in &lng_cells {
+                if let Some(ids) = self.cells.get(&(lat, *lng)) {
+                    candidates.extend(ids.iter().cloned());
+                }
+            }
+        }
+        candidates
+    }
+}
+
+fn lat_cell(latitude: f64) -> i32 {
+    (latitude / CELL_DEG).floor() as i32
+}
+
+fn cell_of(latitude: f64, longitude: f64) -> Cell {
+    let lng = ((longitude + 180.0) / CELL_DEG).floor() as i32;
+    (lat_cell(latitude), lng.rem_euclid(LNG_CELLS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geo::haversine_km;
+
+    /// Deterministic pseudo-random numbers in `[0, 1)`
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self) -> f64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn between(&mut self, low: f64, high: f64) -> f64 {
+            low + self.next() * (high - low)
+        }
+    }
+
+    #[test]
+    fn radius_queries_match_a_linear_scan() {
+        let mut rng = Lcg(42);
+        let mut index = GridIndex::new();
+        let mut points = Vec::new();
+        for i in 0..2000 {
+            // Half spread over the globe, half clustered near each query
+            let (latitude, longitude) = if i % 2 == 0 {
+                (rng.between(-90.0, 90.0), rng.between(-180.0, 180.0))
+            } else {
+                let centers = [(41.0, 29.0), (89.5, 0.0), (0.0, 179.9), (-33.9, 151.2)];
+                let (lat, lng) = centers[i % centers.len()];
+                let lng = lng + rng.between(-2.0, 2.0);
+                let lng = (lng + 180.0).rem_euclid(360.0) - 180.0;
+                ((lat + rng.between(-2.0, 2.0)).clamp(-90.0, 90.0), lng)
+            };
+            let id = format!("user-{}", i);
+            index.insert(&id, latitude, longitude);
+            points.push((id, latitude, longitude));
+        }
+        // Moving someone must take them out of their old cell
+        index.insert("user-0", 41.01, 29.01);
+        points[0] = ("user-0".to_string(), 41.01, 29.01);
+
+        let queries = [
+            (41.0, 29.0, 5.0),
+            (41.0, 29.0, 150.0),
+            (89.5, 0.0, 100.0),
+            (0.0, 179.95, 50.0),
+            (0.0, -179.95, 300.0),
+            (-33.9, 151.2, 1.0),
+            (10.0, 10.0, 5000.0),
+        ];
+        let mut matched = 0;
+        for (latitude, longitude, radius_km) in queries {
+            let within =
+                |lat: f64, lng: f64| haversine_km(latitude, longitude, lat, lng) <= radius_km;
+            let indexed: HashSet<String> = index
+                .candidates_within(latitude, longitude, radius_km)
+                .into_iter()
+                .filter(|id| {
+                    let (_, lat, lng) = points.iter().find(|(p, _, _)| p == id).unwrap();
+                    within(*lat, *lng)
+                })
+                .collect();
+            let scanned: HashSet<String> = points
+                .iter()
+                .filter(|(_, lat, lng)| within(*lat, *lng))
+                .map(|(id, _, _)| id.clone())
+                .collect();
+            matched += scanned.len();
+            assert_eq!(
+                indexed, scanned,
+                "query ({}, {}, {}km)",
+                latitude, longitude, radius_km
+            );
+        }
+        assert!(matched > 100, "only {} matches", matched);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::privacy::round_to_city;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Scale applied to City-rounded coordinates to get an integer cache key
+/// (City rounding keeps 2 decimal places)
+const CELL_KEY_SCALE: f64 = 100.0;
+
+/// Cells remembered before the cache is dropped and starts over
+const MAX_CACHED_CELLS: usize = 10_000;
+
+/// Reverse geocoder for rough, human-readable addresses
+/// Never resolves anything finer than a neighborhood, so labels can be shown
+/// to friends who only see City-level coordinates
+pub struct ReverseGeocoder {
+    // City cell -> label (or `None` if the lookup found nothing)
+    cache: RwLock<HashMap<(i64, i64), Option<String>>>,
+}
+
+impl ReverseGeocoder {
+    pub fn new() -> Self {
+        Self {
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Approximate address for a point
+    /// Lookups are keyed by the City cell the point rounds to, so nearby
+    /// points reuse one cached result and exact coordinates never reach the
+    /// geocoder. `fallback` is the label the client reported, if any.
+    pub async fn approximate_address(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        fallback: Option<String>,
+    ) -> Option<String> {
+        let (lat, lng) = round_to_city(latitude, longitude, None);
+        let key = cell_key(lat, lng);
+
+        if let Some(label) = self.cache.read().unwrap().get(&key) {
+            return label.clone();
+        }
+
+        let label = self.query_address(lat, lng, fallback).await;
+        let mut cache = self.cache.write().unwrap();
+        if cache.len() >= MAX_CACHED_CELLS {
+            cache.clear();
+        }
+        cache.insert(key, label.clone());
+        label
+    }
+
+    /// Resolve a City-rounded point to a label, bypassing the cache
+    async fn query_address(&self, lat: f64, lng: f64, fallback: Option<String>) -> Option<String> {
+        // TODO: Query a reverse geocoding provider for the neighborhood
+        tracing::info!("🔍 Would reverse geocode ({}, {})", lat, lng);
+
+        // For development, use the labels reported by the client
+        fallback.map(|label| format!("Near {}", label))
+    }
+}
+
+/// Integer cache key for a City-rounded point
+fn cell_key(lat: f64, lng: f64) -> (i64, i64) {
+    (
+        (lat * CELL_KEY_SCALE).round() as i64,
+        (lng * CELL_KEY_SCALE).round() as i64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn nearby_points_share_a_cached_lookup() {
+        let geocoder = ReverseGeocoder::new();
+        let first = geocoder
+            .approximate_address(41.0012, 29.0011, Some("Kadıköy".to_string()))
+            .await;
+        assert_eq!(first.as_deref(), Some("Near Kadıköy"));
+
+        // Same City cell: answered from the cache, not from this fallback
+        let second = geocoder
+            .approximate_address(41.0034, 29.0042, Some("Moda".to_string()))
+            .await;
+        assert_eq!(second, first);
+        assert_eq!(geocoder.cache.read().unwrap().len(), 1);
+
+        let elsewhere = geocoder
+            .approximate_address(41.0512, 29.0011, Some("Beşiktaş".to_string()))
+            .await;
+        assert_eq!(elsewhere.as_deref(), Some("Near Beşiktaş"));
+        assert_eq!(geocoder.cache.read().unwrap().len(), 2);
+    }
+}
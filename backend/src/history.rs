@@ -0,0 +1,357 @@
+use crate::config::HistoryEncoding;
+use crate::LocationData;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+/// Decimal places delta-encoded history keeps coordinates to (~1cm)
+pub const DELTA_DECIMALS: u32 = 7;
+
+/// Coordinate steps per degree in delta-encoded history
+const STEPS_PER_DEGREE: f64 = 1e7;
+
+/// One user's location history, oldest first
+/// Either full points, or the first point in full and every later one as
+/// deltas from the point before it. Deltas keep coordinates to 7 decimal
+/// places and everything else exactly, and cost decoding on every read.
+pub enum HistoryBuffer {
+    Plain(VecDeque<LocationData>),
+    Delta(Box<DeltaBuffer>),
+}
+
+impl HistoryBuffer {
+    pub fn new(encoding: HistoryEncoding) -> Self {
+        Self::from_points(VecDeque::new(), encoding)
+    }
+
+    pub fn from_points(points: VecDeque<LocationData>, encoding: HistoryEncoding) -> Self {
+        match encoding {
+            HistoryEncoding::Plain => Self::Plain(points),
+            HistoryEncoding::Delta => {
+                let mut buffer = Box::<DeltaBuffer>::default();
+                for point in points {
+                    buffer.push_back(point);
+                }
+                Self::Delta(buffer)
+            }
+        }
+    }
+
+    /// All points, oldest first; decoded into a copy when delta-encoded
+    pub fn points(&self) -> Cow<'_, VecDeque<LocationData>> {
+        match self {
+            Self::Plain(points) => Cow::Borrowed(points),
+            Self::Delta(buffer) => Cow::Owned(buffer.decode()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Plain(points) => points.len(),
+            Self::Delta(buffer) => buffer.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn front(&self) -> Option<&LocationData> {
+        match self {
+            Self::Plain(points) => points.front(),
+            Self::Delta(buffer) => buffer.first.as_ref(),
+        }
+    }
+
+    pub fn back(&self) -> Option<&LocationData> {
+        match self {
+            Self::Plain(points) => points.back(),
+            Self::Delta(buffer) => buffer.last.as_ref(),
+        }
+    }
+
+    pub fn push_back(&mut self, point: LocationData) {
+        match self {
+            Self::Plain(points) => points.push_back(point),
+            Self::Delta(buffer) => buffer.push_back(point),
+        }
+    }
+
+    pub fn pop_front(&mut self) {
+        match self {
+            Self::Plain(points) => {
+                points.pop_front();
+            }
+            Self::Delta(buffer) => buffer.pop_front(),
+        }
+    }
+
+    pub fn pop_back(&mut self) {
+        match self {
+            Self::Plain(points) => {
+                points.pop_back();
+            }
+            Self::Delta(buffer) => buffer.pop_back(),
+        }
+    }
+
+    pub fn retain(&mut self, keep: impl FnMut(&LocationData) -> bool) {
+        match self {
+            Self::Plain(points) => points.retain(keep),
+            Self::Delta(buffer) => {
+                let mut points = buffer.decode();
+                points.retain(keep);
+                *self = Self::from_points(points, HistoryEncoding::Delta);
+            }
+        }
+    }
+}
+
+/// History as a first point plus a delta per later point
+#[derive(Default)]
+pub struct DeltaBuffer {
+    first: Option<LocationData>,
+    deltas: VecDeque<Delta>,
+    /// Decoded last point, so appends don't decode the whole buffer
+    last: Option<LocationData>,
+}
+
+/// A point relative to the one before it
+struct Delta {
+    /// Coordinate changes in `STEPS_PER_DEGREE` steps, wrapping
+    lat: i32,
+    lng: i32,
+    /// Seconds since the previous point
+    secs: i32,
+    speed: Option<f64>,
+    heading: Option<f64>,
+    /// Anything plain deltas can't express; `None` for the common case
+    extra: Option<Box<Extra>>,
+}
+
+enum Extra {
+    /// City or country changed from the previous point
+    Place {
+        city: Option<String>,
+        country: Option<String>,
+    },
+    /// The point can't be stored relative to the previous one (no
+    /// timestamp, or too far apart in time), so it is kept in full
+    Full(LocationData),
+}
+
+impl DeltaBuffer {
+    fn len(&self) -> usize {
+        self.first.as_ref().map_or(0, |_| 1 + self.deltas.len())
+    }
+
+    fn push_back(&mut self, point: LocationData) {
+        let Some(last) = &self.last else {
+            self.first = Some(point.clone());
+            self.last = Some(point);
+            return;
+        };
+        let delta = encode(last, &point);
+        self.last = Some(apply(last, &delta));
+        self.deltas.push_back(delta);
+    }
+
+    fn pop_front(&mut self) {
+        let Some(first) = &self.first else {
+            return;
+        };
+        match self.deltas.pop_front() {
+            Some(delta) => self.first = Some(apply(first, &delta)),
+            None => {
+                self.first = None;
+                self.last = None;
+            }
+        }
+    }
+
+    fn pop_back(&mut self) {
+        if self.deltas.pop_back().is_none() {
+            self.first = None;
+            self.last = None;
+            return;
+        }
+        self.last = self.decode().pop_back();
+    }
+
+    fn decode(&self) -> VecDeque<LocationData> {
+        let mut points = VecDeque::with_capacity(self.len());
+        let Some(first) = &self.first else {
+            return points;
+        };
+        points.push_back(first.clone());
+        let mut previous = first.clone();
+        for delta in &self.deltas {
+            previous = apply(&previous, delta);
+            points.push_back(previous.clone());
+        }
+        points
+    }
+}
+
+/// Coordinate in `STEPS_PER_DEGREE` steps; `None` if it doesn't fit an `i32`
+fn to_steps(degrees: f64) -> Option<i32> {
+    let steps = (degrees * STEPS_PER_DEGREE).round();
+    (steps.abs() <= i32::MAX as f64).then_some(steps as i32)
+}
+
+fn encode(previous: &LocationData, point: &LocationData) -> Delta {
+    let steps = to_steps(previous.latitude)
+        .zip(to_steps(previous.longitude))
+        .zip(to_steps(point.latitude).zip(to_steps(point.longitude)));
+    let secs = previous
+        .timestamp
+        .zip(point.timestamp)
+        .and_then(|(from, to)| i32::try_from(to.checked_sub(from)?).ok());
+    let (Some(((prev_lat, prev_lng), (lat, lng))), Some(secs)) = (steps, secs) else {
+        return Delta {
+            lat: 0,
+            lng: 0,
+            secs: 0,
+            speed: None,
+            heading: None,
+            extra: Some(Box::new(Extra::Full(point.clone()))),
+        };
+    };
+
+    let place_changed = point.city != previous.city || point.country != previous.country;
+    Delta {
+        lat: lat.wrapping_sub(prev_lat),
+        lng: lng.wrapping_sub(prev_lng),
+        secs,
+        speed: point.speed,
+        heading: point.heading,
+        extra: place_changed.then(|| {
+            Box::new(Extra::Place {
+                city: point.city.clone(),
+                country: point.country.clone(),
+            })
+        }),
+    }
+}
+
+fn apply(previous: &LocationData, delta: &Delta) -> LocationData {
+    let (city, country) = match delta.extra.as_deref() {
+        Some(Extra::Full(point)) => return point.clone(),
+        Some(Extra::Place { city, country }) => (city.clone(), country.clone()),
+        None => (previous.city.clone(), previous.country.clone()),
+    };
+    // `encode` only writes deltas when both of these fit
+    let lat = to_steps(previous.latitude)
+        .unwrap_or(0)
+        .wrapping_add(delta.lat);
+    let lng = to_steps(previous.longitude)
+        .unwrap_or(0)
+        .wrapping_add(delta.lng);
+    LocationData {
+        latitude: lat as f64 / STEPS_PER_DEGREE,
+        longitude: lng as f64 / STEPS_PER_DEGREE,
+        city,
+        country,
+        timestamp: previous.timestamp.map(|t| t + delta.secs as i64),
+        speed: delta.speed,
+        heading: delta.heading,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, lng: f64, timestamp: Option<i64>, city: &str) -> LocationData {
+        LocationData {
+            latitude: lat,
+            longitude: lng,
+            city: Some(city.to_string()),
+            country: Some("TR".to_string()),
+            timestamp,
+            speed: Some(1.5),
+            heading: None,
+        }
+    }
+
+    fn as_json(points: &VecDeque<LocationData>) -> serde_json::Value {
+        serde_json::to_value(points).unwrap()
+    }
+
+    #[test]
+    fn delta_history_reconstructs_the_original_points() {
+        let original: VecDeque<LocationData> = [
+            point(41.0082376, 28.9783589, Some(1_700_000_000), "Istanbul"),
+            point(41.0091234, 28.9801112, Some(1_700_000_030), "Istanbul"),
+            // Wraps the longitude steps
+            point(-33.8688197, -151.2092955, Some(1_700_003_600), "Sydney"),
+            // No timestamp, so it's kept in full
+            point(-33.8701, -151.2101, None, "Sydney"),
+            point(39.9333635, 32.8597419, Some(1_700_090_000), "Ankara"),
+        ]
+        .into();
+
+        let history = HistoryBuffer::from_points(original.clone(), HistoryEncoding::Delta);
+        assert!(matches!(history, HistoryBuffer::Delta(_)));
+        assert_eq!(history.len(), original.len());
+        // Coordinates with at most `DELTA_DECIMALS` places come back exactly
+        assert_eq!(as_json(&history.points()), as_json(&original));
+        assert_eq!(
+            serde_json::to_value(history.back()).unwrap(),
+            serde_json::to_value(original.back()).unwrap()
+        );
+    }
+
+    #[test]
+    fn delta_history_keeps_finer_coordinates_within_precision() {
+        let original: VecDeque<LocationData> = (0..50)
+            .map(|i| {
+                let step = i as f64 * 0.000123456789;
+                point(
+                    41.0 + step,
+                    29.0 - step,
+                    Some(1_700_000_000 + i * 5),
+                    "Istanbul",
+                )
+            })
+            .collect();
+
+        let history = HistoryBuffer::from_points(original.clone(), HistoryEncoding::Delta);
+        let tolerance = 0.5 / 10f64.powi(DELTA_DECIMALS as i32) + f64::EPSILON * 100.0;
+        for (decoded, point) in history.points().iter().zip(&original) {
+            assert!((decoded.latitude - point.latitude).abs() <= tolerance);
+            assert!((decoded.longitude - point.longitude).abs() <= tolerance);
+            assert_eq!(decoded.timestamp, point.timestamp);
+            assert_eq!(decoded.speed, point.speed);
+        }
+    }
+
+    #[test]
+    fn delta_history_edits_match_plain_history() {
+        let original: VecDeque<LocationData> = (0..6)
+            .map(|i| {
+                point(
+                    41.0 + i as f64 * 0.01,
+                    29.0,
+                    Some(1_700_000_000 + i * 60),
+                    "Istanbul",
+                )
+            })
+            .collect();
+
+        let mut plain = HistoryBuffer::from_points(original.clone(), HistoryEncoding::Plain);
+        let mut delta = HistoryBuffer::from_points(original, HistoryEncoding::Delta);
+        for history in [&mut plain, &mut delta] {
+            history.pop_front();
+            history.pop_back();
+            history.retain(|p| p.timestamp != Some(1_700_000_120));
+            history.push_back(point(40.0, 30.0, Some(1_700_001_000), "Bursa"));
+        }
+
+        assert_eq!(delta.len(), 4);
+        assert_eq!(as_json(&delta.points()), as_json(&plain.points()));
+        assert_eq!(
+            serde_json::to_value(delta.front()).unwrap(),
+            serde_json::to_value(plain.front()).unwrap()
+        );
+    }
+}
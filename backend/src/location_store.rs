@@ -1,11 +1,64 @@
-use crate::{LocationData, SharingLevel, User};
+use crate::allowlist::Allowlist;
+use crate::config::{HistoryEncoding, MovementConfig, MovementMode, RequestLimits};
+use crate::error::StoreError;
+use crate::geo;
+use crate::geo_index::GridIndex;
+use crate::history::HistoryBuffer;
+use crate::privacy::SharingOverrides;
+use crate::schedule::{self, ScheduleRule, ScheduledLevel};
+use crate::{LocationData, SharingLevel, User, UserStatus};
+use ed25519_dalek::VerifyingKey;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::RwLock;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
 use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Current Unix time in seconds.
+///
+/// Falls back to 0 when the system clock reports a time before the Unix
+/// epoch (misconfigured TEE clocks at boot) instead of panicking.
+pub fn now_secs() -> i64 {
+    unix_secs(SystemTime::now())
+}
+
+/// Seconds from the Unix epoch to `time`, or 0 if it's before the epoch
+fn unix_secs(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(e) => {
+            tracing::warn!("⚠️ System clock is before UNIX_EPOCH, using 0: {}", e);
+            0
+        }
+    }
+}
+
+/// Record a change to a user: bump their version, and their `last_updated`
+/// to `timestamp` without ever moving it backwards
+fn touch(user: &mut User, timestamp: i64) {
+    user.version += 1;
+    user.last_updated = Some(
+        user.last_updated
+            .map_or(timestamp, |last| last.max(timestamp)),
+    );
+}
+
+/// Check an `If-Match` style precondition against a user's current version
+/// Users that don't exist yet are at version 0
+fn check_version(user: Option<&User>, expected: Option<u64>) -> Result<(), StoreError> {
+    let current = user.map_or(0, |user| user.version);
+    match expected {
+        Some(expected) if expected != current => Err(StoreError::Conflict(format!(
+            "User was modified: version is {}, not {}",
+            current, expected
+        ))),
+        _ => Ok(()),
+    }
+}
 
 /// Friend request status
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum FriendRequestStatus {
     Pending,
@@ -13,8 +66,18 @@ pub enum FriendRequestStatus {
     Declined,
 }
 
+/// Which way a pending friend request points, from one user's perspective
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestDirection {
+    /// The user sent it
+    Outgoing,
+    /// The user received it
+    Incoming,
+}
+
 /// Friend request
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct FriendRequest {
     pub id: String,
     #[serde(rename = "senderId")]
@@ -23,6 +86,91 @@ pub struct FriendRequest {
     pub receiver_id: String,
     pub status: FriendRequestStatus,
     pub timestamp: i64,
+    /// Optional note from the sender, already sanitized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Most location points kept per user; older ones are dropped first
+const MAX_HISTORY_POINTS: usize = 1000;
+
+/// Most recent city transitions kept per user
+const MAX_CITY_VISITS: usize = 50;
+
+/// A stay in one city, between city changes
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CityVisit {
+    pub city: String,
+    pub country: Option<String>,
+    #[serde(rename = "firstSeen")]
+    pub first_seen: i64,
+    #[serde(rename = "lastSeen")]
+    pub last_seen: i64,
+}
+
+/// A sharing level granted to one friend until an expiry time
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TemporaryShare {
+    pub level: SharingLevel,
+    /// Unix timestamp after which the normal level applies again
+    pub until: i64,
+}
+
+/// A named circle of friends ("Family", "Work") that share one level
+/// A friend is in at most one of a user's groups, so their level is never
+/// ambiguous
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SharingGroup {
+    pub id: String,
+    pub name: String,
+    pub level: SharingLevel,
+    /// Friend IDs, sorted
+    pub members: Vec<String>,
+}
+
+/// Changes to a user's own fields, applied together by `update_user`
+/// Fields left as `None` are not touched
+#[derive(Debug, Clone, Default)]
+pub struct UserPatch {
+    /// New display name; `Some(None)` clears it
+    pub user_name: Option<Option<String>>,
+    pub sharing_level: Option<SharingLevel>,
+    /// New status; `Some(None)` clears it
+    pub status: Option<Option<UserStatus>>,
+    /// New time zone; `Some(None)` clears it
+    pub timezone: Option<Option<String>>,
+}
+
+/// Serializable copy of everything in a `LocationStore`, for persistence
+/// The pending-request index is rebuilt from the requests on restore
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StoreSnapshot {
+    users: Vec<User>,
+    friend_requests: Vec<FriendRequest>,
+    discovery_hashes: HashMap<String, String>,
+    paused_users: HashSet<String>,
+    #[serde(default)]
+    nearby_only_users: HashSet<String>,
+    #[serde(default)]
+    discoverable_users: HashSet<String>,
+    #[serde(default)]
+    guests: HashMap<String, i64>,
+    observers: HashMap<String, HashSet<String>>,
+    #[serde(default)]
+    favorites: HashMap<String, HashSet<String>>,
+    #[serde(default)]
+    mutes: HashMap<String, HashMap<String, Option<i64>>>,
+    /// Hex-encoded device keys
+    device_keys: HashMap<String, String>,
+    /// (owner ID, viewer ID, share)
+    temporary_shares: Vec<(String, String, TemporaryShare)>,
+    #[serde(default)]
+    groups: HashMap<String, Vec<SharingGroup>>,
+    #[serde(default)]
+    schedules: HashMap<String, Vec<ScheduleRule>>,
+    sos: HashMap<String, i64>,
+    cities: HashMap<String, VecDeque<CityVisit>>,
+    history: HashMap<String, VecDeque<LocationData>>,
 }
 
 /// In-memory location store (running in TEE)
@@ -30,6 +178,52 @@ pub struct FriendRequest {
 pub struct LocationStore {
     users: RwLock<HashMap<String, User>>,
     friend_requests: RwLock<HashMap<String, FriendRequest>>,
+    // (sender ID, receiver ID) -> ID of the pending request between them
+    pending_requests: RwLock<HashMap<(String, String), String>>,
+    // Both user IDs, in sorted order -> ID of the newest accepted request between them
+    accepted_requests: RwLock<HashMap<(String, String), String>>,
+    // Salted contact hash -> user ID, used for private friend discovery
+    discovery_hashes: RwLock<HashMap<String, String>>,
+    // Users who temporarily paused all sharing
+    paused_users: RwLock<HashSet<String>>,
+    // Users whose friend map defaults to nearby friends only
+    nearby_only_users: RwLock<HashSet<String>>,
+    // Users who opted in to being shown on nearby strangers' radar
+    discoverable_users: RwLock<HashSet<String>>,
+    // Guest user ID -> when the guest expires and is purged
+    guests: RwLock<HashMap<String, i64>>,
+    // User ID -> observers allowed to view that user's location (one-directional)
+    observers: RwLock<HashMap<String, HashSet<String>>>,
+    // User ID -> friends they pinned as favorites
+    favorites: RwLock<HashMap<String, HashSet<String>>>,
+    // User ID -> friends they muted -> when the mute ends (never when `None`)
+    mutes: RwLock<HashMap<String, HashMap<String, Option<i64>>>>,
+    // User ID -> device key that signs location updates
+    device_keys: RwLock<HashMap<String, VerifyingKey>>,
+    // (owner ID, viewer ID) -> time-boxed level the owner shares with that viewer
+    temporary_shares: RwLock<HashMap<(String, String), TemporaryShare>>,
+    // Owner ID -> the owner's sharing groups
+    groups: RwLock<HashMap<String, Vec<SharingGroup>>>,
+    // User ID -> their scheduled sharing rules, in the order they set them
+    schedules: RwLock<HashMap<String, Vec<ScheduleRule>>>,
+    // User ID -> when their active SOS expires
+    sos: RwLock<HashMap<String, i64>>,
+    // User ID -> city transitions, oldest first
+    cities: RwLock<HashMap<String, VecDeque<CityVisit>>>,
+    // User ID -> recorded locations, oldest first, at most one per second
+    history: RwLock<HashMap<String, HistoryBuffer>>,
+    // History points older than this many seconds are dropped (no age limit when `None`)
+    history_max_age_secs: Option<i64>,
+    // How new history buffers are encoded
+    history_encoding: HistoryEncoding,
+    // Grid of current locations for radius queries (linear scans when `None`)
+    geo_index: Option<RwLock<GridIndex>>,
+    // Lowercased user name -> its owner, only when names must be unique
+    user_names: Option<RwLock<HashMap<String, String>>>,
+    // Reject location updates for users that don't exist yet instead of creating them
+    require_existing_user: bool,
+    // Closed beta: only these users may be created (anyone when `None`)
+    allowlist: Option<Arc<Allowlist>>,
 }
 
 impl LocationStore {
@@ -37,6 +231,279 @@ impl LocationStore {
         Self {
             users: RwLock::new(HashMap::new()),
             friend_requests: RwLock::new(HashMap::new()),
+            pending_requests: RwLock::new(HashMap::new()),
+            accepted_requests: RwLock::new(HashMap::new()),
+            discovery_hashes: RwLock::new(HashMap::new()),
+            paused_users: RwLock::new(HashSet::new()),
+            nearby_only_users: RwLock::new(HashSet::new()),
+            discoverable_users: RwLock::new(HashSet::new()),
+            guests: RwLock::new(HashMap::new()),
+            observers: RwLock::new(HashMap::new()),
+            favorites: RwLock::new(HashMap::new()),
+            mutes: RwLock::new(HashMap::new()),
+            device_keys: RwLock::new(HashMap::new()),
+            temporary_shares: RwLock::new(HashMap::new()),
+            groups: RwLock::new(HashMap::new()),
+            schedules: RwLock::new(HashMap::new()),
+            sos: RwLock::new(HashMap::new()),
+            cities: RwLock::new(HashMap::new()),
+            history: RwLock::new(HashMap::new()),
+            history_max_age_secs: None,
+            history_encoding: HistoryEncoding::Plain,
+            geo_index: None,
+            user_names: None,
+            require_existing_user: false,
+            allowlist: None,
+        }
+    }
+
+    /// Rebuild a store from a persisted snapshot
+    pub fn from_snapshot(snapshot: StoreSnapshot) -> Self {
+        let accepted = accepted_index(snapshot.friend_requests.iter());
+        let pending = snapshot
+            .friend_requests
+            .iter()
+            .filter(|req| req.status == FriendRequestStatus::Pending)
+            .map(|req| {
+                (
+                    (req.sender_id.clone(), req.receiver_id.clone()),
+                    req.id.clone(),
+                )
+            })
+            .collect();
+
+        let device_keys = snapshot
+            .device_keys
+            .into_iter()
+            .filter_map(
+                |(user_id, key)| match crate::signing::parse_public_key(&key) {
+                    Ok(key) => Some((user_id, key)),
+                    Err(e) => {
+                        tracing::warn!("⚠️ Dropping stored device key for {}: {}", user_id, e);
+                        None
+                    }
+                },
+            )
+            .collect();
+
+        Self {
+            users: RwLock::new(
+                snapshot
+                    .users
+                    .into_iter()
+                    .map(|user| (user.id.clone(), user))
+                    .collect(),
+            ),
+            friend_requests: RwLock::new(
+                snapshot
+                    .friend_requests
+                    .into_iter()
+                    .map(|req| (req.id.clone(), req))
+                    .collect(),
+            ),
+            pending_requests: RwLock::new(pending),
+            accepted_requests: RwLock::new(accepted),
+            discovery_hashes: RwLock::new(snapshot.discovery_hashes),
+            paused_users: RwLock::new(snapshot.paused_users),
+            nearby_only_users: RwLock::new(snapshot.nearby_only_users),
+            discoverable_users: RwLock::new(snapshot.discoverable_users),
+            guests: RwLock::new(snapshot.guests),
+            observers: RwLock::new(snapshot.observers),
+            favorites: RwLock::new(snapshot.favorites),
+            mutes: RwLock::new(snapshot.mutes),
+            device_keys: RwLock::new(device_keys),
+            temporary_shares: RwLock::new(
+                snapshot
+                    .temporary_shares
+                    .into_iter()
+                    .map(|(owner, viewer, share)| ((owner, viewer), share))
+                    .collect(),
+            ),
+            groups: RwLock::new(snapshot.groups),
+            schedules: RwLock::new(snapshot.schedules),
+            sos: RwLock::new(snapshot.sos),
+            cities: RwLock::new(snapshot.cities),
+            history: RwLock::new(
+                snapshot
+                    .history
+                    .into_iter()
+                    .map(|(id, points)| (id, HistoryBuffer::Plain(points)))
+                    .collect(),
+            ),
+            history_max_age_secs: None,
+            history_encoding: HistoryEncoding::Plain,
+            geo_index: None,
+            user_names: None,
+            require_existing_user: false,
+            allowlist: None,
+        }
+    }
+
+    /// Drop history points older than `max_age_secs`, on top of the count cap
+    /// Enforced when history is written or read, and by `prune_history`
+    pub fn with_history_max_age(mut self, max_age_secs: Option<i64>) -> Self {
+        self.history_max_age_secs = max_age_secs;
+        self
+    }
+
+    /// Keep history as full points or delta-encoded, re-encoding whatever
+    /// was loaded
+    pub fn with_history_encoding(mut self, encoding: HistoryEncoding) -> Self {
+        self.history_encoding = encoding;
+        for points in self.history.get_mut().unwrap().values_mut() {
+            let decoded = points.points().into_owned();
+            *points = HistoryBuffer::from_points(decoded, encoding);
+        }
+        self
+    }
+
+    /// Keep a grid index of current locations to speed up radius queries
+    /// Without it they scan every candidate, which gives the same answers
+    pub fn with_geo_index(mut self, enabled: bool) -> Self {
+        self.geo_index = enabled.then(|| {
+            let mut index = GridIndex::new();
+            for user in self.users.get_mut().unwrap().values() {
+                if let Some(location) = &user.location {
+                    index.insert(&user.id, location.latitude, location.longitude);
+                }
+            }
+            RwLock::new(index)
+        });
+        self
+    }
+
+    /// Require user names to be unique, ignoring case
+    /// Names already shared by several users are left alone; the first one
+    /// found keeps the name in the index, and the others keep theirs until
+    /// they change it
+    pub fn with_unique_user_names(mut self, enabled: bool) -> Self {
+        self.user_names = enabled.then(|| {
+            let mut index = HashMap::new();
+            for user in self.users.get_mut().unwrap().values() {
+                if let Some(name) = &user.user_name {
+                    index
+                        .entry(user_name_key(name))
+                        .or_insert_with(|| user.id.clone());
+                }
+            }
+            RwLock::new(index)
+        });
+        self
+    }
+
+    /// Make `update_location` fail with `NotFound` for users that don't
+    /// exist yet, instead of creating them
+    pub fn with_require_existing_user(mut self, enabled: bool) -> Self {
+        self.require_existing_user = enabled;
+        self
+    }
+
+    /// Refuse to create users that aren't on `allowlist`, with `Forbidden`
+    /// Users that already exist and guests are unaffected
+    pub fn with_allowlist(mut self, allowlist: Option<Arc<Allowlist>>) -> Self {
+        self.allowlist = allowlist;
+        self
+    }
+
+    /// Check that a user that doesn't exist yet may be created
+    fn check_allowed(&self, user_id: &str) -> Result<(), StoreError> {
+        let Some(allowlist) = &self.allowlist else {
+            return Ok(());
+        };
+        if allowlist.allows(user_id) || self.guests.read().unwrap().contains_key(user_id) {
+            return Ok(());
+        }
+        Err(StoreError::Forbidden(
+            "User is not on the beta allowlist".to_string(),
+        ))
+    }
+
+    /// Take `name` for `user_id` in the unique name index
+    fn claim_user_name(&self, user_id: &str, name: &str) -> Result<(), StoreError> {
+        let Some(index) = &self.user_names else {
+            return Ok(());
+        };
+        let mut index = index.write().unwrap();
+        let owner = index
+            .entry(user_name_key(name))
+            .or_insert_with(|| user_id.to_string());
+        if owner != user_id {
+            return Err(StoreError::Conflict(format!(
+                "User name {} is already taken",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Free `name` in the unique name index, if `user_id` holds it
+    fn release_user_name(&self, user_id: &str, name: &str) {
+        let Some(index) = &self.user_names else {
+            return;
+        };
+        let mut index = index.write().unwrap();
+        let key = user_name_key(name);
+        if index.get(&key).is_some_and(|owner| owner == user_id) {
+            index.remove(&key);
+        }
+    }
+
+    /// Keep the grid index in step with a user's current location
+    fn index_location(&self, user_id: &str, location: Option<&LocationData>) {
+        let Some(index) = &self.geo_index else {
+            return;
+        };
+        let mut index = index.write().unwrap();
+        match location {
+            Some(location) => index.insert(user_id, location.latitude, location.longitude),
+            None => index.remove(user_id),
+        }
+    }
+
+    /// Copy the whole store for persistence
+    pub fn snapshot(&self) -> StoreSnapshot {
+        StoreSnapshot {
+            users: self.users.read().unwrap().values().cloned().collect(),
+            friend_requests: self
+                .friend_requests
+                .read()
+                .unwrap()
+                .values()
+                .cloned()
+                .collect(),
+            discovery_hashes: self.discovery_hashes.read().unwrap().clone(),
+            paused_users: self.paused_users.read().unwrap().clone(),
+            nearby_only_users: self.nearby_only_users.read().unwrap().clone(),
+            discoverable_users: self.discoverable_users.read().unwrap().clone(),
+            guests: self.guests.read().unwrap().clone(),
+            observers: self.observers.read().unwrap().clone(),
+            favorites: self.favorites.read().unwrap().clone(),
+            mutes: self.mutes.read().unwrap().clone(),
+            device_keys: self
+                .device_keys
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(user_id, key)| (user_id.clone(), hex::encode(key.as_bytes())))
+                .collect(),
+            temporary_shares: self
+                .temporary_shares
+                .read()
+                .unwrap()
+                .iter()
+                .map(|((owner, viewer), share)| (owner.clone(), viewer.clone(), share.clone()))
+                .collect(),
+            groups: self.groups.read().unwrap().clone(),
+            schedules: self.schedules.read().unwrap().clone(),
+            sos: self.sos.read().unwrap().clone(),
+            cities: self.cities.read().unwrap().clone(),
+            history: self
+                .history
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(id, points)| (id.clone(), points.points().into_owned()))
+                .collect(),
         }
     }
 
@@ -46,92 +513,1089 @@ impl LocationStore {
         users.get(user_id).cloned()
     }
 
+    /// Whether the server has ever seen this user: they have a profile, or
+    /// sent or received a friend request
+    pub async fn is_known_user(&self, user_id: &str) -> bool {
+        if self.users.read().unwrap().contains_key(user_id) {
+            return true;
+        }
+        let requests = self.friend_requests.read().unwrap();
+        requests
+            .values()
+            .any(|req| req.sender_id == user_id || req.receiver_id == user_id)
+    }
+
+    /// Get several users at once under a single read lock
+    /// Unknown IDs are skipped; order follows `user_ids`
+    pub async fn get_users_bulk(&self, user_ids: &[String]) -> Vec<User> {
+        let users = self.users.read().unwrap();
+        user_ids
+            .iter()
+            .filter_map(|id| users.get(id).cloned())
+            .collect()
+    }
+
     /// Update user's location
-    pub async fn update_location(&self, user_id: &str, mut location: LocationData) {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+    /// Returns whether the new coordinates were recorded; moves shorter than
+    /// the configured minimum only refresh the timestamp (or are skipped).
+    /// With `expected_version`, fails with `Conflict` unless the user is
+    /// still at that version. Unknown users are created, unless existing
+    /// users are required.
+    pub async fn update_location(
+        &self,
+        user_id: &str,
+        mut location: LocationData,
+        movement: &MovementConfig,
+        expected_version: Option<u64>,
+    ) -> Result<bool, StoreError> {
+        let mut users = self.users.write().unwrap();
+        if !users.contains_key(user_id) {
+            if self.require_existing_user {
+                return Err(StoreError::NotFound("User"));
+            }
+            self.check_allowed(user_id)?;
+        }
+        check_version(users.get(user_id), expected_version)?;
 
+        // Stamped under the lock, so fixes are timestamped in the order
+        // they're applied
+        let timestamp = now_secs();
         location.timestamp = Some(timestamp);
 
-        let mut users = self.users.write().unwrap();
-        users
+        if let Some(min_meters) = movement.min_meters {
+            if let Some(user) = users.get_mut(user_id) {
+                if let Some(current) = &mut user.location {
+                    let moved_meters = geo::haversine_km(
+                        current.latitude,
+                        current.longitude,
+                        location.latitude,
+                        location.longitude,
+                    ) * 1000.0;
+
+                    if moved_meters < min_meters {
+                        if movement.mode == MovementMode::Touch {
+                            current.timestamp = Some(timestamp);
+                            touch(user, timestamp);
+                        }
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+
+        self.record_history(user_id, location.clone());
+        self.record_city(user_id, &location, timestamp);
+
+        let user = users.entry(user_id.to_string()).or_insert_with(|| User {
+            id: user_id.to_string(),
+            user_name: None,
+            sharing_level: None,
+            location: None,
+            last_updated: None,
+            status: None,
+            timezone: None,
+            version: 0,
+        });
+        self.index_location(user_id, Some(&location));
+        user.location = Some(location);
+        touch(user, timestamp);
+        Ok(true)
+    }
+
+    /// Append a recorded location to the user's history
+    /// A second fix within the same second replaces the first, so timestamps
+    /// stay unique and can be used as paging cursors
+    fn record_history(&self, user_id: &str, location: LocationData) {
+        let mut history = self.history.write().unwrap();
+        let points = history
             .entry(user_id.to_string())
-            .and_modify(|user| {
-                user.location = Some(location.clone());
-                user.last_updated = Some(timestamp);
-            })
-            .or_insert_with(|| User {
-                id: user_id.to_string(),
-                user_name: None,
-                sharing_level: None,
-                location: Some(location),
-                last_updated: Some(timestamp),
-            });
+            .or_insert_with(|| HistoryBuffer::new(self.history_encoding));
+        if points
+            .back()
+            .is_some_and(|last| last.timestamp >= location.timestamp)
+        {
+            points.pop_back();
+        }
+        points.push_back(location);
+        if points.len() > MAX_HISTORY_POINTS {
+            points.pop_front();
+        }
+        let cutoff = self.history_cutoff(now_secs());
+        while points
+            .front()
+            .is_some_and(|point| is_expired(point, cutoff))
+        {
+            points.pop_front();
+        }
+    }
+
+    /// Oldest timestamp history may still hold
+    fn history_cutoff(&self, now: i64) -> i64 {
+        self.history_max_age_secs
+            .map_or(i64::MIN, |max_age| now - max_age)
     }
 
-    /// Update user's sharing level
-    pub async fn update_sharing_level(&self, user_id: &str, level: SharingLevel) {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
+    /// Remove history points past the age limit for every user
+    /// Returns how many were removed
+    pub async fn prune_history(&self, now: i64) -> usize {
+        if self.history_max_age_secs.is_none() {
+            return 0;
+        }
+        let cutoff = self.history_cutoff(now);
+        let mut history = self.history.write().unwrap();
+        let mut removed = 0;
+        history.retain(|_, points| {
+            let before = points.len();
+            points.retain(|point| !is_expired(point, cutoff));
+            removed += before - points.len();
+            !points.is_empty()
+        });
+        removed
+    }
+
+    /// Note the city of a recorded location, starting a new visit only when
+    /// the city changes
+    fn record_city(&self, user_id: &str, location: &LocationData, timestamp: i64) {
+        let Some(city) = &location.city else {
+            return;
+        };
+
+        let mut cities = self.cities.write().unwrap();
+        let visits = cities.entry(user_id.to_string()).or_default();
+        match visits.back_mut() {
+            Some(last) if &last.city == city && last.country == location.country => {
+                last.last_seen = timestamp;
+            }
+            _ => {
+                visits.push_back(CityVisit {
+                    city: city.clone(),
+                    country: location.country.clone(),
+                    first_seen: timestamp,
+                    last_seen: timestamp,
+                });
+                if visits.len() > MAX_CITY_VISITS {
+                    visits.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Get a user's recent city visits, newest first
+    pub async fn get_cities(&self, user_id: &str) -> Vec<CityVisit> {
+        let cities = self.cities.read().unwrap();
+        cities
+            .get(user_id)
+            .map(|visits| visits.iter().rev().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Get every history point at or after `since`, oldest first
+    pub async fn get_history_since(&self, user_id: &str, since: i64) -> Vec<LocationData> {
+        let cutoff = self.history_cutoff(now_secs());
+        let history = self.history.read().unwrap();
+        let Some(points) = history.get(user_id) else {
+            return Vec::new();
+        };
+        points
+            .points()
+            .iter()
+            .filter(|point| point.timestamp.unwrap_or(0) >= since.max(cutoff))
+            .cloned()
+            .collect()
+    }
+
+    /// Current speed of a user in meters per second
+    /// Uses the speed reported with the latest location, or else derives it
+    /// from the last two history points
+    pub async fn current_speed(&self, user_id: &str) -> Option<f64> {
+        let reported = self
+            .users
+            .read()
             .unwrap()
-            .as_secs() as i64;
+            .get(user_id)
+            .and_then(|user| user.location.as_ref())
+            .and_then(|location| location.speed);
+        if reported.is_some() {
+            return reported;
+        }
 
-        let mut users = self.users.write().unwrap();
-        users
-            .entry(user_id.to_string())
-            .and_modify(|user| {
-                user.sharing_level = Some(level.clone());
-                user.last_updated = Some(timestamp);
-            })
-            .or_insert_with(|| User {
-                id: user_id.to_string(),
-                user_name: None,
-                sharing_level: Some(level),
-                location: None,
-                last_updated: Some(timestamp),
-            });
+        let cutoff = self.history_cutoff(now_secs());
+        let history = self.history.read().unwrap();
+        let points = history.get(user_id)?.points();
+        let mut latest = points
+            .iter()
+            .rev()
+            .filter(|point| !is_expired(point, cutoff));
+        let to = latest.next()?;
+        let from = latest.next()?;
+        geo::speed_mps(from, to)
     }
 
-    /// Update user profile
-    pub async fn update_profile(&self, user_id: &str, user_name: Option<String>) {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
+    /// Current direction of travel of a user, in degrees clockwise from north
+    /// Uses the heading reported with the latest location, or else the
+    /// bearing between the last two distinct history points
+    pub async fn current_heading(&self, user_id: &str) -> Option<f64> {
+        let reported = self
+            .users
+            .read()
             .unwrap()
-            .as_secs() as i64;
+            .get(user_id)
+            .and_then(|user| user.location.as_ref())
+            .and_then(|location| location.heading);
+        if reported.is_some() {
+            return reported;
+        }
+
+        let cutoff = self.history_cutoff(now_secs());
+        let history = self.history.read().unwrap();
+        let points = history.get(user_id)?.points();
+        let mut latest = points
+            .iter()
+            .rev()
+            .filter(|point| !is_expired(point, cutoff));
+        let to = latest.next()?;
+        let from = latest.next()?;
+        if (from.latitude, from.longitude) == (to.latitude, to.longitude) {
+            return None;
+        }
+        Some(geo::bearing_deg(
+            from.latitude,
+            from.longitude,
+            to.latitude,
+            to.longitude,
+        ))
+    }
+
+    /// Get up to `limit` history points older than `before`, newest first
+    /// Also returns the cursor for the next page, if there are older points
+    pub async fn get_history(
+        &self,
+        user_id: &str,
+        before: Option<i64>,
+        limit: usize,
+    ) -> (Vec<LocationData>, Option<i64>) {
+        let cutoff = self.history_cutoff(now_secs());
+        let history = self.history.read().unwrap();
+        let points = history.get(user_id).map(|points| points.points());
+        let mut older = points
+            .iter()
+            .flat_map(|points| points.iter())
+            .rev()
+            .filter(|point| !is_expired(point, cutoff))
+            .filter(|point| before.is_none_or(|cursor| point.timestamp.unwrap_or(0) < cursor));
+
+        let page: Vec<LocationData> = older.by_ref().take(limit).cloned().collect();
+        let next_cursor = if older.next().is_some() {
+            page.last().and_then(|point| point.timestamp)
+        } else {
+            None
+        };
+        (page, next_cursor)
+    }
 
+    /// Register (or replace) the device key for a user
+    pub async fn set_device_key(&self, user_id: &str, key: VerifyingKey) {
+        let mut device_keys = self.device_keys.write().unwrap();
+        device_keys.insert(user_id.to_string(), key);
+    }
+
+    /// Get the device key registered for a user
+    pub async fn get_device_key(&self, user_id: &str) -> Option<VerifyingKey> {
+        let device_keys = self.device_keys.read().unwrap();
+        device_keys.get(user_id).copied()
+    }
+
+    /// Apply several changes to a user at once, creating them if needed,
+    /// and return the user as it was before (`None` if new)
+    /// Everything happens under one write lock, so concurrent updates of
+    /// different fields can't undo each other. With `expected_version`,
+    /// fails with `Conflict` unless the user is still at that version.
+    pub async fn update_user(
+        &self,
+        user_id: &str,
+        patch: UserPatch,
+        expected_version: Option<u64>,
+    ) -> Result<Option<User>, StoreError> {
+        let mut users = self.users.write().unwrap();
+        let previous = users.get(user_id).cloned();
+        check_version(previous.as_ref(), expected_version)?;
+        if previous.is_none() {
+            self.check_allowed(user_id)?;
+        }
+        // Claim the new name before releasing the old one, so a rename
+        // that only changes case doesn't lose it
+        if let Some(user_name) = &patch.user_name {
+            let new_key = user_name.as_deref().map(user_name_key);
+            if let Some(name) = user_name {
+                self.claim_user_name(user_id, name)?;
+            }
+            if let Some(old) = previous.as_ref().and_then(|user| user.user_name.as_deref()) {
+                if new_key != Some(user_name_key(old)) {
+                    self.release_user_name(user_id, old);
+                }
+            }
+        }
+        let user = users.entry(user_id.to_string()).or_insert_with(|| User {
+            id: user_id.to_string(),
+            user_name: None,
+            sharing_level: None,
+            location: None,
+            last_updated: None,
+            status: None,
+            timezone: None,
+            version: 0,
+        });
+        if let Some(user_name) = patch.user_name {
+            user.user_name = user_name;
+        }
+        if let Some(level) = patch.sharing_level {
+            user.sharing_level = Some(level);
+        }
+        if let Some(status) = patch.status {
+            user.status = status;
+        }
+        if let Some(timezone) = patch.timezone {
+            user.timezone = timezone;
+        }
+        touch(user, now_secs());
+        Ok(previous)
+    }
+
+    /// Pause or resume all sharing for a user
+    /// Sharing levels are left untouched so resuming restores them as-is
+    pub async fn set_sharing_paused(&self, user_id: &str, paused: bool) {
+        let mut paused_users = self.paused_users.write().unwrap();
+        if paused {
+            paused_users.insert(user_id.to_string());
+        } else {
+            paused_users.remove(user_id);
+        }
+    }
+
+    /// Check whether a user has paused all sharing
+    pub async fn is_sharing_paused(&self, user_id: &str) -> bool {
+        let paused_users = self.paused_users.read().unwrap();
+        paused_users.contains(user_id)
+    }
+
+    /// Create a guest user that expires at `expires_at`, returning its ID
+    pub async fn create_guest(&self, expires_at: i64) -> String {
+        let guest_id = format!("guest-{}", Uuid::new_v4().simple());
+        let mut guests = self.guests.write().unwrap();
+        guests.insert(guest_id.clone(), expires_at);
+        guest_id
+    }
+
+    /// Check whether a user is a guest
+    pub async fn is_guest(&self, user_id: &str) -> bool {
+        let guests = self.guests.read().unwrap();
+        guests.contains_key(user_id)
+    }
+
+    /// Delete every guest whose lifetime is over, with all of their data
+    /// Returns the IDs of the purged guests
+    pub async fn purge_expired_guests(&self, now: i64) -> Vec<String> {
+        let expired: Vec<String> = {
+            let mut guests = self.guests.write().unwrap();
+            let expired = guests
+                .iter()
+                .filter(|(_, expires_at)| **expires_at <= now)
+                .map(|(guest_id, _)| guest_id.clone())
+                .collect::<Vec<_>>();
+            for guest_id in &expired {
+                guests.remove(guest_id);
+            }
+            expired
+        };
+        for guest_id in &expired {
+            self.delete_user(guest_id).await;
+        }
+        expired
+    }
+
+    /// Clear statuses that expired by `now`, returning how many
+    /// Reads already skip expired statuses; this just drops them from storage
+    pub async fn clear_expired_statuses(&self, now: i64) -> usize {
         let mut users = self.users.write().unwrap();
-        users
+        let mut cleared = 0;
+        for user in users.values_mut() {
+            if user
+                .status
+                .as_ref()
+                .is_some_and(|status| !status.is_active(now))
+            {
+                user.status = None;
+                cleared += 1;
+            }
+        }
+        cleared
+    }
+
+    /// Remove everything stored about a user: profile, location, history,
+    /// friend requests, observer grants in both directions, and settings
+    /// Friendships live in Sapphire and must be removed there
+    pub async fn delete_user(&self, user_id: &str) {
+        self.remove_user(user_id);
+    }
+
+    /// Body of `delete_user`, which `merge_users` also ends with
+    fn remove_user(&self, user_id: &str) {
+        let removed = self.users.write().unwrap().remove(user_id);
+        if let Some(name) = removed.and_then(|user| user.user_name) {
+            self.release_user_name(user_id, &name);
+        }
+        self.index_location(user_id, None);
+
+        let mut requests = self.friend_requests.write().unwrap();
+        let mut pending = self.pending_requests.write().unwrap();
+        let mut accepted = self.accepted_requests.write().unwrap();
+        requests.retain(|_, req| req.sender_id != user_id && req.receiver_id != user_id);
+        pending.retain(|(sender, receiver), _| sender != user_id && receiver != user_id);
+        accepted.retain(|(a, b), _| a != user_id && b != user_id);
+        drop(accepted);
+        drop(pending);
+        drop(requests);
+
+        self.discovery_hashes
+            .write()
+            .unwrap()
+            .retain(|_, owner| owner != user_id);
+        self.paused_users.write().unwrap().remove(user_id);
+        self.nearby_only_users.write().unwrap().remove(user_id);
+        self.discoverable_users.write().unwrap().remove(user_id);
+        self.guests.write().unwrap().remove(user_id);
+
+        let mut observers = self.observers.write().unwrap();
+        observers.remove(user_id);
+        for set in observers.values_mut() {
+            set.remove(user_id);
+        }
+        drop(observers);
+
+        let mut favorites = self.favorites.write().unwrap();
+        favorites.remove(user_id);
+        for set in favorites.values_mut() {
+            set.remove(user_id);
+        }
+        drop(favorites);
+
+        let mut mutes = self.mutes.write().unwrap();
+        mutes.remove(user_id);
+        for muted in mutes.values_mut() {
+            muted.remove(user_id);
+        }
+        drop(mutes);
+
+        self.device_keys.write().unwrap().remove(user_id);
+        self.temporary_shares
+            .write()
+            .unwrap()
+            .retain(|(owner, viewer), _| owner != user_id && viewer != user_id);
+
+        let mut groups = self.groups.write().unwrap();
+        groups.remove(user_id);
+        for group in groups.values_mut().flatten() {
+            group.members.retain(|member| member != user_id);
+        }
+        drop(groups);
+
+        let mut schedules = self.schedules.write().unwrap();
+        schedules.remove(user_id);
+        for rules in schedules.values_mut() {
+            rules.retain(|rule| rule.friend_id.as_deref() != Some(user_id));
+        }
+        drop(schedules);
+
+        self.sos.write().unwrap().remove(user_id);
+        self.cities.write().unwrap().remove(user_id);
+        self.history.write().unwrap().remove(user_id);
+    }
+
+    /// Turn the "nearby only" friend map default on or off for a user
+    pub async fn set_nearby_only(&self, user_id: &str, enabled: bool) {
+        let mut nearby_only_users = self.nearby_only_users.write().unwrap();
+        if enabled {
+            nearby_only_users.insert(user_id.to_string());
+        } else {
+            nearby_only_users.remove(user_id);
+        }
+    }
+
+    /// Check whether a user only wants nearby friends on their map by default
+    pub async fn is_nearby_only(&self, user_id: &str) -> bool {
+        let nearby_only_users = self.nearby_only_users.read().unwrap();
+        nearby_only_users.contains(user_id)
+    }
+
+    /// Opt a user in to or out of nearby strangers' radar
+    pub async fn set_discoverable(&self, user_id: &str, enabled: bool) {
+        let mut discoverable_users = self.discoverable_users.write().unwrap();
+        if enabled {
+            discoverable_users.insert(user_id.to_string());
+        } else {
+            discoverable_users.remove(user_id);
+        }
+    }
+
+    /// Check whether a user opted in to the radar
+    pub async fn is_discoverable(&self, user_id: &str) -> bool {
+        let discoverable_users = self.discoverable_users.read().unwrap();
+        discoverable_users.contains(user_id)
+    }
+
+    /// Users who opted in to the radar and may be within `radius_km` of a
+    /// point: every one with a location, narrowed down by the grid index when
+    /// enabled. Callers still need to check the exact distance.
+    /// Paused users are left out, since pausing hides them from everyone
+    pub async fn get_discoverable_users_near(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        radius_km: f64,
+    ) -> Vec<User> {
+        let mut ids = self.discoverable_users.read().unwrap().clone();
+        if let Some(index) = &self.geo_index {
+            let nearby: HashSet<String> = index
+                .read()
+                .unwrap()
+                .candidates_within(latitude, longitude, radius_km)
+                .into_iter()
+                .collect();
+            ids.retain(|id| nearby.contains(id));
+        }
+        {
+            let paused_users = self.paused_users.read().unwrap();
+            ids.retain(|id| !paused_users.contains(id));
+        }
+        let users = self.users.read().unwrap();
+        ids.iter()
+            .filter_map(|id| users.get(id))
+            .filter(|user| user.location.is_some())
+            .cloned()
+            .collect()
+    }
+
+    /// Share `level` with `viewer_id` until `until`, replacing any earlier grant
+    pub async fn set_temporary_share(
+        &self,
+        user_id: &str,
+        viewer_id: &str,
+        level: SharingLevel,
+        until: i64,
+    ) {
+        let mut shares = self.temporary_shares.write().unwrap();
+        shares.insert(
+            (user_id.to_string(), viewer_id.to_string()),
+            TemporaryShare { level, until },
+        );
+    }
+
+    /// Temporary level `user_id` shares with `viewer_id`, if one is still active
+    /// Expired grants are dropped on lookup
+    pub async fn get_temporary_level(
+        &self,
+        user_id: &str,
+        viewer_id: &str,
+        now: i64,
+    ) -> Option<SharingLevel> {
+        let key = (user_id.to_string(), viewer_id.to_string());
+        let expired = {
+            let shares = self.temporary_shares.read().unwrap();
+            match shares.get(&key) {
+                Some(share) if share.until > now => return Some(share.level.clone()),
+                Some(_) => true,
+                None => false,
+            }
+        };
+        if expired {
+            self.temporary_shares.write().unwrap().remove(&key);
+        }
+        None
+    }
+
+    /// Raise an SOS for a user until `until`
+    pub async fn set_sos(&self, user_id: &str, until: i64) {
+        let mut sos = self.sos.write().unwrap();
+        sos.insert(user_id.to_string(), until);
+    }
+
+    /// Clear a user's SOS, returning whether one was active
+    pub async fn clear_sos(&self, user_id: &str, now: i64) -> bool {
+        let mut sos = self.sos.write().unwrap();
+        sos.remove(user_id).is_some_and(|until| until > now)
+    }
+
+    /// Check whether a user has an unexpired SOS
+    /// Expired SOS entries are dropped on lookup
+    pub async fn is_sos_active(&self, user_id: &str, now: i64) -> bool {
+        let expired = {
+            let sos = self.sos.read().unwrap();
+            match sos.get(user_id) {
+                Some(&until) if until > now => return true,
+                Some(_) => true,
+                None => false,
+            }
+        };
+        if expired {
+            self.sos.write().unwrap().remove(user_id);
+        }
+        false
+    }
+
+    /// Drop any temporary share between two users, in either direction
+    pub async fn clear_temporary_shares(&self, user_a: &str, user_b: &str) {
+        let mut shares = self.temporary_shares.write().unwrap();
+        shares.remove(&(user_a.to_string(), user_b.to_string()));
+        shares.remove(&(user_b.to_string(), user_a.to_string()));
+    }
+
+    /// Collect everything layered over `user_id`'s global level toward `viewer_id`
+    pub async fn sharing_overrides(
+        &self,
+        user_id: &str,
+        viewer_id: &str,
+        now: i64,
+    ) -> SharingOverrides {
+        SharingOverrides {
+            paused: self.is_sharing_paused(user_id).await,
+            sos: self.is_sos_active(user_id, now).await,
+            temporary: self.get_temporary_level(user_id, viewer_id, now).await,
+            scheduled: self.scheduled_level(user_id, viewer_id, now).await,
+            group: self.group_level(user_id, viewer_id).await,
+        }
+    }
+
+    /// Get a user's scheduled sharing rules
+    pub async fn get_schedule(&self, user_id: &str) -> Vec<ScheduleRule> {
+        let schedules = self.schedules.read().unwrap();
+        schedules.get(user_id).cloned().unwrap_or_default()
+    }
+
+    /// Replace all of a user's scheduled sharing rules
+    /// Rules scoped to a group must name one of the user's groups
+    pub async fn set_schedule(
+        &self,
+        user_id: &str,
+        rules: Vec<ScheduleRule>,
+    ) -> Result<Vec<ScheduleRule>, StoreError> {
+        let groups = self.groups.read().unwrap();
+        let owned = groups.get(user_id).map(Vec::as_slice).unwrap_or_default();
+        for rule in &rules {
+            if rule.friend_id.is_some() && rule.group_id.is_some() {
+                return Err(StoreError::Invalid(
+                    "A rule can be scoped to a friend or a group, not both".to_string(),
+                ));
+            }
+            if rule.friend_id.as_deref() == Some(user_id) {
+                return Err(StoreError::Invalid(
+                    "A rule can't be scoped to its own owner".to_string(),
+                ));
+            }
+            if let Some(group_id) = &rule.group_id {
+                if !owned.iter().any(|group| &group.id == group_id) {
+                    return Err(StoreError::NotFound("Group"));
+                }
+            }
+        }
+        drop(groups);
+
+        let mut schedules = self.schedules.write().unwrap();
+        if rules.is_empty() {
+            schedules.remove(user_id);
+        } else {
+            schedules.insert(user_id.to_string(), rules.clone());
+        }
+        Ok(rules)
+    }
+
+    /// Level `user_id`'s schedule puts in force toward `viewer_id` at `now`,
+    /// in the user's time zone, if any rule covers it
+    pub async fn scheduled_level(
+        &self,
+        user_id: &str,
+        viewer_id: &str,
+        now: i64,
+    ) -> Option<ScheduledLevel> {
+        let schedules = self.schedules.read().unwrap();
+        let rules = schedules.get(user_id)?;
+        let viewer_groups: HashSet<String> = self
+            .groups
+            .read()
+            .unwrap()
+            .get(user_id)
+            .into_iter()
+            .flatten()
+            .filter(|group| group.members.iter().any(|member| member == viewer_id))
+            .map(|group| group.id.clone())
+            .collect();
+        let timezone = self
+            .users
+            .read()
+            .unwrap()
+            .get(user_id)
+            .and_then(|user| user.timezone.clone());
+        schedule::active_level(rules, viewer_id, &viewer_groups, timezone.as_deref(), now)
+    }
+
+    /// Get a user's sharing groups, sorted by name
+    pub async fn get_groups(&self, user_id: &str) -> Vec<SharingGroup> {
+        let groups = self.groups.read().unwrap();
+        let mut list = groups.get(user_id).cloned().unwrap_or_default();
+        list.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        list
+    }
+
+    /// Create an empty sharing group
+    /// Group names are unique per user
+    pub async fn create_group(
+        &self,
+        user_id: &str,
+        name: String,
+        level: SharingLevel,
+    ) -> Result<SharingGroup, StoreError> {
+        let mut groups = self.groups.write().unwrap();
+        let owned = groups.entry(user_id.to_string()).or_default();
+        check_group_name(owned, &name, None)?;
+
+        let group = SharingGroup {
+            id: Uuid::new_v4().to_string(),
+            name,
+            level,
+            members: Vec::new(),
+        };
+        owned.push(group.clone());
+        Ok(group)
+    }
+
+    /// Rename a group and/or change its level
+    pub async fn update_group(
+        &self,
+        user_id: &str,
+        group_id: &str,
+        name: Option<String>,
+        level: Option<SharingLevel>,
+    ) -> Result<SharingGroup, StoreError> {
+        let mut groups = self.groups.write().unwrap();
+        let owned = groups
+            .get_mut(user_id)
+            .ok_or(StoreError::NotFound("Group"))?;
+        if let Some(name) = &name {
+            check_group_name(owned, name, Some(group_id))?;
+        }
+
+        let group = owned
+            .iter_mut()
+            .find(|group| group.id == group_id)
+            .ok_or(StoreError::NotFound("Group"))?;
+        if let Some(name) = name {
+            group.name = name;
+        }
+        if let Some(level) = level {
+            group.level = level;
+        }
+        Ok(group.clone())
+    }
+
+    /// Delete a group; its members fall back to the user's global level
+    /// Returns the deleted group
+    pub async fn delete_group(
+        &self,
+        user_id: &str,
+        group_id: &str,
+    ) -> Result<SharingGroup, StoreError> {
+        let mut groups = self.groups.write().unwrap();
+        let owned = groups
+            .get_mut(user_id)
+            .ok_or(StoreError::NotFound("Group"))?;
+        let index = owned
+            .iter()
+            .position(|group| group.id == group_id)
+            .ok_or(StoreError::NotFound("Group"))?;
+        let group = owned.remove(index);
+        drop(groups);
+
+        // Rules scoped to the group would never apply again
+        let mut schedules = self.schedules.write().unwrap();
+        if let Some(rules) = schedules.get_mut(user_id) {
+            rules.retain(|rule| rule.group_id.as_deref() != Some(group_id));
+            if rules.is_empty() {
+                schedules.remove(user_id);
+            }
+        }
+        Ok(group)
+    }
+
+    /// Put a friend in a group, taking them out of any other group of the user's
+    pub async fn add_group_member(
+        &self,
+        user_id: &str,
+        group_id: &str,
+        friend_id: &str,
+    ) -> Result<SharingGroup, StoreError> {
+        let mut groups = self.groups.write().unwrap();
+        let owned = groups
+            .get_mut(user_id)
+            .ok_or(StoreError::NotFound("Group"))?;
+        if !owned.iter().any(|group| group.id == group_id) {
+            return Err(StoreError::NotFound("Group"));
+        }
+
+        for group in owned.iter_mut() {
+            if group.id == group_id {
+                if let Err(index) = group
+                    .members
+                    .binary_search_by(|m| m.as_str().cmp(friend_id))
+                {
+                    group.members.insert(index, friend_id.to_string());
+                }
+            } else {
+                group.members.retain(|member| member != friend_id);
+            }
+        }
+        Ok(owned
+            .iter()
+            .find(|group| group.id == group_id)
+            .cloned()
+            .expect("group checked above"))
+    }
+
+    /// Take a friend out of a group, returning whether they were in it
+    pub async fn remove_group_member(
+        &self,
+        user_id: &str,
+        group_id: &str,
+        friend_id: &str,
+    ) -> Result<bool, StoreError> {
+        let mut groups = self.groups.write().unwrap();
+        let group = groups
+            .get_mut(user_id)
+            .and_then(|owned| owned.iter_mut().find(|group| group.id == group_id))
+            .ok_or(StoreError::NotFound("Group"))?;
+        let before = group.members.len();
+        group.members.retain(|member| member != friend_id);
+        Ok(group.members.len() != before)
+    }
+
+    /// Pin or unpin a friend as one of the user's favorites
+    /// Returns whether anything changed
+    pub async fn set_favorite(&self, user_id: &str, friend_id: &str, favorite: bool) -> bool {
+        let mut favorites = self.favorites.write().unwrap();
+        if favorite {
+            return favorites
+                .entry(user_id.to_string())
+                .or_default()
+                .insert(friend_id.to_string());
+        }
+        let Some(set) = favorites.get_mut(user_id) else {
+            return false;
+        };
+        let removed = set.remove(friend_id);
+        if set.is_empty() {
+            favorites.remove(user_id);
+        }
+        removed
+    }
+
+    /// The friends a user pinned as favorites
+    pub async fn get_favorites(&self, user_id: &str) -> HashSet<String> {
+        let favorites = self.favorites.read().unwrap();
+        favorites.get(user_id).cloned().unwrap_or_default()
+    }
+
+    /// Mute a friend's location updates for a user until `until` (for good
+    /// when `None`), replacing any earlier mute
+    pub async fn set_mute(&self, user_id: &str, friend_id: &str, until: Option<i64>) {
+        let mut mutes = self.mutes.write().unwrap();
+        mutes
             .entry(user_id.to_string())
-            .and_modify(|user| {
-                user.user_name = user_name.clone();
-                user.last_updated = Some(timestamp);
-            })
-            .or_insert_with(|| User {
-                id: user_id.to_string(),
-                user_name,
-                sharing_level: None,
-                location: None,
-                last_updated: Some(timestamp),
-            });
+            .or_default()
+            .insert(friend_id.to_string(), until);
+    }
+
+    /// Unmute a friend, returning whether they were muted
+    pub async fn clear_mute(&self, user_id: &str, friend_id: &str, now: i64) -> bool {
+        let mut mutes = self.mutes.write().unwrap();
+        let Some(muted) = mutes.get_mut(user_id) else {
+            return false;
+        };
+        let removed = muted.remove(friend_id);
+        if muted.is_empty() {
+            mutes.remove(user_id);
+        }
+        removed.is_some_and(|until| until.is_none_or(|until| until > now))
+    }
+
+    /// Drop any mute between two users, in either direction
+    pub async fn clear_mutes(&self, user_a: &str, user_b: &str) {
+        let mut mutes = self.mutes.write().unwrap();
+        for (owner, other) in [(user_a, user_b), (user_b, user_a)] {
+            if let Some(muted) = mutes.get_mut(owner) {
+                muted.remove(other);
+                if muted.is_empty() {
+                    mutes.remove(owner);
+                }
+            }
+        }
+    }
+
+    /// The friends a user currently has muted
+    /// Expired mutes are dropped on lookup
+    pub async fn get_muted(&self, user_id: &str, now: i64) -> HashSet<String> {
+        let (active, expired) = {
+            let mutes = self.mutes.read().unwrap();
+            let Some(muted) = mutes.get(user_id) else {
+                return HashSet::new();
+            };
+            let active: HashSet<String> = muted
+                .iter()
+                .filter(|(_, until)| until.is_none_or(|until| until > now))
+                .map(|(friend_id, _)| friend_id.clone())
+                .collect();
+            let expired = active.len() < muted.len();
+            (active, expired)
+        };
+        if expired {
+            let mut mutes = self.mutes.write().unwrap();
+            if let Some(muted) = mutes.get_mut(user_id) {
+                muted.retain(|_, until| until.is_none_or(|until| until > now));
+                if muted.is_empty() {
+                    mutes.remove(user_id);
+                }
+            }
+        }
+        active
+    }
+
+    /// Take two users out of each other's groups
+    pub async fn leave_groups(&self, user_a: &str, user_b: &str) {
+        let mut groups = self.groups.write().unwrap();
+        for (owner, other) in [(user_a, user_b), (user_b, user_a)] {
+            for group in groups.get_mut(owner).into_iter().flatten() {
+                group.members.retain(|member| member != other);
+            }
+        }
+    }
+
+    /// Level of the group `user_id` put `viewer_id` in, if any
+    pub async fn group_level(&self, user_id: &str, viewer_id: &str) -> Option<SharingLevel> {
+        let groups = self.groups.read().unwrap();
+        groups
+            .get(user_id)?
+            .iter()
+            .find(|group| group.members.iter().any(|member| member == viewer_id))
+            .map(|group| group.level.clone())
+    }
+
+    /// Grant `observer_id` view access to `user_id`'s location
+    pub async fn add_observer(&self, user_id: &str, observer_id: &str) {
+        let mut observers = self.observers.write().unwrap();
+        observers
+            .entry(user_id.to_string())
+            .or_default()
+            .insert(observer_id.to_string());
+    }
+
+    /// Revoke `observer_id`'s view access, returning whether a grant existed
+    pub async fn remove_observer(&self, user_id: &str, observer_id: &str) -> bool {
+        let mut observers = self.observers.write().unwrap();
+        observers
+            .get_mut(user_id)
+            .is_some_and(|set| set.remove(observer_id))
+    }
+
+    /// Get everyone allowed to observe the user, sorted by ID
+    pub async fn get_observers(&self, user_id: &str) -> Vec<String> {
+        let observers = self.observers.read().unwrap();
+        let mut list: Vec<String> = observers
+            .get(user_id)
+            .map(|set| set.iter().cloned().collect())
+            .unwrap_or_default();
+        list.sort();
+        list
+    }
+
+    /// Check whether `observer_id` may view `user_id`'s location
+    pub async fn is_observer(&self, user_id: &str, observer_id: &str) -> bool {
+        let observers = self.observers.read().unwrap();
+        observers
+            .get(user_id)
+            .is_some_and(|set| set.contains(observer_id))
+    }
+
+    /// Direction of a pending request between two users, if any
+    pub async fn pending_request_direction(
+        &self,
+        user_id: &str,
+        other_id: &str,
+    ) -> Option<RequestDirection> {
+        let pending = self.pending_requests.read().unwrap();
+        if pending.contains_key(&(user_id.to_string(), other_id.to_string())) {
+            Some(RequestDirection::Outgoing)
+        } else if pending.contains_key(&(other_id.to_string(), user_id.to_string())) {
+            Some(RequestDirection::Incoming)
+        } else {
+            None
+        }
     }
 
     /// Send friend request
-    pub async fn send_friend_request(&self, sender_id: &str, receiver_id: &str) -> Result<FriendRequest, String> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+    pub async fn send_friend_request(
+        &self,
+        sender_id: &str,
+        receiver_id: &str,
+        message: Option<String>,
+        limits: &RequestLimits,
+    ) -> Result<FriendRequest, StoreError> {
+        let timestamp = now_secs();
 
-        let request_id = format!("{}_{}", sender_id, receiver_id);
+        let pair = (sender_id.to_string(), receiver_id.to_string());
 
-        // Check if request already exists
-        let requests = self.friend_requests.read().unwrap();
-        if requests.contains_key(&request_id) {
-            return Err("Friend request already exists".to_string());
+        // Hold both write locks across the check and the insert so concurrent
+        // identical sends can't both pass the existence check
+        let mut requests = self.friend_requests.write().unwrap();
+        let mut pending = self.pending_requests.write().unwrap();
+        let accepted = self.accepted_requests.read().unwrap();
+
+        // Guests are limited to one-off sharing and never join the friend graph
+        {
+            let guests = self.guests.read().unwrap();
+            if guests.contains_key(sender_id) || guests.contains_key(receiver_id) {
+                return Err(StoreError::Forbidden(
+                    "Guests can't send or receive friend requests".to_string(),
+                ));
+            }
+        }
+
+        // One live request per pair, whichever direction it was sent in
+        if let Some(existing) =
+            find_request_between(&requests, &pending, &accepted, sender_id, receiver_id)
+        {
+            let reason = match existing.status {
+                FriendRequestStatus::Accepted => "Already friends",
+                _ if existing.sender_id == sender_id => "Friend request already exists",
+                _ => "This user already sent you a friend request",
+            };
+            return Err(StoreError::Conflict(reason.to_string()));
+        }
+
+        let outgoing = pending.keys().filter(|(s, _)| s == sender_id).count();
+        if outgoing >= limits.max_outgoing_pending {
+            return Err(StoreError::Conflict(format!(
+                "Too many pending outgoing friend requests (max {})",
+                limits.max_outgoing_pending
+            )));
         }
-        drop(requests);
+
+        let incoming = pending.keys().filter(|(_, r)| r == receiver_id).count();
+        if incoming >= limits.max_incoming_pending {
+            return Err(StoreError::Conflict(format!(
+                "Receiver has too many pending friend requests (max {})",
+                limits.max_incoming_pending
+            )));
+        }
+
+        // Server-assigned ID that reveals nothing about the participants
+        let request_id = Uuid::new_v4().to_string();
 
         let request = FriendRequest {
             id: request_id.clone(),
@@ -139,46 +1603,1143 @@ impl LocationStore {
             receiver_id: receiver_id.to_string(),
             status: FriendRequestStatus::Pending,
             timestamp,
+            message,
         };
 
-        let mut requests = self.friend_requests.write().unwrap();
-        requests.insert(request_id, request.clone());
+        requests.insert(request_id.clone(), request.clone());
+        pending.insert(pair, request_id);
 
         Ok(request)
     }
 
     /// Get pending friend requests for a user
+    /// Newest first, with ties broken by ID so the order is stable
     pub async fn get_friend_requests(&self, user_id: &str) -> Vec<FriendRequest> {
         let requests = self.friend_requests.read().unwrap();
-        requests
+        let mut pending: Vec<FriendRequest> = requests
             .values()
             .filter(|req| req.receiver_id == user_id && req.status == FriendRequestStatus::Pending)
             .cloned()
-            .collect()
+            .collect();
+        pending.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.id.cmp(&b.id)));
+        pending
+    }
+
+    /// Get every friend request the user sent or received, in any status
+    pub async fn get_friend_requests_involving(&self, user_id: &str) -> Vec<FriendRequest> {
+        let requests = self.friend_requests.read().unwrap();
+        let mut involving: Vec<FriendRequest> = requests
+            .values()
+            .filter(|req| req.sender_id == user_id || req.receiver_id == user_id)
+            .cloned()
+            .collect();
+        involving.sort_by_key(|req| req.timestamp);
+        involving
     }
 
     /// Accept friend request
-    pub async fn accept_friend_request(&self, request_id: &str) -> Result<FriendRequest, String> {
+    /// Only the receiver may accept. Accepting again is a no-op that returns
+    /// the request as it is, with `false` to tell the caller nothing changed;
+    /// a declined request can't be accepted.
+    pub async fn accept_friend_request(
+        &self,
+        request_id: &str,
+        receiver_id: &str,
+    ) -> Result<(FriendRequest, bool), StoreError> {
         let mut requests = self.friend_requests.write().unwrap();
 
-        if let Some(request) = requests.get_mut(request_id) {
-            request.status = FriendRequestStatus::Accepted;
-            Ok(request.clone())
-        } else {
-            Err("Friend request not found".to_string())
+        let request = requests
+            .get_mut(request_id)
+            .ok_or(StoreError::NotFound("Friend request"))?;
+        match request.status {
+            FriendRequestStatus::Accepted if request.receiver_id == receiver_id => {
+                return Ok((request.clone(), false));
+            }
+            FriendRequestStatus::Declined if request.receiver_id == receiver_id => {
+                return Err(StoreError::Conflict(
+                    "Friend request was declined".to_string(),
+                ));
+            }
+            _ => check_can_respond(request, receiver_id)?,
         }
+
+        request.status = FriendRequestStatus::Accepted;
+        self.pending_requests
+            .write()
+            .unwrap()
+            .remove(&(request.sender_id.clone(), request.receiver_id.clone()));
+        self.accepted_requests.write().unwrap().insert(
+            pair_key(&request.sender_id, &request.receiver_id),
+            request.id.clone(),
+        );
+        Ok((request.clone(), true))
     }
 
     /// Decline friend request
-    pub async fn decline_friend_request(&self, request_id: &str) -> Result<(), String> {
+    /// Only the receiver may decline, and only while the request is pending.
+    /// The request stays around as declined, so accepting it later is a
+    /// conflict rather than a 404; it no longer blocks a new request.
+    pub async fn decline_friend_request(
+        &self,
+        request_id: &str,
+        receiver_id: &str,
+    ) -> Result<(), StoreError> {
         let mut requests = self.friend_requests.write().unwrap();
-        requests.remove(request_id);
+
+        let request = requests
+            .get_mut(request_id)
+            .ok_or(StoreError::NotFound("Friend request"))?;
+        check_can_respond(request, receiver_id)?;
+
+        request.status = FriendRequestStatus::Declined;
+        self.pending_requests
+            .write()
+            .unwrap()
+            .remove(&(request.sender_id.clone(), request.receiver_id.clone()));
         Ok(())
     }
 
+    /// The live friend request between two users, in either direction
+    /// A pending request wins over an accepted one; declined requests don't count
+    pub async fn request_between(&self, user_a: &str, user_b: &str) -> Option<FriendRequest> {
+        let requests = self.friend_requests.read().unwrap();
+        let pending = self.pending_requests.read().unwrap();
+        let accepted = self.accepted_requests.read().unwrap();
+        find_request_between(&requests, &pending, &accepted, user_a, user_b).cloned()
+    }
+
+    /// Remove every friend request between two users, in either direction
+    /// Returns how many were removed
+    pub async fn remove_requests_between(&self, user_a: &str, user_b: &str) -> usize {
+        let mut requests = self.friend_requests.write().unwrap();
+        let mut pending = self.pending_requests.write().unwrap();
+
+        let before = requests.len();
+        requests.retain(|_, req| {
+            let between = (req.sender_id == user_a && req.receiver_id == user_b)
+                || (req.sender_id == user_b && req.receiver_id == user_a);
+            !between
+        });
+        pending.remove(&(user_a.to_string(), user_b.to_string()));
+        pending.remove(&(user_b.to_string(), user_a.to_string()));
+        self.accepted_requests
+            .write()
+            .unwrap()
+            .remove(&pair_key(user_a, user_b));
+
+        before - requests.len()
+    }
+
+    /// Remove requests `receiver_id` received, returning how many
+    /// Without a cutoff only resolved (non-pending) requests go; with one,
+    /// every request sent before it goes, pending or not
+    pub async fn clear_received_requests(&self, receiver_id: &str, before: Option<i64>) -> usize {
+        let mut requests = self.friend_requests.write().unwrap();
+        let mut pending = self.pending_requests.write().unwrap();
+
+        let before_len = requests.len();
+        requests.retain(|_, req| {
+            let clear = req.receiver_id == receiver_id
+                && match before {
+                    Some(cutoff) => req.timestamp < cutoff,
+                    None => req.status != FriendRequestStatus::Pending,
+                };
+            if clear {
+                pending.remove(&(req.sender_id.clone(), req.receiver_id.clone()));
+            }
+            !clear
+        });
+        if requests.len() < before_len {
+            // An older accepted request may now be the newest for its pair
+            *self.accepted_requests.write().unwrap() = accepted_index(requests.values());
+        }
+
+        before_len - requests.len()
+    }
+
     /// Get friend request by ID
     pub async fn get_friend_request(&self, request_id: &str) -> Option<FriendRequest> {
         let requests = self.friend_requests.read().unwrap();
         requests.get(request_id).cloned()
     }
+
+    /// Register discovery hashes for a user
+    /// Only hashes are stored; raw contact info never reaches the server
+    pub async fn register_discovery_hashes(&self, user_id: &str, hashes: Vec<String>) -> usize {
+        let mut discovery_hashes = self.discovery_hashes.write().unwrap();
+        let count = hashes.len();
+        for hash in hashes {
+            discovery_hashes.insert(hash, user_id.to_string());
+        }
+        count
+    }
+
+    /// Get the discovery hashes registered by a user
+    pub async fn get_discovery_hashes(&self, user_id: &str) -> Vec<String> {
+        let discovery_hashes = self.discovery_hashes.read().unwrap();
+        let mut hashes: Vec<String> = discovery_hashes
+            .iter()
+            .filter(|(_, owner)| *owner == user_id)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        hashes.sort();
+        hashes
+    }
+
+    /// Find user IDs matching any of the given discovery hashes
+    pub async fn match_discovery_hashes(&self, hashes: &[String]) -> Vec<String> {
+        let discovery_hashes = self.discovery_hashes.read().unwrap();
+        let mut matches: Vec<String> = hashes
+            .iter()
+            .filter_map(|hash| discovery_hashes.get(hash).cloned())
+            .collect();
+        matches.sort();
+        matches.dedup();
+        matches
+    }
+
+    /// Merge everything stored for `from_id` into `into_id`, then delete `from_id`
+    /// Profile fields missing on `into_id` are filled in, the newer location wins,
+    /// history and city visits are interleaved by time, and requests/grants are
+    /// re-pointed with duplicates and self-references dropped
+    pub async fn merge_users(&self, from_id: &str, into_id: &str) {
+        let mut users = self.users.write().unwrap();
+        if let Some(from) = users.remove(from_id) {
+            let into = users.entry(into_id.to_string()).or_insert_with(|| User {
+                id: into_id.to_string(),
+                user_name: None,
+                sharing_level: None,
+                location: None,
+                last_updated: None,
+                status: None,
+                timezone: None,
+                version: 0,
+            });
+            if let Some(name) = &from.user_name {
+                self.release_user_name(from_id, name);
+            }
+            if into.user_name.is_none() {
+                into.user_name = from.user_name;
+                // Held by `from_id` until just now, so this only fails if
+                // someone else already had it too
+                if let Some(name) = &into.user_name {
+                    let _ = self.claim_user_name(into_id, name);
+                }
+            }
+            if into.sharing_level.is_none() {
+                into.sharing_level = from.sharing_level;
+            }
+            if into.status.is_none() {
+                into.status = from.status;
+            }
+            if into.timezone.is_none() {
+                into.timezone = from.timezone;
+            }
+            let from_is_newer = match (&into.location, &from.location) {
+                (None, Some(_)) => true,
+                (Some(current), Some(other)) => other.timestamp > current.timestamp,
+                _ => false,
+            };
+            if from_is_newer {
+                into.location = from.location;
+            }
+            into.last_updated = into.last_updated.max(from.last_updated);
+            // Newer than anything either account was at, so stale
+            // preconditions against either fail
+            into.version = into.version.max(from.version) + 1;
+            let location = into.location.clone();
+            self.index_location(from_id, None);
+            self.index_location(into_id, location.as_ref());
+        }
+        drop(users);
+
+        // Re-point friend requests, keeping at most one pending request per pair
+        let mut requests = self.friend_requests.write().unwrap();
+        let mut pending = self.pending_requests.write().unwrap();
+        let repoint = |id: &mut String| {
+            if id == from_id {
+                *id = into_id.to_string();
+            }
+        };
+        let mut merged: HashMap<String, FriendRequest> = HashMap::new();
+        pending.clear();
+        for (id, mut request) in requests.drain() {
+            repoint(&mut request.sender_id);
+            repoint(&mut request.receiver_id);
+            if request.sender_id == request.receiver_id {
+                continue;
+            }
+            if request.status == FriendRequestStatus::Pending {
+                let pair = (request.sender_id.clone(), request.receiver_id.clone());
+                if pending.contains_key(&pair) {
+                    continue;
+                }
+                pending.insert(pair, id.clone());
+            }
+            merged.insert(id, request);
+        }
+        *self.accepted_requests.write().unwrap() = accepted_index(merged.values());
+        *requests = merged;
+        drop(pending);
+        drop(requests);
+
+        let mut paused_users = self.paused_users.write().unwrap();
+        if paused_users.remove(from_id) {
+            paused_users.insert(into_id.to_string());
+        }
+        drop(paused_users);
+
+        let mut nearby_only_users = self.nearby_only_users.write().unwrap();
+        if nearby_only_users.remove(from_id) {
+            nearby_only_users.insert(into_id.to_string());
+        }
+        drop(nearby_only_users);
+
+        let mut discoverable_users = self.discoverable_users.write().unwrap();
+        if discoverable_users.remove(from_id) {
+            discoverable_users.insert(into_id.to_string());
+        }
+        drop(discoverable_users);
+
+        // Merge observer grants in both directions
+        let mut observers = self.observers.write().unwrap();
+        if let Some(from_observers) = observers.remove(from_id) {
+            observers
+                .entry(into_id.to_string())
+                .or_default()
+                .extend(from_observers);
+        }
+        for (owner, set) in observers.iter_mut() {
+            if set.remove(from_id) && owner != into_id {
+                set.insert(into_id.to_string());
+            }
+            if owner == into_id {
+                set.remove(into_id);
+            }
+        }
+        drop(observers);
+
+        let mut favorites = self.favorites.write().unwrap();
+        if let Some(from_favorites) = favorites.remove(from_id) {
+            favorites
+                .entry(into_id.to_string())
+                .or_default()
+                .extend(from_favorites);
+        }
+        for (owner, set) in favorites.iter_mut() {
+            if set.remove(from_id) && owner != into_id {
+                set.insert(into_id.to_string());
+            }
+            if owner == into_id {
+                set.remove(into_id);
+            }
+        }
+        drop(favorites);
+
+        let mut mutes = self.mutes.write().unwrap();
+        if let Some(from_mutes) = mutes.remove(from_id) {
+            let into_mutes = mutes.entry(into_id.to_string()).or_default();
+            for (friend_id, until) in from_mutes {
+                into_mutes.entry(friend_id).or_insert(until);
+            }
+        }
+        for (owner, muted) in mutes.iter_mut() {
+            if let Some(until) = muted.remove(from_id) {
+                if owner != into_id {
+                    muted.entry(into_id.to_string()).or_insert(until);
+                }
+            }
+            if owner == into_id {
+                muted.remove(into_id);
+            }
+        }
+        drop(mutes);
+
+        // Move groups over and re-point memberships; a friend already in one
+        // of `into_id`'s groups stays there
+        let mut groups = self.groups.write().unwrap();
+        let from_groups = groups.remove(from_id).unwrap_or_default();
+        let into_groups = groups.entry(into_id.to_string()).or_default();
+        for mut group in from_groups {
+            group.members.retain(|member| {
+                member != into_id
+                    && !into_groups
+                        .iter()
+                        .any(|existing| existing.members.contains(member))
+            });
+            if into_groups
+                .iter()
+                .any(|existing| existing.name == group.name)
+            {
+                group.name = format!("{} ({})", group.name, from_id);
+            }
+            into_groups.push(group);
+        }
+        for (owner, owned) in groups.iter_mut() {
+            let mut grouped = false;
+            for group in owned.iter_mut() {
+                let had_from = group.members.iter().any(|member| member == from_id);
+                let had_into = group.members.iter().any(|member| member == into_id);
+                group.members.retain(|member| member != from_id);
+                if had_from && !had_into && !grouped && owner != into_id {
+                    if let Err(index) = group.members.binary_search_by(|m| m.as_str().cmp(into_id))
+                    {
+                        group.members.insert(index, into_id.to_string());
+                    }
+                }
+                grouped |= had_into || (had_from && owner != into_id);
+            }
+        }
+        drop(groups);
+
+        // `into_id`'s own schedule wins; rules aimed at `from_id` follow the
+        // merge, and ones that would now aim at their own owner are dropped
+        let mut schedules = self.schedules.write().unwrap();
+        if let Some(from_rules) = schedules.remove(from_id) {
+            schedules.entry(into_id.to_string()).or_insert(from_rules);
+        }
+        for (owner, rules) in schedules.iter_mut() {
+            for rule in rules.iter_mut() {
+                if rule.friend_id.as_deref() == Some(from_id) {
+                    rule.friend_id = Some(into_id.to_string());
+                }
+            }
+            if owner == into_id {
+                rules.retain(|rule| rule.friend_id.as_deref() != Some(into_id));
+            }
+        }
+        drop(schedules);
+
+        // Interleave both histories by time; on a clash within the same
+        // second `into_id`'s point is kept
+        let mut history = self.history.write().unwrap();
+        if let Some(from_history) = history.remove(from_id) {
+            let mut points = history
+                .remove(into_id)
+                .map(|buffer| buffer.points().into_owned())
+                .unwrap_or_default();
+            points.extend(from_history.points().iter().cloned());
+            let mut points = Vec::from(points);
+            points.sort_by_key(|point| point.timestamp);
+            points.dedup_by_key(|point| point.timestamp);
+            let excess = points.len().saturating_sub(MAX_HISTORY_POINTS);
+            let points = points.into_iter().skip(excess).collect();
+            history.insert(
+                into_id.to_string(),
+                HistoryBuffer::from_points(points, self.history_encoding),
+            );
+        }
+        drop(history);
+
+        let mut cities = self.cities.write().unwrap();
+        if let Some(from_visits) = cities.remove(from_id) {
+            let visits = cities.entry(into_id.to_string()).or_default();
+            visits.extend(from_visits);
+            visits
+                .make_contiguous()
+                .sort_by_key(|visit| (visit.first_seen, visit.last_seen));
+            while visits.len() > MAX_CITY_VISITS {
+                visits.pop_front();
+            }
+        }
+        drop(cities);
+
+        // `into_id`'s device keeps signing; `from_id`'s only moves over if
+        // `into_id` never registered one
+        let mut device_keys = self.device_keys.write().unwrap();
+        if let Some(key) = device_keys.remove(from_id) {
+            device_keys.entry(into_id.to_string()).or_insert(key);
+        }
+        drop(device_keys);
+
+        let mut shares = self.temporary_shares.write().unwrap();
+        let moved: Vec<((String, String), TemporaryShare)> = shares
+            .iter()
+            .filter(|((owner, viewer), _)| owner == from_id || viewer == from_id)
+            .map(|(key, share)| (key.clone(), share.clone()))
+            .collect();
+        for ((owner, viewer), share) in moved {
+            shares.remove(&(owner.clone(), viewer.clone()));
+            let repointed = |id: String| {
+                if id == from_id {
+                    into_id.to_string()
+                } else {
+                    id
+                }
+            };
+            let (owner, viewer) = (repointed(owner), repointed(viewer));
+            if owner != viewer {
+                shares.entry((owner, viewer)).or_insert(share);
+            }
+        }
+        drop(shares);
+
+        let mut sos = self.sos.write().unwrap();
+        if let Some(from_until) = sos.remove(from_id) {
+            let until = sos.entry(into_id.to_string()).or_insert(from_until);
+            *until = (*until).max(from_until);
+        }
+        drop(sos);
+
+        let mut discovery_hashes = self.discovery_hashes.write().unwrap();
+        for owner in discovery_hashes.values_mut() {
+            if owner == from_id {
+                *owner = into_id.to_string();
+            }
+        }
+        drop(discovery_hashes);
+
+        // Everything worth keeping has moved; sweep up whatever is left
+        self.remove_user(from_id);
+    }
+
+    /// Move everything recorded about `user_id`'s whereabouts and status
+    /// `secs` into the past, as if it had been reported that much earlier
+    #[cfg(test)]
+    pub fn backdate(&self, user_id: &str, secs: i64) {
+        let earlier = |timestamp: &mut Option<i64>| {
+            if let Some(timestamp) = timestamp {
+                *timestamp -= secs;
+            }
+        };
+        if let Some(user) = self.users.write().unwrap().get_mut(user_id) {
+            earlier(&mut user.last_updated);
+            if let Some(location) = &mut user.location {
+                earlier(&mut location.timestamp);
+            }
+            if let Some(status) = &mut user.status {
+                status.expires_at -= secs;
+            }
+        }
+        if let Some(points) = self.history.write().unwrap().get_mut(user_id) {
+            let mut shifted = points.points().into_owned();
+            for point in &mut shifted {
+                earlier(&mut point.timestamp);
+            }
+            *points = HistoryBuffer::from_points(shifted, self.history_encoding);
+        }
+        if let Some(visits) = self.cities.write().unwrap().get_mut(user_id) {
+            for visit in visits {
+                visit.first_seen -= secs;
+                visit.last_seen -= secs;
+            }
+        }
+    }
+
+    /// Move a friend request `secs` into the past, as if sent that much earlier
+    #[cfg(test)]
+    pub fn backdate_request(&self, request_id: &str, secs: i64) {
+        if let Some(request) = self.friend_requests.write().unwrap().get_mut(request_id) {
+            request.timestamp -= secs;
+        }
+    }
+}
+
+/// Whether a history point is older than `cutoff`
+fn is_expired(point: &LocationData, cutoff: i64) -> bool {
+    point.timestamp.unwrap_or(0) < cutoff
+}
+
+/// Pending or accepted request between two users, in either direction,
+/// looked up through the request indexes. A pending request wins over an
+/// accepted one, and the newer of two pending ones wins.
+fn find_request_between<'a>(
+    requests: &'a HashMap<String, FriendRequest>,
+    pending: &HashMap<(String, String), String>,
+    accepted: &HashMap<(String, String), String>,
+    user_a: &str,
+    user_b: &str,
+) -> Option<&'a FriendRequest> {
+    [(user_a, user_b), (user_b, user_a)]
+        .into_iter()
+        .filter_map(|(sender, receiver)| pending.get(&(sender.to_string(), receiver.to_string())))
+        .filter_map(|id| requests.get(id))
+        .max_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| b.id.cmp(&a.id)))
+        .or_else(|| {
+            accepted
+                .get(&pair_key(user_a, user_b))
+                .and_then(|id| requests.get(id))
+        })
+}
+
+/// Key for the accepted-request index: the same for either direction
+fn pair_key(user_a: &str, user_b: &str) -> (String, String) {
+    if user_a <= user_b {
+        (user_a.to_string(), user_b.to_string())
+    } else {
+        (user_b.to_string(), user_a.to_string())
+    }
+}
+
+/// Index the newest accepted request of each pair
+fn accepted_index<'a>(
+    requests: impl Iterator<Item = &'a FriendRequest>,
+) -> HashMap<(String, String), String> {
+    let mut newest: HashMap<(String, String), &FriendRequest> = HashMap::new();
+    for request in requests.filter(|req| req.status == FriendRequestStatus::Accepted) {
+        let key = pair_key(&request.sender_id, &request.receiver_id);
+        let current = newest.entry(key).or_insert(request);
+        let newer = request.timestamp > current.timestamp
+            || (request.timestamp == current.timestamp && request.id < current.id);
+        if newer {
+            *current = request;
+        }
+    }
+    newest
+        .into_iter()
+        .map(|(key, request)| (key, request.id.clone()))
+        .collect()
+}
+
+/// Key for the unique name index: names differing only in case clash
+fn user_name_key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// Check that no other group of the user's is already called `name`
+fn check_group_name(
+    groups: &[SharingGroup],
+    name: &str,
+    except_id: Option<&str>,
+) -> Result<(), StoreError> {
+    let taken = groups
+        .iter()
+        .any(|group| group.name == name && Some(group.id.as_str()) != except_id);
+    if taken {
+        return Err(StoreError::Conflict(format!(
+            "A group named {} already exists",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Check that `user_id` may accept or decline `request`
+fn check_can_respond(request: &FriendRequest, user_id: &str) -> Result<(), StoreError> {
+    if request.receiver_id != user_id {
+        return Err(StoreError::Forbidden(
+            "Friend request is not addressed to this user".to_string(),
+        ));
+    }
+    if request.status != FriendRequestStatus::Pending {
+        return Err(StoreError::Conflict(
+            "Friend request is no longer pending".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn fix(timestamp: i64, city: &str) -> LocationData {
+        LocationData {
+            latitude: 41.0,
+            longitude: 29.0,
+            city: Some(city.to_string()),
+            country: Some("TR".to_string()),
+            timestamp: Some(timestamp),
+            speed: None,
+            heading: None,
+        }
+    }
+
+    fn record(store: &LocationStore, user_id: &str, timestamp: i64, city: &str) {
+        let location = fix(timestamp, city);
+        store.record_history(user_id, location.clone());
+        store.record_city(user_id, &location, timestamp);
+    }
+
+    /// Record every move, however small
+    fn every_move() -> MovementConfig {
+        MovementConfig {
+            min_meters: None,
+            mode: MovementMode::Touch,
+        }
+    }
+
+    fn limits() -> RequestLimits {
+        RequestLimits {
+            max_outgoing_pending: 10,
+            max_incoming_pending: 10,
+        }
+    }
+
+    #[tokio::test]
+    async fn request_between_finds_either_direction() {
+        let store = LocationStore::new();
+        let sent = store
+            .send_friend_request("alice", "bob", None, &limits())
+            .await
+            .unwrap();
+
+        for (a, b) in [("alice", "bob"), ("bob", "alice")] {
+            let found = store.request_between(a, b).await.unwrap();
+            assert_eq!(found.id, sent.id);
+        }
+        assert!(store.request_between("alice", "carol").await.is_none());
+
+        // Pending in the other direction is a conflict, not a second request
+        let err = store
+            .send_friend_request("bob", "alice", None, &limits())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StoreError::Conflict(_)));
+
+        // Once accepted it's still found, through the accepted index
+        store.accept_friend_request(&sent.id, "bob").await.unwrap();
+        let found = store.request_between("bob", "alice").await.unwrap();
+        assert_eq!(found.status, FriendRequestStatus::Accepted);
+        let err = store
+            .send_friend_request("bob", "alice", None, &limits())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Already friends");
+
+        // And gone once the pair's requests are removed
+        store.remove_requests_between("alice", "bob").await;
+        assert!(store.request_between("alice", "bob").await.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_identical_requests_leave_one_pending() {
+        let store = Arc::new(LocationStore::new());
+        let sends: Vec<_> = (0..32)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    store
+                        .send_friend_request("alice", "bob", None, &limits())
+                        .await
+                })
+            })
+            .collect();
+
+        let mut sent = 0;
+        for send in sends {
+            match send.await.unwrap() {
+                Ok(_) => sent += 1,
+                Err(e) => assert!(matches!(e, StoreError::Conflict(_))),
+            }
+        }
+        assert_eq!(sent, 1);
+        assert_eq!(store.pending_requests.read().unwrap().len(), 1);
+        assert_eq!(store.friend_requests.read().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn accepting_twice_changes_nothing_the_second_time() {
+        let store = LocationStore::new();
+        let sent = store
+            .send_friend_request("alice", "bob", None, &limits())
+            .await
+            .unwrap();
+
+        let (first, newly_accepted) = store.accept_friend_request(&sent.id, "bob").await.unwrap();
+        assert!(newly_accepted);
+        let (second, newly_accepted) = store.accept_friend_request(&sent.id, "bob").await.unwrap();
+        assert!(!newly_accepted);
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.status, FriendRequestStatus::Accepted);
+        assert_eq!(store.get_friend_requests_involving("alice").await.len(), 1);
+        assert_eq!(store.accepted_requests.read().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn accepting_a_declined_request_is_a_conflict() {
+        let store = LocationStore::new();
+        let sent = store
+            .send_friend_request("alice", "bob", None, &limits())
+            .await
+            .unwrap();
+        store.decline_friend_request(&sent.id, "bob").await.unwrap();
+
+        let err = store
+            .accept_friend_request(&sent.id, "bob")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, StoreError::Conflict(_)));
+        let declined = store.get_friend_request(&sent.id).await.unwrap();
+        assert_eq!(declined.status, FriendRequestStatus::Declined);
+
+        // It no longer counts as pending, so alice may ask again
+        assert!(store.request_between("alice", "bob").await.is_none());
+        assert!(store.get_friend_requests("bob").await.is_empty());
+        store
+            .send_friend_request("alice", "bob", None, &limits())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn merge_users_moves_history_and_leaves_nothing_behind() {
+        let store = LocationStore::new();
+        let movement = MovementConfig {
+            min_meters: None,
+            mode: MovementMode::Touch,
+        };
+        for user_id in ["old-account", "new-account", "friend"] {
+            store
+                .update_location(user_id, fix(0, "Istanbul"), &movement, None)
+                .await
+                .unwrap();
+        }
+        store.history.write().unwrap().clear();
+        store.cities.write().unwrap().clear();
+
+        record(&store, "old-account", 100, "Istanbul");
+        // Same second as a point of `new-account`'s, which wins
+        record(&store, "old-account", 200, "Bursa");
+        record(&store, "old-account", 300, "Izmir");
+        record(&store, "new-account", 200, "Ankara");
+        record(&store, "new-account", 400, "Ankara");
+
+        store.set_sos("old-account", 5_000).await;
+        store
+            .set_temporary_share("old-account", "friend", SharingLevel::Realtime, 5_000)
+            .await;
+        store
+            .set_temporary_share("friend", "old-account", SharingLevel::City, 5_000)
+            .await;
+        store.set_sharing_paused("old-account", true).await;
+        store.add_observer("friend", "old-account").await;
+        store.set_favorite("friend", "old-account", true).await;
+        store.set_mute("old-account", "friend", None).await;
+
+        store.merge_users("old-account", "new-account").await;
+
+        let history = store.get_history_since("new-account", 0).await;
+        let timestamps: Vec<Option<i64>> = history.iter().map(|point| point.timestamp).collect();
+        assert_eq!(timestamps, [Some(100), Some(200), Some(300), Some(400)]);
+        assert_eq!(history[1].city.as_deref(), Some("Ankara"));
+        let cities: Vec<String> = store
+            .get_cities("new-account")
+            .await
+            .into_iter()
+            .map(|visit| visit.city)
+            .collect();
+        assert_eq!(cities, ["Izmir", "Ankara", "Bursa", "Istanbul"]);
+
+        assert!(store.is_sos_active("new-account", 1_000).await);
+        assert_eq!(
+            store
+                .get_temporary_level("new-account", "friend", 1_000)
+                .await,
+            Some(SharingLevel::Realtime)
+        );
+        assert_eq!(
+            store
+                .get_temporary_level("friend", "new-account", 1_000)
+                .await,
+            Some(SharingLevel::City)
+        );
+
+        assert!(store.get_user("old-account").await.is_none());
+        let snapshot = serde_json::to_string(&store.snapshot()).unwrap();
+        assert!(
+            !snapshot.contains("old-account"),
+            "left behind: {}",
+            snapshot
+        );
+    }
+
+    #[test]
+    fn unix_secs_counts_from_the_epoch() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(unix_secs(time), 1_700_000_000);
+    }
+
+    #[test]
+    fn unix_secs_falls_back_to_zero_before_the_epoch() {
+        let time = UNIX_EPOCH - Duration::from_secs(86_400);
+        assert_eq!(unix_secs(time), 0);
+    }
+
+    #[tokio::test]
+    async fn bulk_lookup_matches_one_by_one_lookups() {
+        let store = LocationStore::new();
+        for user_id in ["alice", "bob", "carol"] {
+            store
+                .update_location(user_id, fix(0, "Istanbul"), &every_move(), None)
+                .await
+                .unwrap();
+        }
+        store
+            .update_user(
+                "bob",
+                UserPatch {
+                    user_name: Some(Some("Bob".to_string())),
+                    ..Default::default()
+                },
+                None,
+            )
+            .await
+            .unwrap();
+
+        let ids: Vec<String> = ["bob", "ghost", "alice", "bob"]
+            .map(str::to_string)
+            .to_vec();
+        let mut one_by_one = Vec::new();
+        for id in &ids {
+            one_by_one.extend(store.get_user(id).await);
+        }
+        let bulk = store.get_users_bulk(&ids).await;
+        assert_eq!(
+            serde_json::to_value(&bulk).unwrap(),
+            serde_json::to_value(&one_by_one).unwrap()
+        );
+        assert_eq!(bulk.len(), 3);
+        assert_eq!(bulk[0].user_name.as_deref(), Some("Bob"));
+    }
+
+    #[tokio::test]
+    async fn small_moves_refresh_the_timestamp_without_adding_history() {
+        let store = LocationStore::new();
+        let movement = MovementConfig {
+            min_meters: Some(50.0),
+            mode: MovementMode::Touch,
+        };
+        assert!(store
+            .update_location("alice", fix(0, "Istanbul"), &movement, None)
+            .await
+            .unwrap());
+        store.backdate("alice", 100);
+        let before = store.get_user("alice").await.unwrap();
+
+        // About 11m north
+        let nudged = LocationData {
+            latitude: 41.0001,
+            ..fix(0, "Istanbul")
+        };
+        assert!(!store
+            .update_location("alice", nudged, &movement, None)
+            .await
+            .unwrap());
+
+        let after = store.get_user("alice").await.unwrap();
+        assert!(after.last_updated.unwrap() >= before.last_updated.unwrap() + 100);
+        let location = after.location.unwrap();
+        assert_eq!(location.latitude, 41.0);
+        assert_eq!(location.timestamp, after.last_updated);
+        assert_eq!(store.history.read().unwrap()["alice"].len(), 1);
+
+        // A real move is recorded
+        let moved = LocationData {
+            latitude: 41.01,
+            ..fix(0, "Istanbul")
+        };
+        assert!(store
+            .update_location("alice", moved, &movement, None)
+            .await
+            .unwrap());
+        assert_eq!(
+            store
+                .get_user("alice")
+                .await
+                .unwrap()
+                .location
+                .unwrap()
+                .latitude,
+            41.01
+        );
+    }
+
+    #[tokio::test]
+    async fn request_ids_are_random_uuids() {
+        let store = LocationStore::new();
+        let limits = RequestLimits {
+            max_outgoing_pending: 100,
+            max_incoming_pending: 100,
+        };
+        let mut ids = HashSet::new();
+        for i in 0..50 {
+            let receiver = format!("user-{}", i);
+            let request = store
+                .send_friend_request("alice", &receiver, None, &limits)
+                .await
+                .unwrap();
+            // Random (v4), so nothing about the pair or the order leaks
+            let uuid = Uuid::parse_str(&request.id).unwrap();
+            assert_eq!(uuid.get_version_num(), 4);
+            assert!(!request.id.contains("alice") && !request.id.contains(&receiver));
+            ids.insert(request.id);
+        }
+        assert_eq!(ids.len(), 50);
+    }
+
+    #[tokio::test]
+    async fn pending_request_caps_apply_to_senders_and_receivers() {
+        let store = LocationStore::new();
+        let limits = RequestLimits {
+            max_outgoing_pending: 2,
+            max_incoming_pending: 2,
+        };
+        for receiver in ["bob", "carol"] {
+            store
+                .send_friend_request("alice", receiver, None, &limits)
+                .await
+                .unwrap();
+        }
+        let Err(StoreError::Conflict(reason)) = store
+            .send_friend_request("alice", "dave", None, &limits)
+            .await
+        else {
+            panic!("sender went over the cap");
+        };
+        assert_eq!(reason, "Too many pending outgoing friend requests (max 2)");
+
+        store
+            .send_friend_request("erin", "bob", None, &limits)
+            .await
+            .unwrap();
+        let Err(StoreError::Conflict(reason)) = store
+            .send_friend_request("frank", "bob", None, &limits)
+            .await
+        else {
+            panic!("receiver went over the cap");
+        };
+        assert_eq!(
+            reason,
+            "Receiver has too many pending friend requests (max 2)"
+        );
+
+        // Answering a request frees up room
+        let pending = store.get_friend_requests("bob").await;
+        store
+            .decline_friend_request(&pending[0].id, "bob")
+            .await
+            .unwrap();
+        store
+            .send_friend_request("frank", "bob", None, &limits)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn history_pages_have_no_gaps_or_overlaps() {
+        let store = LocationStore::new();
+        for timestamp in 1..=5 {
+            record(&store, "alice", timestamp * 60, "Istanbul");
+        }
+        let timestamps = |points: &[LocationData]| {
+            points
+                .iter()
+                .map(|p| p.timestamp.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let (first, cursor) = store.get_history("alice", None, 3).await;
+        assert_eq!(timestamps(&first), [300, 240, 180]);
+        assert_eq!(cursor, Some(180));
+
+        let (second, cursor) = store.get_history("alice", cursor, 3).await;
+        assert_eq!(timestamps(&second), [120, 60]);
+        assert_eq!(cursor, None);
+    }
+
+    #[tokio::test]
+    async fn staying_in_a_city_extends_the_visit() {
+        let store = LocationStore::new();
+        for (timestamp, city) in [
+            (60, "Istanbul"),
+            (120, "Istanbul"),
+            (180, "Ankara"),
+            (240, "Istanbul"),
+        ] {
+            record(&store, "alice", timestamp, city);
+        }
+
+        let visits: Vec<(String, i64, i64)> = store
+            .get_cities("alice")
+            .await
+            .into_iter()
+            .map(|visit| (visit.city, visit.first_seen, visit.last_seen))
+            .collect();
+        assert_eq!(
+            visits,
+            [
+                ("Istanbul".to_string(), 240, 240),
+                ("Ankara".to_string(), 180, 180),
+                ("Istanbul".to_string(), 60, 120),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pruning_drops_only_points_past_the_age_limit() {
+        let store = LocationStore::new().with_history_max_age(Some(3_600));
+        let now = now_secs();
+        for age in [1_800, 600, 60] {
+            record(&store, "alice", now - age, "Istanbul");
+        }
+        record(&store, "bob", now - 3_000, "Ankara");
+
+        // Half an hour on, Alice's oldest point and all of Bob's are too old
+        assert_eq!(store.prune_history(now + 2_000).await, 2);
+
+        let (points, _) = store.get_history("alice", None, 10).await;
+        let timestamps: Vec<i64> = points.iter().map(|p| p.timestamp.unwrap()).collect();
+        assert_eq!(timestamps, [now - 60, now - 600]);
+        assert!(!store.history.read().unwrap().contains_key("bob"));
+        assert_eq!(store.prune_history(now + 2_000).await, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_updates_of_different_fields_all_stick() {
+        let store = Arc::new(LocationStore::new());
+        let patches = (0..25).flat_map(|round| {
+            [
+                UserPatch {
+                    user_name: Some(Some(format!("Alice {}", round))),
+                    ..Default::default()
+                },
+                UserPatch {
+                    sharing_level: Some(SharingLevel::City),
+                    ..Default::default()
+                },
+                UserPatch {
+                    status: Some(Some(UserStatus {
+                        text: format!("Busy {}", round),
+                        expires_at: i64::MAX,
+                    })),
+                    ..Default::default()
+                },
+                UserPatch {
+                    timezone: Some(Some("Europe/Istanbul".to_string())),
+                    ..Default::default()
+                },
+            ]
+        });
+        let mut updates = Vec::new();
+        for patch in patches {
+            let profiles = store.clone();
+            updates.push(tokio::spawn(async move {
+                profiles.update_user("alice", patch, None).await.map(|_| ())
+            }));
+            let locations = store.clone();
+            updates.push(tokio::spawn(async move {
+                locations
+                    .update_location("alice", fix(0, "Istanbul"), &every_move(), None)
+                    .await
+                    .map(|_| ())
+            }));
+        }
+        for update in updates {
+            update.await.unwrap().unwrap();
+        }
+
+        let alice = store.get_user("alice").await.unwrap();
+        assert!(alice
+            .user_name
+            .is_some_and(|name| name.starts_with("Alice ")));
+        assert_eq!(alice.sharing_level, Some(SharingLevel::City));
+        assert!(alice
+            .status
+            .is_some_and(|status| status.text.starts_with("Busy ")));
+        assert_eq!(alice.timezone.as_deref(), Some("Europe/Istanbul"));
+        assert_eq!(alice.location.unwrap().city.as_deref(), Some("Istanbul"));
+        assert_eq!(alice.version, 200);
+    }
 }
@@ -1,44 +1,151 @@
 use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{delete, get, post},
-    Router,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Json, Path, RawQuery, State,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Router, ServiceExt,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tower_http::cors::CorsLayer;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tower::Layer;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    cors::CorsLayer,
+    normalize_path::{NormalizePath, NormalizePathLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+};
 use tracing::{info, warn};
+use tracing_subscriber::util::SubscriberInitExt;
+use utoipa::ToSchema;
 
+mod allowlist;
 mod celo_verifier;
+mod config;
+mod country;
+mod digest;
+mod error;
+mod events;
+mod extractors;
+mod geo;
+mod geo_index;
+mod geocoder;
+mod history;
 mod location_store;
+mod middleware;
+mod nonce;
+mod openapi;
+mod persistence;
+mod privacy;
+mod reconcile;
 mod sapphire_client;
+mod schedule;
+mod seed;
+mod signing;
+mod single_flight;
+mod stream_limit;
+mod timezone;
+mod wal;
 
+use allowlist::Allowlist;
 use celo_verifier::CeloVerifier;
-use location_store::{FriendRequest, FriendRequestStatus, LocationStore};
-use sapphire_client::SapphireClient;
+use config::{Config, LogFormat};
+use digest::RequestDigest;
+use error::{ApiResult, StoreError};
+use events::{Event, EventHub, LocationThrottle};
+use extractors::{
+    select_fields, AdminAuth, AgeParams, ApiJson, Caller, ClientIp, CursorParams, EtaParams,
+    FieldsParams, GeoParams, IfMatch, PaginationParams, PresenceParams, SinceParams, SortOrder,
+    SortParams, ValidatedQuery,
+};
+use geocoder::ReverseGeocoder;
+use location_store::{
+    now_secs, CityVisit, FriendRequest, FriendRequestStatus, LocationStore, RequestDirection,
+    SharingGroup, UserPatch,
+};
+use nonce::NonceStore;
+use persistence::Persister;
+use privacy::apply_privacy_filter;
+use sapphire_client::{Friendship, SapphireClient};
+use schedule::ScheduleRule;
+use seed::SeedData;
+use single_flight::SingleFlight;
+use stream_limit::{StreamLimiter, StreamPermit};
 
 // ============================================================================
 // Types
 // ============================================================================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum SharingLevel {
+    /// City/country labels only, no coordinates
+    #[serde(rename = "city_name")]
+    CityName,
     City,
     Realtime,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl SharingLevel {
+    pub const ALL: [SharingLevel; 3] = [Self::CityName, Self::City, Self::Realtime];
+
+    /// Name used in JSON and query strings
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CityName => "city_name",
+            Self::City => "city",
+            Self::Realtime => "realtime",
+        }
+    }
+}
+
+impl FromStr for SharingLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|level| level.as_str() == s)
+            .ok_or_else(|| {
+                let valid: Vec<&str> = Self::ALL.iter().map(Self::as_str).collect();
+                format!(
+                    "unknown sharing level `{}`, valid levels are: {}",
+                    s,
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+// Hand-written so an unknown level gets an error listing the valid ones
+impl<'de> Deserialize<'de> for SharingLevel {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        raw.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct LocationData {
     pub latitude: f64,
     pub longitude: f64,
     pub city: Option<String>,
     pub country: Option<String>,
     pub timestamp: Option<i64>,
+    /// Ground speed reported by the device, in meters per second
+    pub speed: Option<f64>,
+    /// Direction of travel reported by the device, in degrees clockwise from north
+    pub heading: Option<f64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct User {
     pub id: String,
     #[serde(rename = "userName")]
@@ -48,37 +155,288 @@ pub struct User {
     pub location: Option<LocationData>,
     #[serde(rename = "lastUpdated")]
     pub last_updated: Option<i64>,
+    /// Short status like "At the gym", shown to friends until it expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<UserStatus>,
+    /// IANA time zone like "Europe/Istanbul", used for `localTime`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Bumped on every change to the user, for `If-Match` preconditions
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// A user's status text and when it clears itself
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct UserStatus {
+    pub text: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: i64,
+}
+
+impl UserStatus {
+    /// Whether the status hasn't expired yet at `now`
+    pub fn is_active(&self, now: i64) -> bool {
+        self.expires_at > now
+    }
+}
+
+/// Location as shown to friends after privacy filtering
+/// Coordinates are `None` when the owner shares the city name only
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SharedLocation {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    pub timestamp: Option<i64>,
+}
+
+/// A user as seen by a friend or observer
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FriendLocation {
+    pub id: String,
+    #[serde(rename = "userName")]
+    pub user_name: Option<String>,
+    #[serde(rename = "sharingLevel")]
+    pub sharing_level: Option<SharingLevel>,
+    pub location: Option<SharedLocation>,
+    #[serde(rename = "lastUpdated")]
+    pub last_updated: Option<i64>,
+    /// Seconds since the visible location fix was taken
+    #[serde(rename = "ageSecs")]
+    pub age_secs: Option<i64>,
+    /// Whether the visible location is within the freshness TTL
+    #[serde(rename = "isFresh")]
+    pub is_fresh: bool,
+    /// The user has an active SOS, so their exact location is shown
+    pub sos: bool,
+    /// Rough neighborhood label, only for City or coarser sharing
+    #[serde(rename = "approximateAddress")]
+    pub approximate_address: Option<String>,
+    /// Distance from the viewer, in the friends' locations list only
+    /// Omitted when either side has no visible coordinates
+    #[serde(rename = "distanceKm", skip_serializing_if = "Option::is_none")]
+    pub distance_km: Option<f64>,
+    /// Why `location` is missing, when it is
+    #[serde(rename = "hiddenReason", skip_serializing_if = "Option::is_none")]
+    pub hidden_reason: Option<HiddenReason>,
+    /// The friend's unexpired status, hidden along with their location
+    /// when they share nothing with the viewer
+    pub status: Option<UserStatus>,
+    /// The friend's current version, to spot stale copies
+    pub version: u64,
+    /// The viewer pinned this friend; favorites are listed first
+    pub favorite: bool,
+    /// `lastUpdated` in the friend's own time zone, if they set one;
+    /// hidden along with their location, and at country-only precision
+    #[serde(rename = "localTime")]
+    pub local_time: Option<String>,
+}
+
+/// Why a friend's location isn't shown
+///
+/// There is no `stale` reason: stale fixes are still shown, marked with
+/// `isFresh: false`, and one that privacy decay hid is `Expired`. There is
+/// no `blocked` either, since users can't block each other. Reasons only
+/// cover friends and observers: anyone else asking for a user gets `403`,
+/// or `404` if there is no such user. Hidden mode is a `hidden` schedule
+/// rule, reported as `Scheduled`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HiddenReason {
+    /// The friend paused all sharing
+    Paused,
+    /// The friend hasn't chosen a sharing level
+    LevelNone,
+    /// There is no location to show, or no record of the friend yet
+    NoLocation,
+    /// The last fix is older than `PRIVACY_DECAY_HIDE_SECS`
+    Expired,
+    /// One of the friend's scheduled sharing rules hides them right now
+    Scheduled,
+}
+
+/// A user's own profile
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Profile {
+    #[serde(flatten)]
+    pub user: User,
+    /// Friends and observers who currently get a location for this user,
+    /// counting pauses, SOS and temporary shares; only shown to the user
+    /// themselves
+    #[serde(rename = "sharedWithCount", skip_serializing_if = "Option::is_none")]
+    pub shared_with_count: Option<usize>,
+    /// `lastUpdated` in the user's time zone, if they set one
+    #[serde(rename = "localTime")]
+    pub local_time: Option<String>,
+}
+
+impl Profile {
+    /// JSON field names, for `?fields=` selection
+    pub const FIELDS: &'static [&'static str] = &[
+        "id",
+        "userName",
+        "sharingLevel",
+        "location",
+        "lastUpdated",
+        "status",
+        "timezone",
+        "version",
+        "sharedWithCount",
+        "localTime",
+    ];
+}
+
+impl FriendLocation {
+    /// JSON field names, for `?fields=` selection
+    pub const FIELDS: &'static [&'static str] = &[
+        "id",
+        "userName",
+        "sharingLevel",
+        "location",
+        "lastUpdated",
+        "ageSecs",
+        "isFresh",
+        "sos",
+        "approximateAddress",
+        "distanceKm",
+        "hiddenReason",
+        "status",
+        "version",
+        "favorite",
+        "localTime",
+    ];
+
+    /// Placeholder for a friend the server has no record of yet
+    /// Reported as having no location
+    fn hidden(id: String) -> Self {
+        Self {
+            id,
+            user_name: None,
+            sharing_level: None,
+            location: None,
+            last_updated: None,
+            age_secs: None,
+            is_fresh: false,
+            sos: false,
+            approximate_address: None,
+            distance_km: None,
+            hidden_reason: Some(HiddenReason::NoLocation),
+            status: None,
+            version: 0,
+            favorite: false,
+            local_time: None,
+        }
+    }
 }
 
 // ============================================================================
 // Request/Response Types
 // ============================================================================
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct VerifySelfAuthRequest {
     pub celo_uid: String,
     pub user_id: String,
+    /// Single-use nonce from `GET /auth/nonce/{user_id}`
+    pub nonce: String,
+    /// Hex-encoded Ed25519 signature over the nonce by the user's registered
+    /// device key; required once a device key is registered
+    pub signature: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VerifyBatchItem {
+    pub celo_uid: String,
+    pub user_id: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VerifyBatchRequest {
+    pub items: Vec<VerifyBatchItem>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuthNonce {
+    pub nonce: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateLocationRequest {
     pub user_id: String,
     pub location: LocationData,
+    /// Hex-encoded Ed25519 signature by the user's registered device key
+    pub signature: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateSharingLevelRequest {
     pub user_id: String,
     pub level: SharingLevel,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ShareUntilRequest {
+    pub level: SharingLevel,
+    /// Unix timestamp at which the temporary level expires
+    pub until: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MuteFriendRequest {
+    /// Unix timestamp at which the mute ends (muted until unmuted when omitted)
+    pub until: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateGroupRequest {
+    /// Group name, e.g. "Family"; unique per user
+    pub name: String,
+    pub level: SharingLevel,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateGroupRequest {
+    pub name: Option<String>,
+    pub level: Option<SharingLevel>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SharingSchedule {
+    /// Rules in force at once resolve to the most restrictive level
+    pub rules: Vec<ScheduleRule>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetNearbyOnlyRequest {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetDiscoverableRequest {
+    pub enabled: bool,
+}
+
+/// A nearby stranger on the radar: who they are and roughly where, never
+/// their coordinates
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RadarEntry {
+    pub id: String,
+    #[serde(rename = "userName")]
+    pub user_name: Option<String>,
+    pub city: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AddFriendRequest {
     pub user_id: String,
     pub friend_id: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ApiResponse<T> {
     pub success: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -105,15 +463,66 @@ impl<T> ApiResponse<T> {
     }
 }
 
+impl<T: Serialize> IntoResponse for ApiResponse<T> {
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
+
+/// 503 response for when the friend graph (Sapphire) can't be reached, so an
+/// outage is not mistaken for an empty friend list
+fn friends_unavailable<T>(e: anyhow::Error) -> (StatusCode, Json<ApiResponse<T>>) {
+    warn!("⚠️ Sapphire unavailable: {}", e);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::err(format!(
+            "Friend service unavailable: {}",
+            e
+        ))),
+    )
+}
+
+/// Only let a request at `user_id`'s private data through if `X-User-Id`
+/// names them, or it carries the admin token
+fn check_self_or_admin(
+    user_id: &str,
+    caller: Option<&str>,
+    admin: Option<AdminAuth>,
+) -> Result<(), StoreError> {
+    if admin.is_some() || caller == Some(user_id) {
+        Ok(())
+    } else {
+        Err(StoreError::Forbidden(
+            "Only the user themselves or an admin may do this".to_string(),
+        ))
+    }
+}
+
 // ============================================================================
 // Application State
 // ============================================================================
 
 #[derive(Clone)]
 pub struct AppState {
+    pub config: Arc<Config>,
     pub location_store: Arc<LocationStore>,
     pub sapphire_client: Arc<SapphireClient>,
     pub celo_verifier: Arc<CeloVerifier>,
+    /// Outstanding login nonces
+    pub nonces: Arc<NonceStore>,
+    pub geocoder: Arc<ReverseGeocoder>,
+    pub events: Arc<EventHub>,
+    /// Friend requests waiting for the next digest (only used in digest mode)
+    pub request_digest: Arc<RequestDigest>,
+    /// Open event stream counts, capped per user and globally
+    pub streams: Arc<StreamLimiter>,
+    /// Coalesces identical concurrent friends' locations requests
+    pub friends_locations_flight: Arc<SingleFlight<(String, String), FriendsLocationsResponse>>,
+    pub persister: Arc<Persister>,
+    /// Read-only mode, toggled at runtime through the admin API
+    pub maintenance: Arc<AtomicBool>,
+    /// Closed beta user IDs (everyone is let in when `None`)
+    pub allowlist: Option<Arc<Allowlist>>,
 }
 
 // ============================================================================
@@ -121,17 +530,128 @@ pub struct AppState {
 // ============================================================================
 
 /// Health check endpoint
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    responses((status = 200, body = String)),
+)]
 async fn health() -> &'static str {
     "ok"
 }
 
+/// JSON 404 for unmatched routes
+async fn not_found() -> impl IntoResponse {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ApiResponse::<()>::err("Not found".to_string())),
+    )
+}
+
+/// JSON 405 for known routes called with the wrong method
+async fn method_not_allowed() -> impl IntoResponse {
+    (
+        StatusCode::METHOD_NOT_ALLOWED,
+        Json(ApiResponse::<()>::err("Method not allowed".to_string())),
+    )
+}
+
+/// Issue a single-use nonce to include in the next `/auth/verify`
+#[utoipa::path(
+    get,
+    path = "/auth/nonce/{user_id}",
+    tag = "auth",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses((status = 200, body = ApiResponse<AuthNonce>)),
+)]
+async fn get_auth_nonce(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    info!("🎟️ Issuing auth nonce for user: {}", user_id);
+
+    let (nonce, expires_at) = state.nonces.issue(&user_id, now_secs());
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(AuthNonce { nonce, expires_at })),
+    )
+}
+
 /// Verify Self Protocol authentication and check Celo UID
+#[utoipa::path(
+    post,
+    path = "/auth/verify",
+    tag = "auth",
+    request_body = VerifySelfAuthRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 401, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 500, body = ApiResponse<serde_json::Value>),
+    ),
+)]
 async fn verify_self_auth(
     State(state): State<AppState>,
-    Json(payload): Json<VerifySelfAuthRequest>,
+    ApiJson(payload): ApiJson<VerifySelfAuthRequest>,
 ) -> impl IntoResponse {
     info!("🔐 Verifying Self auth for user: {}", payload.user_id);
 
+    if state
+        .allowlist
+        .as_ref()
+        .is_some_and(|allowlist| !allowlist.allows(&payload.user_id))
+    {
+        warn!("❌ User not on the beta allowlist: {}", payload.user_id);
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::err(
+                "User is not on the beta allowlist".to_string(),
+            )),
+        );
+    }
+
+    // Use up the nonce first, so a captured body can't be replayed even if
+    // the rest of the verification fails
+    if !state
+        .nonces
+        .consume(&payload.user_id, &payload.nonce, now_secs())
+    {
+        warn!(
+            "❌ Invalid or reused auth nonce for user: {}",
+            payload.user_id
+        );
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::err(
+                "Invalid, expired, or already used nonce".to_string(),
+            )),
+        );
+    }
+
+    // Bind the nonce to the device, once the user has registered a key
+    if let Some(key) = state.location_store.get_device_key(&payload.user_id).await {
+        let verified = payload
+            .signature
+            .as_deref()
+            .ok_or_else(|| "Auth signature required".to_string())
+            .and_then(|signature| {
+                signing::verify_auth_signature(
+                    &key,
+                    &payload.user_id,
+                    &payload.celo_uid,
+                    &payload.nonce,
+                    signature,
+                )
+            });
+        if let Err(e) = verified {
+            warn!(
+                "❌ Auth signature rejected for user {}: {}",
+                payload.user_id, e
+            );
+            return (StatusCode::UNAUTHORIZED, Json(ApiResponse::err(e)));
+        }
+    }
+
     // Verify Celo UID matches
     match state
         .celo_verifier
@@ -152,447 +672,6232 @@ async fn verify_self_auth(
             warn!("❌ Celo UID mismatch for user: {}", payload.user_id);
             (
                 StatusCode::UNAUTHORIZED,
-                Json(ApiResponse::ok(serde_json::json!({
-                    "error": "Celo UID verification failed"
-                }))),
+                Json(ApiResponse::err("Celo UID verification failed".to_string())),
             )
         }
         Err(e) => {
             warn!("⚠️ Celo verification error: {}", e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::ok(serde_json::json!({
-                    "error": format!("Verification error: {}", e)
-                }))),
+                Json(ApiResponse::err(format!("Verification error: {}", e))),
             )
         }
     }
 }
 
-/// Get user profile
-async fn get_profile(
+/// Largest number of users verified in one batch
+const MAX_VERIFY_BATCH: usize = 100;
+
+/// How many Celo verifications a batch runs at once
+const VERIFY_BATCH_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VerifyBatchResult {
+    pub user_id: String,
+    pub verified: bool,
+    pub error: Option<String>,
+}
+
+/// Verify many users' Celo UIDs at once (admin onboarding)
+/// Each item is verified on its own; an error in one doesn't fail the batch
+#[utoipa::path(
+    post,
+    path = "/auth/verify/batch",
+    tag = "auth",
+    request_body = VerifyBatchRequest,
+    responses(
+        (status = 200, body = ApiResponse<Vec<VerifyBatchResult>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 401, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn verify_self_auth_batch(
     State(state): State<AppState>,
-    Path(user_id): Path<String>,
+    _admin: AdminAuth,
+    ApiJson(payload): ApiJson<VerifyBatchRequest>,
 ) -> impl IntoResponse {
-    info!("👤 Getting profile for user: {}", user_id);
+    info!("🔐 Batch verifying {} users", payload.items.len());
 
-    match state.location_store.get_user(&user_id).await {
-        Some(user) => (StatusCode::OK, Json(ApiResponse::ok(user))),
-        None => {
-            let empty_user = User {
-                id: user_id.clone(),
-                user_name: None,
-                sharing_level: None,
-                location: None,
-                last_updated: None,
-            };
-            (StatusCode::OK, Json(ApiResponse::ok(empty_user)))
-        },
+    if payload.items.len() > MAX_VERIFY_BATCH {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::err(format!(
+                "Too many items (max {})",
+                MAX_VERIFY_BATCH
+            ))),
+        );
     }
-}
 
-#[derive(Debug, Deserialize)]
-pub struct UpdateProfileRequest {
-    #[serde(rename = "userName")]
-    pub user_name: Option<String>,
+    let permits = Arc::new(tokio::sync::Semaphore::new(VERIFY_BATCH_CONCURRENCY));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, item) in payload.items.into_iter().enumerate() {
+        let verifier = state.celo_verifier.clone();
+        let permits = permits.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let outcome = verifier.verify_uid(&item.celo_uid, &item.user_id).await;
+            (index, item.user_id, outcome)
+        });
+    }
+
+    let mut results = Vec::with_capacity(tasks.len());
+    while let Some(joined) = tasks.join_next().await {
+        let (index, user_id, outcome) = match joined {
+            Ok(done) => done,
+            Err(e) => {
+                warn!("⚠️ Verification task failed: {}", e);
+                continue;
+            }
+        };
+        results.push((
+            index,
+            VerifyBatchResult {
+                user_id,
+                verified: matches!(outcome, Ok(true)),
+                error: match outcome {
+                    Ok(true) => None,
+                    Ok(false) => Some("Celo UID verification failed".to_string()),
+                    Err(e) => Some(format!("Verification error: {}", e)),
+                },
+            },
+        ));
+    }
+
+    // Return results in request order
+    results.sort_by_key(|(index, _)| *index);
+    let results: Vec<VerifyBatchResult> = results.into_iter().map(|(_, r)| r).collect();
+
+    (StatusCode::OK, Json(ApiResponse::ok(results)))
 }
 
-/// Update user profile
-async fn update_profile(
-    State(state): State<AppState>,
-    Path(user_id): Path<String>,
-    Json(payload): Json<UpdateProfileRequest>,
-) -> impl IntoResponse {
-    info!("✏️ Updating profile for user: {}", user_id);
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GuestUser {
+    pub id: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: i64,
+}
 
-    state
-        .location_store
-        .update_profile(&user_id, payload.user_name)
-        .await;
+/// Create a temporary guest user for one-off sharing
+/// Guests skip Celo verification, can't have friends, and are deleted with
+/// all their data once they expire
+#[utoipa::path(
+    post,
+    path = "/guests",
+    tag = "users",
+    responses((status = 200, body = ApiResponse<GuestUser>)),
+)]
+async fn create_guest(State(state): State<AppState>) -> impl IntoResponse {
+    let expires_at = now_secs() + state.config.guest_ttl_secs;
+    let id = state.location_store.create_guest(expires_at).await;
+    info!("🎫 Created guest user: {}", id);
 
     (
         StatusCode::OK,
-        Json(ApiResponse::ok(serde_json::json!({
-            "updated": true
-        }))),
+        Json(ApiResponse::ok(GuestUser { id, expires_at })),
     )
 }
 
-/// Update user's location
-async fn update_location(
+/// Largest number of profiles fetched in one batch
+const MAX_PROFILE_BATCH: usize = 100;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ProfilesRequest {
+    pub ids: Vec<String>,
+}
+
+/// What anyone may see of a user, friend or not: never their location
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PublicProfile {
+    pub id: String,
+    #[serde(rename = "userName")]
+    pub user_name: Option<String>,
+    /// Has a fresh location fix and isn't paused
+    pub online: bool,
+}
+
+/// Get several users' public profiles at once
+/// Unknown IDs are left out; the rest keep the order they were asked in
+#[utoipa::path(
+    post,
+    path = "/users/profiles",
+    tag = "users",
+    request_body = ProfilesRequest,
+    responses(
+        (status = 200, body = ApiResponse<Vec<PublicProfile>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_profiles(
     State(state): State<AppState>,
-    Json(payload): Json<UpdateLocationRequest>,
-) -> impl IntoResponse {
-    info!("📍 Updating location for user: {}", payload.user_id);
+    ApiJson(payload): ApiJson<ProfilesRequest>,
+) -> ApiResult<Vec<PublicProfile>> {
+    info!("👤 Getting {} profiles", payload.ids.len());
 
-    state
-        .location_store
-        .update_location(&payload.user_id, payload.location)
-        .await;
+    if payload.ids.len() > MAX_PROFILE_BATCH {
+        return Err(StoreError::Invalid(format!(
+            "Too many ids (max {})",
+            MAX_PROFILE_BATCH
+        )));
+    }
+    let mut seen = HashSet::new();
+    let ids: Vec<String> = payload
+        .ids
+        .into_iter()
+        .filter(|id| seen.insert(id.clone()))
+        .collect();
 
-    (
-        StatusCode::OK,
-        Json(ApiResponse::ok(serde_json::json!({
-            "updated": true
-        }))),
-    )
+    let now = now_secs();
+    let mut profiles = Vec::new();
+    for user in state.location_store.get_users_bulk(&ids).await {
+        let online = !state.location_store.is_sharing_paused(&user.id).await
+            && user
+                .location
+                .as_ref()
+                .is_some_and(|loc| privacy::is_fresh(loc, state.config.location_ttl_secs, now));
+        profiles.push(PublicProfile {
+            id: user.id,
+            user_name: user.user_name,
+            online,
+        });
+    }
+
+    Ok(ApiResponse::ok(profiles))
 }
 
-/// Update sharing level
-async fn update_sharing_level(
+/// Get user profile
+/// `sharedWithCount` is only included when `X-User-Id` names the user
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}",
+    tag = "users",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("X-User-Id" = Option<String>, Header, description = "ID of the user making the request"),
+        FieldsParams,
+    ),
+    responses(
+        (status = 200, body = ApiResponse<Profile>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_profile(
     State(state): State<AppState>,
-    Json(payload): Json<UpdateSharingLevelRequest>,
+    Path(user_id): Path<String>,
+    Caller(caller): Caller,
+    ValidatedQuery(fields): ValidatedQuery<FieldsParams>,
 ) -> impl IntoResponse {
-    info!(
-        "🔒 Updating sharing level for user: {} to {:?}",
-        payload.user_id, payload.level
-    );
+    info!("👤 Getting profile for user: {}", user_id);
 
-    state
+    let fields = match fields.selection(Profile::FIELDS) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::err(e))),
+    };
+
+    let mut user = state
         .location_store
-        .update_sharing_level(&payload.user_id, payload.level)
-        .await;
+        .get_user(&user_id)
+        .await
+        .unwrap_or_else(|| User {
+            id: user_id.clone(),
+            user_name: None,
+            sharing_level: None,
+            location: None,
+            last_updated: None,
+            status: None,
+            timezone: None,
+            version: 0,
+        });
+    user.status = user.status.filter(|status| status.is_active(now_secs()));
+    // Who can see you is for your eyes only
+    let shared_with_count = if caller.as_deref() == Some(user_id.as_str()) {
+        match shared_with_count(&state, &user).await {
+            Ok(count) => Some(count),
+            Err(e) => return friends_unavailable(e),
+        }
+    } else {
+        None
+    };
 
+    let local_time = user
+        .last_updated
+        .zip(user.timezone.as_deref())
+        .and_then(|(timestamp, tz)| timezone::local_time(timestamp, tz));
+    let profile = Profile {
+        user,
+        shared_with_count,
+        local_time,
+    };
     (
         StatusCode::OK,
-        Json(ApiResponse::ok(serde_json::json!({
-            "updated": true
-        }))),
+        Json(ApiResponse::ok(select_fields(&profile, fields.as_deref()))),
     )
 }
 
-/// Get user's friends from Sapphire
-async fn get_friends(
-    State(state): State<AppState>,
-    Path(user_id): Path<String>,
-) -> impl IntoResponse {
-    info!("👥 Getting friends for user: {}", user_id);
+/// Count the friends and observers who would get a location for `user`
+/// right now, each judged by the same privacy filter they'd see
+async fn shared_with_count(state: &AppState, user: &User) -> anyhow::Result<usize> {
+    let mut viewers = state.sapphire_client.get_friends(&user.id).await?;
+    viewers.extend(state.location_store.get_observers(&user.id).await);
+    viewers.sort();
+    viewers.dedup();
 
-    match state.sapphire_client.get_friends(&user_id).await {
-        Ok(friends) => (StatusCode::OK, Json(ApiResponse::ok(friends))),
-        Err(_e) => (StatusCode::OK, Json(ApiResponse::ok(Vec::<String>::new()))),
+    let now = now_secs();
+    let mut count = 0;
+    for viewer in &viewers {
+        let overrides = state
+            .location_store
+            .sharing_overrides(&user.id, viewer, now)
+            .await;
+        let visible = apply_privacy_filter(
+            user.clone(),
+            &overrides,
+            state.config.privacy_rounding_meters,
+            state.config.privacy_decay.as_ref(),
+            now,
+        );
+        if visible.location.is_some() {
+            count += 1;
+        }
     }
+    Ok(count)
 }
 
-/// Add friend (stores on Sapphire)
-async fn add_friend(
+/// Everything the server holds about a user, for data portability
+/// Friends appear by ID only; their locations are never included
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserDataExport {
+    pub profile: Option<User>,
+    #[serde(rename = "sharingPaused")]
+    pub sharing_paused: bool,
+    #[serde(rename = "nearbyOnly")]
+    pub nearby_only: bool,
+    pub discoverable: bool,
+    pub friends: Vec<String>,
+    pub observers: Vec<String>,
+    /// Sharing groups, sorted by name
+    pub groups: Vec<SharingGroup>,
+    #[serde(rename = "friendRequests")]
+    pub friend_requests: Vec<FriendRequest>,
+    #[serde(rename = "deviceKey")]
+    pub device_key: Option<String>,
+    #[serde(rename = "discoveryHashes")]
+    pub discovery_hashes: Vec<String>,
+    /// Recorded locations, newest first
+    pub history: Vec<LocationData>,
+    /// Recent city visits, newest first
+    pub cities: Vec<CityVisit>,
+    #[serde(rename = "exportedAt")]
+    pub exported_at: i64,
+}
+
+/// Export all of a user's data as a single JSON document
+/// Only for the user themselves (`X-User-Id`) or an admin
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/export",
+    tag = "users",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("X-User-Id" = Option<String>, Header, description = "ID of the user making the request"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<UserDataExport>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn export_user_data(
     State(state): State<AppState>,
-    Json(payload): Json<AddFriendRequest>,
+    Path(user_id): Path<String>,
+    Caller(caller): Caller,
+    admin: Option<AdminAuth>,
 ) -> impl IntoResponse {
-    info!(
-        "➕ Adding friend {} for user: {}",
-        payload.friend_id, payload.user_id
-    );
+    info!("📦 Exporting data for user: {}", user_id);
 
-    match state
-        .sapphire_client
-        .add_friend(&payload.user_id, &payload.friend_id)
-        .await
-    {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(ApiResponse::ok(serde_json::json!({
-                "added": true
-            }))),
-        ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::ok(serde_json::json!({
-                "error": format!("Failed to add friend: {}", e)
-            }))),
-        ),
+    if let Err(e) = check_self_or_admin(&user_id, caller.as_deref(), admin) {
+        return (e.status(), Json(ApiResponse::err(e.to_string())));
     }
-}
 
-/// Remove friend (removes from Sapphire)
-async fn remove_friend(
-    State(state): State<AppState>,
-    Path((user_id, friend_id)): Path<(String, String)>,
-) -> impl IntoResponse {
-    info!("➖ Removing friend {} for user: {}", friend_id, user_id);
+    let friends = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(f) => f,
+        Err(e) => return friends_unavailable(e),
+    };
 
-    match state
-        .sapphire_client
-        .remove_friend(&user_id, &friend_id)
-        .await
-    {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(ApiResponse::ok(serde_json::json!({
-                "removed": true
-            }))),
-        ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::ok(serde_json::json!({
-                "error": format!("Failed to remove friend: {}", e)
-            }))),
-        ),
-    }
+    let store = &state.location_store;
+    let export = UserDataExport {
+        profile: store.get_user(&user_id).await,
+        sharing_paused: store.is_sharing_paused(&user_id).await,
+        nearby_only: store.is_nearby_only(&user_id).await,
+        discoverable: store.is_discoverable(&user_id).await,
+        friends,
+        observers: store.get_observers(&user_id).await,
+        groups: store.get_groups(&user_id).await,
+        friend_requests: store.get_friend_requests_involving(&user_id).await,
+        device_key: store
+            .get_device_key(&user_id)
+            .await
+            .map(|key| hex::encode(key.as_bytes())),
+        discovery_hashes: store.get_discovery_hashes(&user_id).await,
+        history: store.get_history(&user_id, None, usize::MAX).await.0,
+        cities: store.get_cities(&user_id).await,
+        exported_at: now_secs(),
+    };
+
+    (StatusCode::OK, Json(ApiResponse::ok(export)))
 }
 
-/// Get all friends' locations (with privacy filtering)
-async fn get_friends_locations(
-    State(state): State<AppState>,
-    Path(user_id): Path<String>,
-) -> impl IntoResponse {
-    info!("🗺️ Getting friends' locations for user: {}", user_id);
+/// History points read from the store per chunk of the streamed export
+const EXPORT_HISTORY_PAGE: usize = 500;
 
-    // Get friends from Sapphire
-    let friends = match state.sapphire_client.get_friends(&user_id).await {
-        Ok(f) => f,
-        Err(_e) => return (StatusCode::OK, Json(ApiResponse::ok(Vec::<User>::new()))),
-    };
+/// Profile and settings, the first line of the streamed export
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExportProfile {
+    pub profile: Option<User>,
+    #[serde(rename = "sharingPaused")]
+    pub sharing_paused: bool,
+    #[serde(rename = "nearbyOnly")]
+    pub nearby_only: bool,
+    pub discoverable: bool,
+    #[serde(rename = "deviceKey")]
+    pub device_key: Option<String>,
+    #[serde(rename = "discoveryHashes")]
+    pub discovery_hashes: Vec<String>,
+    #[serde(rename = "exportedAt")]
+    pub exported_at: i64,
+}
 
-    // Get locations for each friend with privacy filtering
-    let mut friend_locations = Vec::new();
-    for friend_id in friends {
-        if let Some(mut friend) = state.location_store.get_user(&friend_id).await {
-            // Apply privacy filtering based on sharing level
-            if let Some(location) = &mut friend.location {
-                match friend.sharing_level {
-                    Some(SharingLevel::City) => {
-                        // Round to city level (2 decimal places)
-                        location.latitude = (location.latitude * 100.0).round() / 100.0;
-                        location.longitude = (location.longitude * 100.0).round() / 100.0;
+/// One line of the streamed export, tagged with `type`
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ExportRecord {
+    Profile(Box<ExportProfile>),
+    /// Newest first, like the history endpoint
+    HistoryPoint(LocationData),
+    CityVisit(CityVisit),
+    Friend {
+        id: String,
+    },
+    Observer {
+        id: String,
+    },
+    Group(SharingGroup),
+    FriendRequest(FriendRequest),
+    /// Always last, so a cut-off export can be told apart from a complete one
+    End {
+        records: usize,
+    },
+}
+
+enum ExportStage {
+    Profile,
+    History { before: Option<i64> },
+    Rest,
+    Done,
+}
+
+/// Produces the streamed export chunk by chunk, reading history a page at a time
+struct ExportCursor {
+    state: AppState,
+    user_id: String,
+    friends: Vec<String>,
+    stage: ExportStage,
+    records: usize,
+}
+
+impl ExportCursor {
+    fn push(&mut self, chunk: &mut Vec<u8>, record: &ExportRecord) {
+        serde_json::to_writer(&mut *chunk, record).expect("export records serialize to JSON");
+        chunk.push(b'\n');
+        self.records += 1;
+    }
+
+    async fn next_chunk(&mut self) -> Option<Vec<u8>> {
+        let store = self.state.location_store.clone();
+        let user_id = self.user_id.clone();
+        let mut chunk = Vec::new();
+        loop {
+            match self.stage {
+                ExportStage::Profile => {
+                    let profile = ExportProfile {
+                        profile: store.get_user(&user_id).await,
+                        sharing_paused: store.is_sharing_paused(&user_id).await,
+                        nearby_only: store.is_nearby_only(&user_id).await,
+                        discoverable: store.is_discoverable(&user_id).await,
+                        device_key: store
+                            .get_device_key(&user_id)
+                            .await
+                            .map(|key| hex::encode(key.as_bytes())),
+                        discovery_hashes: store.get_discovery_hashes(&user_id).await,
+                        exported_at: now_secs(),
+                    };
+                    self.push(&mut chunk, &ExportRecord::Profile(Box::new(profile)));
+                    self.stage = ExportStage::History { before: None };
+                    return Some(chunk);
+                }
+                ExportStage::History { before } => {
+                    let (points, next) = store
+                        .get_history(&user_id, before, EXPORT_HISTORY_PAGE)
+                        .await;
+                    for point in points {
+                        self.push(&mut chunk, &ExportRecord::HistoryPoint(point));
+                    }
+                    self.stage = match next {
+                        Some(before) => ExportStage::History {
+                            before: Some(before),
+                        },
+                        None => ExportStage::Rest,
+                    };
+                    if !chunk.is_empty() {
+                        return Some(chunk);
                     }
-                    Some(SharingLevel::Realtime) => {
-                        // Keep exact coordinates
+                }
+                ExportStage::Rest => {
+                    for visit in store.get_cities(&user_id).await {
+                        self.push(&mut chunk, &ExportRecord::CityVisit(visit));
+                    }
+                    for id in std::mem::take(&mut self.friends) {
+                        self.push(&mut chunk, &ExportRecord::Friend { id });
+                    }
+                    for id in store.get_observers(&user_id).await {
+                        self.push(&mut chunk, &ExportRecord::Observer { id });
+                    }
+                    for group in store.get_groups(&user_id).await {
+                        self.push(&mut chunk, &ExportRecord::Group(group));
                     }
-                    None => {
-                        // No sharing level set, hide location
-                        friend.location = None;
+                    for request in store.get_friend_requests_involving(&user_id).await {
+                        self.push(&mut chunk, &ExportRecord::FriendRequest(request));
                     }
+                    let records = self.records + 1;
+                    self.push(&mut chunk, &ExportRecord::End { records });
+                    self.stage = ExportStage::Done;
+                    return Some(chunk);
                 }
+                ExportStage::Done => return None,
             }
-            friend_locations.push(friend);
         }
     }
-
-    (StatusCode::OK, Json(ApiResponse::ok(friend_locations)))
 }
 
-/// Get specific friend's location (with privacy filtering)
-async fn get_friend_location(
+/// Export all of a user's data as newline-delimited JSON, streamed
+/// Same data as `/export`, one `ExportRecord` per line: the profile, then
+/// history points, city visits and friendships, then an `end` line. History
+/// is read a page at a time, so large exports aren't held in memory.
+/// Only for the user themselves (`X-User-Id`) or an admin, like `/export`.
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/export/stream",
+    tag = "users",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("X-User-Id" = Option<String>, Header, description = "ID of the user making the request"),
+    ),
+    responses(
+        (status = 200, description = "One JSON record per line", body = ExportRecord, content_type = "application/x-ndjson"),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn export_user_data_stream(
     State(state): State<AppState>,
-    Path((user_id, friend_id)): Path<(String, String)>,
-) -> impl IntoResponse {
-    info!("👤 Getting location for friend: {} (user: {})", friend_id, user_id);
+    Path(user_id): Path<String>,
+    Caller(caller): Caller,
+    admin: Option<AdminAuth>,
+) -> Response {
+    info!("📦 Streaming data export for user: {}", user_id);
 
-    // Check if they are friends
+    if let Err(e) = check_self_or_admin(&user_id, caller.as_deref(), admin) {
+        return (e.status(), Json(ApiResponse::<()>::err(e.to_string()))).into_response();
+    }
+
+    // Loaded up front, so a failure can still be reported with a status
     let friends = match state.sapphire_client.get_friends(&user_id).await {
         Ok(f) => f,
-        Err(_e) => {
-            let empty_user = User {
-                id: friend_id.clone(),
-                user_name: None,
-                sharing_level: None,
-                location: None,
-                last_updated: None,
-            };
-            return (StatusCode::OK, Json(ApiResponse::ok(empty_user)));
-        }
+        Err(e) => return friends_unavailable::<()>(e).into_response(),
     };
 
-    if !friends.contains(&friend_id) {
-        let empty_user = User {
-            id: friend_id.clone(),
-            user_name: None,
-            sharing_level: None,
-            location: None,
-            last_updated: None,
-        };
-        return (StatusCode::OK, Json(ApiResponse::ok(empty_user)));
-    }
-
-    // Get friend's location
-    match state.location_store.get_user(&friend_id).await {
-        Some(mut friend) => {
-            // Apply privacy filtering
-            if let Some(location) = &mut friend.location {
-                match friend.sharing_level {
-                    Some(SharingLevel::City) => {
-                        location.latitude = (location.latitude * 100.0).round() / 100.0;
-                        location.longitude = (location.longitude * 100.0).round() / 100.0;
-                    }
-                    Some(SharingLevel::Realtime) => {}
-                    None => {
-                        friend.location = None;
-                    }
-                }
-            }
-            (StatusCode::OK, Json(ApiResponse::ok(friend)))
-        }
-        None => {
-            let empty_user = User {
-                id: friend_id.clone(),
-                user_name: None,
-                sharing_level: None,
-                location: None,
-                last_updated: None,
-            };
-            (StatusCode::OK, Json(ApiResponse::ok(empty_user)))
-        },
+    let cursor = ExportCursor {
+        state,
+        user_id,
+        friends,
+        stage: ExportStage::Profile,
+        records: 0,
+    };
+    let chunks = futures_util::stream::unfold(cursor, |mut cursor| async move {
+        let chunk = cursor.next_chunk().await?;
+        Some((Ok::<_, std::convert::Infallible>(chunk), cursor))
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(chunks),
+    )
+        .into_response()
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateProfileRequest {
+    #[serde(rename = "userName")]
+    pub user_name: Option<String>,
+}
+
+/// Update user profile
+#[utoipa::path(
+    put,
+    path = "/users/{user_id}",
+    tag = "users",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = UpdateProfileRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn update_profile(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    IfMatch(expected_version): IfMatch,
+    ApiJson(payload): ApiJson<UpdateProfileRequest>,
+) -> impl IntoResponse {
+    info!("✏️ Updating profile for user: {}", user_id);
+
+    // Blank names clear the name, like leaving it out
+    let user_name = match payload
+        .user_name
+        .as_deref()
+        .map(|name| sanitize_text(name, state.config.max_user_name_chars, "User name"))
+        .transpose()
+    {
+        Ok(name) => name.flatten(),
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::err(e))),
+    };
+
+    let updated = state
+        .location_store
+        .update_user(
+            &user_id,
+            UserPatch {
+                user_name: Some(user_name),
+                ..Default::default()
+            },
+            expected_version,
+        )
+        .await;
+    if let Err(e) = updated {
+        return (e.status(), Json(ApiResponse::err(e.to_string())));
     }
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "updated": true
+        }))),
+    )
 }
 
-// ============================================================================
-// Friend Request Handlers
-// ============================================================================
+/// Longest status text accepted, in characters
+const MAX_STATUS_CHARS: usize = 80;
 
-#[derive(Debug, Deserialize)]
-pub struct SendFriendRequestRequest {
-    #[serde(rename = "senderId")]
-    pub sender_id: String,
-    #[serde(rename = "receiverId")]
-    pub receiver_id: String,
+/// How long a status lasts when no duration is given, in hours
+const DEFAULT_STATUS_HOURS: i64 = 4;
+
+/// Longest a status may last, in hours
+const MAX_STATUS_HOURS: i64 = 24;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetStatusRequest {
+    /// Status text, e.g. "At the gym"; blank clears the status
+    pub text: String,
+    /// Hours until the status clears itself (default 4, max 24)
+    pub hours: Option<i64>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct RespondFriendRequestRequest {
-    #[serde(rename = "requestId")]
-    pub request_id: String,
+/// Set or clear the user's status
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/status",
+    tag = "users",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = SetStatusRequest,
+    responses(
+        (status = 200, body = ApiResponse<Option<UserStatus>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn set_status(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    IfMatch(expected_version): IfMatch,
+    ApiJson(payload): ApiJson<SetStatusRequest>,
+) -> ApiResult<Option<UserStatus>> {
+    info!("💬 Setting status for user: {}", user_id);
+
+    let hours = payload.hours.unwrap_or(DEFAULT_STATUS_HOURS);
+    if !(1..=MAX_STATUS_HOURS).contains(&hours) {
+        return Err(StoreError::Invalid(format!(
+            "hours must be between 1 and {}, got {}",
+            MAX_STATUS_HOURS, hours
+        )));
+    }
+    let text =
+        sanitize_text(&payload.text, MAX_STATUS_CHARS, "Status").map_err(StoreError::Invalid)?;
+
+    let status = text.map(|text| UserStatus {
+        text,
+        expires_at: now_secs() + hours * 3600,
+    });
+    state
+        .location_store
+        .update_user(
+            &user_id,
+            UserPatch {
+                status: Some(status.clone()),
+                ..Default::default()
+            },
+            expected_version,
+        )
+        .await?;
+
+    Ok(ApiResponse::ok(status))
 }
 
-/// Send friend request
-async fn send_friend_request(
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetTimezoneRequest {
+    /// IANA time zone, e.g. "Europe/Istanbul"; `null` or blank clears it
+    pub timezone: Option<String>,
+}
+
+/// Set or clear the user's time zone
+/// Once set, the profile and friends' views of the user include `localTime`
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/timezone",
+    tag = "users",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = SetTimezoneRequest,
+    responses(
+        (status = 200, body = ApiResponse<Option<String>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn set_timezone(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    IfMatch(expected_version): IfMatch,
+    ApiJson(payload): ApiJson<SetTimezoneRequest>,
+) -> ApiResult<Option<String>> {
+    info!("🕰️ Setting time zone for user: {}", user_id);
+
+    let tz = payload
+        .timezone
+        .map(|tz| tz.trim().to_string())
+        .filter(|tz| !tz.is_empty());
+    if let Some(tz) = &tz {
+        timezone::validate(tz).map_err(StoreError::Invalid)?;
+    }
+    state
+        .location_store
+        .update_user(
+            &user_id,
+            UserPatch {
+                timezone: Some(tz.clone()),
+                ..Default::default()
+            },
+            expected_version,
+        )
+        .await?;
+
+    Ok(ApiResponse::ok(tz))
+}
+
+/// Update user's location
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/location",
+    tag = "users",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = UpdateLocationRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 401, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn update_location(
     State(state): State<AppState>,
-    Json(payload): Json<SendFriendRequestRequest>,
+    IfMatch(expected_version): IfMatch,
+    ApiJson(mut payload): ApiJson<UpdateLocationRequest>,
 ) -> impl IntoResponse {
-    info!(
-        "📨 Sending friend request from {} to {}",
-        payload.sender_id, payload.receiver_id
-    );
+    info!("📍 Updating location for user: {}", payload.user_id);
 
-    match state
+    // Verify the device signature over the raw update, when signed or required
+    let device_key = state.location_store.get_device_key(&payload.user_id).await;
+    match (&payload.signature, device_key) {
+        (Some(signature), Some(key)) => {
+            if let Err(e) = signing::verify_location_signature(
+                &key,
+                &payload.user_id,
+                &payload.location,
+                signature,
+            ) {
+                warn!("❌ Rejected location for user {}: {}", payload.user_id, e);
+                return (StatusCode::UNAUTHORIZED, Json(ApiResponse::err(e)));
+            }
+        }
+        (Some(_), None) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse::err(
+                    "No device key registered for this user".to_string(),
+                )),
+            )
+        }
+        (None, _) if state.config.require_signed_location => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse::err(
+                    "Location updates must be signed".to_string(),
+                )),
+            )
+        }
+        (None, _) => {}
+    }
+
+    // Store countries canonically as ISO 3166-1 alpha-2 codes
+    if let Some(raw) = payload.location.country.take() {
+        match country::normalize_country(&raw) {
+            Some(code) => payload.location.country = Some(code.to_string()),
+            None if state.config.country_lenient => payload.location.country = Some(raw),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::err(format!("Unrecognized country: {}", raw))),
+                )
+            }
+        }
+    }
+
+    // Only after the signature check, which covers the coordinates as sent
+    geo::round_coordinates(&mut payload.location, state.config.coordinate_decimals);
+
+    let moved = match state
         .location_store
-        .send_friend_request(&payload.sender_id, &payload.receiver_id)
+        .update_location(
+            &payload.user_id,
+            payload.location,
+            &state.config.movement,
+            expected_version,
+        )
         .await
     {
-        Ok(request) => (StatusCode::OK, Json(ApiResponse::ok(request))),
-        Err(e) => (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::err(e)),
-        ),
+        Ok(moved) => moved,
+        Err(e) => return (e.status(), Json(ApiResponse::err(e.to_string()))),
+    };
+    if moved {
+        state.events.publish(Event::LocationUpdated {
+            user_id: payload.user_id.clone(),
+        });
     }
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "updated": true,
+            "moved": moved
+        }))),
+    )
 }
 
-/// Get pending friend requests for a user
-async fn get_friend_requests(
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LocationHistoryPage {
+    /// Newest first
+    pub points: Vec<LocationData>,
+    /// Pass as `before` to fetch the next page; `None` on the last page
+    #[serde(rename = "nextCursor")]
+    pub next_cursor: Option<i64>,
+}
+
+/// Get the user's location history, newest first, paged by timestamp cursor
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/history",
+    tag = "users",
+    params(("user_id" = String, Path, description = "User ID"), CursorParams),
+    responses(
+        (status = 200, body = ApiResponse<LocationHistoryPage>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_location_history(
     State(state): State<AppState>,
     Path(user_id): Path<String>,
+    ValidatedQuery(cursor): ValidatedQuery<CursorParams>,
 ) -> impl IntoResponse {
-    info!("📬 Getting friend requests for user: {}", user_id);
+    info!("🕰️ Getting location history for user: {}", user_id);
+
+    let (points, next_cursor) = state
+        .location_store
+        .get_history(&user_id, cursor.before, cursor.limit())
+        .await;
 
-    let requests = state.location_store.get_friend_requests(&user_id).await;
-    (StatusCode::OK, Json(ApiResponse::ok(requests)))
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(LocationHistoryPage {
+            points,
+            next_cursor,
+        })),
+    )
 }
 
-/// Accept friend request
-async fn accept_friend_request(
+/// Get the total distance the user traveled, from their location history
+/// Only for the user themself (`X-User-Id`) or an admin, like the history
+/// it's computed from
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/distance",
+    tag = "users",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("X-User-Id" = Option<String>, Header, description = "ID of the user making the request"),
+        SinceParams,
+    ),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_distance(
     State(state): State<AppState>,
-    Path((user_id, request_id)): Path<(String, String)>,
+    Path(user_id): Path<String>,
+    Caller(caller): Caller,
+    admin: Option<AdminAuth>,
+    ValidatedQuery(window): ValidatedQuery<SinceParams>,
 ) -> impl IntoResponse {
-    info!("✅ User {} accepting friend request: {}", user_id, request_id);
-
-    match state.location_store.accept_friend_request(&request_id).await {
-        Ok(request) => {
-            // Add both users as friends on Sapphire
-            let _ = state
-                .sapphire_client
-                .add_friend(&request.sender_id, &request.receiver_id)
-                .await;
-            let _ = state
-                .sapphire_client
-                .add_friend(&request.receiver_id, &request.sender_id)
-                .await;
+    info!("📏 Getting distance traveled for user: {}", user_id);
 
-            (StatusCode::OK, Json(ApiResponse::ok(request)))
-        }
-        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::err(e))),
+    if let Err(e) = check_self_or_admin(&user_id, caller.as_deref(), admin) {
+        return (e.status(), Json(ApiResponse::err(e.to_string())));
     }
+
+    let points = state
+        .location_store
+        .get_history_since(&user_id, window.since.unwrap_or(i64::MIN))
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "distance_km": geo::path_length_km(&points),
+            "points": points.len()
+        }))),
+    )
 }
 
-/// Decline friend request
-async fn decline_friend_request(
+/// Get the distinct cities the user has recently been in, newest first
+/// Only for the user themself (`X-User-Id`) or an admin: the trail reveals
+/// more than any sharing level
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/cities",
+    tag = "users",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("X-User-Id" = Option<String>, Header, description = "ID of the user making the request"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<Vec<CityVisit>>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_cities(
     State(state): State<AppState>,
-    Path((user_id, request_id)): Path<(String, String)>,
+    Path(user_id): Path<String>,
+    Caller(caller): Caller,
+    admin: Option<AdminAuth>,
 ) -> impl IntoResponse {
-    info!("❌ User {} declining friend request: {}", user_id, request_id);
+    info!("🏙️ Getting recent cities for user: {}", user_id);
 
-    match state.location_store.decline_friend_request(&request_id).await {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(ApiResponse::ok(serde_json::json!({"declined": true}))),
-        ),
-        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::err(e))),
+    if let Err(e) = check_self_or_admin(&user_id, caller.as_deref(), admin) {
+        return (e.status(), Json(ApiResponse::err(e.to_string())));
     }
+
+    let cities = state.location_store.get_cities(&user_id).await;
+    (StatusCode::OK, Json(ApiResponse::ok(cities)))
 }
 
-// ============================================================================
-// Main Application
-// ============================================================================
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RegisterDeviceKeyRequest {
+    /// Hex-encoded Ed25519 public key
+    pub public_key: String,
+}
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
+/// Register the device key used to sign location updates
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/device-key",
+    tag = "users",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = RegisterDeviceKeyRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn register_device_key(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ApiJson(payload): ApiJson<RegisterDeviceKeyRequest>,
+) -> impl IntoResponse {
+    info!("🔑 Registering device key for user: {}", user_id);
 
-    info!("🚀 Starting Linda ROFL Backend...");
+    match signing::parse_public_key(&payload.public_key) {
+        Ok(key) => {
+            state.location_store.set_device_key(&user_id, key).await;
+            (
+                StatusCode::OK,
+                Json(ApiResponse::ok(serde_json::json!({
+                    "registered": true
+                }))),
+            )
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, Json(ApiResponse::err(e))),
+    }
+}
 
-    // Initialize components
-    let location_store = Arc::new(LocationStore::new());
-    let sapphire_client = Arc::new(SapphireClient::new().await?);
-    let celo_verifier = Arc::new(CeloVerifier::new());
+/// Update sharing level
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/sharing-level",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = UpdateSharingLevelRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn update_sharing_level(
+    State(state): State<AppState>,
+    IfMatch(expected_version): IfMatch,
+    ApiJson(payload): ApiJson<UpdateSharingLevelRequest>,
+) -> impl IntoResponse {
+    info!(
+        "🔒 Updating sharing level for user: {} to {:?}",
+        payload.user_id, payload.level
+    );
 
-    let state = AppState {
-        location_store,
-        sapphire_client,
-        celo_verifier,
+    let previous = match state
+        .location_store
+        .update_user(
+            &payload.user_id,
+            UserPatch {
+                sharing_level: Some(payload.level.clone()),
+                ..Default::default()
+            },
+            expected_version,
+        )
+        .await
+    {
+        Ok(previous) => previous.and_then(|user| user.sharing_level),
+        Err(e) => return (e.status(), Json(ApiResponse::err(e.to_string()))),
     };
 
-    // Build router
-    let app = Router::new()
-        .route("/health", get(health))
-        .route("/auth/verify", post(verify_self_auth))
-        .route("/users/:user_id", get(get_profile).put(update_profile))
-        .route("/users/:user_id/location", post(update_location))
-        .route("/users/:user_id/sharing-level", post(update_sharing_level))
+    // Let friends know to refetch; the level update itself stands even if
+    // the friend list can't be loaded
+    if previous.as_ref() != Some(&payload.level) {
+        match state.sapphire_client.get_friends(&payload.user_id).await {
+            Ok(friends) => state.events.publish(Event::VisibilityChanged {
+                user_id: payload.user_id,
+                recipients: friends,
+            }),
+            Err(e) => warn!("⚠️ Not notifying friends of level change: {}", e),
+        }
+    }
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "updated": true
+        }))),
+    )
+}
+
+/// Pause all sharing
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/sharing/pause",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses((status = 200, body = ApiResponse<serde_json::Value>)),
+)]
+async fn pause_sharing(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    info!("⏸️ Pausing sharing for user: {}", user_id);
+
+    state
+        .location_store
+        .set_sharing_paused(&user_id, true)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "paused": true
+        }))),
+    )
+}
+
+/// Resume sharing with the previous settings
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/sharing/resume",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses((status = 200, body = ApiResponse<serde_json::Value>)),
+)]
+async fn resume_sharing(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    info!("▶️ Resuming sharing for user: {}", user_id);
+
+    state
+        .location_store
+        .set_sharing_paused(&user_id, false)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "paused": false
+        }))),
+    )
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SharingAuditEntry {
+    #[serde(rename = "friendId")]
+    pub friend_id: String,
+    #[serde(rename = "sharingLevel")]
+    pub sharing_level: Option<SharingLevel>,
+    #[serde(rename = "precisionMeters")]
+    pub precision_meters: Option<f64>,
+    #[serde(rename = "hasFreshFix")]
+    pub has_fresh_fix: bool,
+}
+
+/// Audit what each friend can currently see of the user
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/sharing/audit",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses(
+        (status = 200, body = ApiResponse<Vec<SharingAuditEntry>>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_sharing_audit(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    info!("🧾 Auditing sharing for user: {}", user_id);
+
+    let friends = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(f) => f,
+        Err(e) => return friends_unavailable(e),
+    };
+
+    let now = now_secs();
+    let user = state.location_store.get_user(&user_id).await;
+    let fix_is_fresh = user
+        .as_ref()
+        .and_then(|u| u.location.as_ref())
+        .is_some_and(|loc| privacy::is_fresh(loc, state.config.location_ttl_secs, now));
+
+    let mut audit = Vec::new();
+    for friend_id in friends {
+        let overrides = state
+            .location_store
+            .sharing_overrides(&user_id, &friend_id, now)
+            .await;
+        let level = user
+            .as_ref()
+            .and_then(|u| privacy::effective_level(u, &overrides));
+
+        audit.push(SharingAuditEntry {
+            friend_id,
+            precision_meters: level
+                .as_ref()
+                .map(|l| privacy::precision_meters(l, state.config.privacy_rounding_meters)),
+            has_fresh_fix: level.is_some() && fix_is_fresh,
+            sharing_level: level,
+        });
+    }
+
+    (StatusCode::OK, Json(ApiResponse::ok(audit)))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SimulatePrivacyRequest {
+    pub level: SharingLevel,
+    /// Point to simulate with; the user's current location when omitted
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+/// What a viewer would see of a location at a given level
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PrivacySimulation {
+    #[serde(rename = "sharingLevel")]
+    pub sharing_level: SharingLevel,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    #[serde(rename = "precisionMeters")]
+    pub precision_meters: f64,
+}
+
+/// Preview what a viewer would see at a proposed sharing level
+/// Nothing is saved
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/privacy/simulate",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = SimulatePrivacyRequest,
+    responses(
+        (status = 200, body = ApiResponse<PrivacySimulation>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn simulate_privacy(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ApiJson(payload): ApiJson<SimulatePrivacyRequest>,
+) -> impl IntoResponse {
+    info!(
+        "🧪 Simulating {:?} sharing for user: {}",
+        payload.level, user_id
+    );
+
+    let current = state
+        .location_store
+        .get_user(&user_id)
+        .await
+        .and_then(|u| u.location);
+
+    let (latitude, longitude, city, country) = match (payload.latitude, payload.longitude) {
+        (Some(lat), Some(lng)) => {
+            if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::err("Coordinates out of range".to_string())),
+                );
+            }
+            // A custom point has no labels of its own
+            (lat, lng, None, None)
+        }
+        (None, None) => match current {
+            Some(loc) => (loc.latitude, loc.longitude, loc.city, loc.country),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::err(
+                        "No location to simulate with; send latitude and longitude".to_string(),
+                    )),
+                )
+            }
+        },
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::err(
+                    "latitude and longitude must be given together".to_string(),
+                )),
+            )
+        }
+    };
+
+    let rounding = state.config.privacy_rounding_meters;
+    let (latitude, longitude) =
+        privacy::filter_coordinates(&payload.level, latitude, longitude, rounding);
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(PrivacySimulation {
+            precision_meters: privacy::precision_meters(&payload.level, rounding),
+            sharing_level: payload.level,
+            latitude,
+            longitude,
+            city,
+            country,
+        })),
+    )
+}
+
+/// Get user's friends from Sapphire
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User ID"), PaginationParams),
+    responses(
+        (status = 200, body = ApiResponse<Vec<String>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_friends(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ValidatedQuery(page): ValidatedQuery<PaginationParams>,
+) -> impl IntoResponse {
+    info!("👥 Getting friends for user: {}", user_id);
+
+    // Favorites come first, which needs the whole list; without any, the
+    // friend service can page by itself
+    let favorites = state.location_store.get_favorites(&user_id).await;
+    let friends = if favorites.is_empty() {
+        let (offset, limit) = page.window();
+        state
+            .sapphire_client
+            .get_friends_paged(&user_id, offset, limit)
+            .await
+    } else {
+        state
+            .sapphire_client
+            .get_friends(&user_id)
+            .await
+            .map(|mut friends| {
+                friends.sort_by_key(|friend| !favorites.contains(friend));
+                page.paginate(friends)
+            })
+    };
+    match friends {
+        Ok(friends) => (StatusCode::OK, Json(ApiResponse::ok(friends))),
+        Err(e) => friends_unavailable(e),
+    }
+}
+
+/// Get user's friends with where each friendship came from: `requestId`
+/// is the accepted friend request that created it, or `null` for
+/// friendships added directly or imported
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends/details",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User ID"), PaginationParams),
+    responses(
+        (status = 200, body = ApiResponse<Vec<Friendship>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_friend_details(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ValidatedQuery(page): ValidatedQuery<PaginationParams>,
+) -> impl IntoResponse {
+    info!("👥 Getting friend details for user: {}", user_id);
+
+    let favorites = state.location_store.get_favorites(&user_id).await;
+    match state.sapphire_client.get_friendships(&user_id).await {
+        Ok(mut friends) => {
+            friends.sort_by_key(|friend| !favorites.contains(&friend.friend_id));
+            (
+                StatusCode::OK,
+                Json(ApiResponse::ok(page.paginate(friends))),
+            )
+        }
+        Err(e) => friends_unavailable(e),
+    }
+}
+
+/// Count a user's friends
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends/count",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn count_friends(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    info!("🔢 Counting friends for user: {}", user_id);
+
+    match state.sapphire_client.count_friends(&user_id).await {
+        Ok(count) => (
+            StatusCode::OK,
+            Json(ApiResponse::ok(serde_json::json!({ "count": count }))),
+        ),
+        Err(e) => friends_unavailable(e),
+    }
+}
+
+/// Add friend (stores on Sapphire)
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friends",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = AddFriendRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 500, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn add_friend(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<AddFriendRequest>,
+) -> impl IntoResponse {
+    info!(
+        "➕ Adding friend {} for user: {}",
+        payload.friend_id, payload.user_id
+    );
+
+    let store = &state.location_store;
+    if store.is_guest(&payload.user_id).await || store.is_guest(&payload.friend_id).await {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::err("Guests can't have friends".to_string())),
+        );
+    }
+
+    match state
+        .sapphire_client
+        .add_friend(&payload.user_id, &payload.friend_id, None)
+        .await
+    {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(ApiResponse::ok(serde_json::json!({
+                "added": true
+            }))),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::err(format!("Failed to add friend: {}", e))),
+        ),
+    }
+}
+
+/// Remove friend (removes from Sapphire)
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/friends/{friend_id}",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 500, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn remove_friend(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    info!("➖ Removing friend {} for user: {}", friend_id, user_id);
+
+    match state
+        .sapphire_client
+        .remove_friend(&user_id, &friend_id)
+        .await
+    {
+        Ok(_) => {
+            state
+                .location_store
+                .clear_temporary_shares(&user_id, &friend_id)
+                .await;
+            state
+                .location_store
+                .leave_groups(&user_id, &friend_id)
+                .await;
+            unfavorite_each_other(&state, &user_id, &friend_id).await;
+            state.location_store.clear_mutes(&user_id, &friend_id).await;
+            // Drop the accepted request too, so either side can ask again later
+            state
+                .location_store
+                .remove_requests_between(&user_id, &friend_id)
+                .await;
+            (
+                StatusCode::OK,
+                Json(ApiResponse::ok(serde_json::json!({
+                    "removed": true
+                }))),
+            )
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::err(format!("Failed to remove friend: {}", e))),
+        ),
+    }
+}
+
+/// Get all friends' locations (with privacy filtering)
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends/locations",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        GeoParams,
+        PresenceParams,
+        FieldsParams,
+    ),
+    responses(
+        (status = 200, body = ApiResponse<Vec<FriendLocation>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_friends_locations(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    RawQuery(query): RawQuery,
+    ValidatedQuery(geo): ValidatedQuery<GeoParams>,
+    ValidatedQuery(presence): ValidatedQuery<PresenceParams>,
+    ValidatedQuery(fields): ValidatedQuery<FieldsParams>,
+) -> impl IntoResponse {
+    info!("🗺️ Getting friends' locations for user: {}", user_id);
+
+    // Clients poll this heavily, so identical requests arriving together
+    // share one computation
+    let key = (user_id.clone(), query.unwrap_or_default());
+    state
+        .friends_locations_flight
+        .run(key, || {
+            compute_friends_locations(state.clone(), user_id, geo, presence, fields)
+        })
+        .await
+}
+
+/// Result of `get_friends_locations`, shared between coalesced requests
+type FriendsLocationsResponse = (StatusCode, Json<ApiResponse<Vec<serde_json::Value>>>);
+
+async fn compute_friends_locations(
+    state: AppState,
+    user_id: String,
+    geo: GeoParams,
+    presence: PresenceParams,
+    fields: FieldsParams,
+) -> FriendsLocationsResponse {
+    let fields = match fields.selection(FriendLocation::FIELDS) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::err(e))),
+    };
+
+    // Get friends from Sapphire
+    let friends = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(f) => f,
+        Err(e) => return friends_unavailable(e),
+    };
+
+    // Get locations for all friends from one consistent snapshot, keeping
+    // only those matching the presence filters. "Online" means the visible
+    // location is fresh; the level is the one effectively shared with this
+    // user, so paused friends never match and SOS counts as realtime.
+    let muted = state.location_store.get_muted(&user_id, now_secs()).await;
+    let mut friend_locations = Vec::new();
+    for friend in state.location_store.get_users_bulk(&friends).await {
+        if muted.contains(&friend.id) {
+            continue;
+        }
+        if let Some(level) = &presence.level {
+            let overrides = state
+                .location_store
+                .sharing_overrides(&friend.id, &user_id, now_secs())
+                .await;
+            if privacy::effective_level(&friend, &overrides).as_ref() != Some(level) {
+                continue;
+            }
+        }
+
+        let friend = filter_friend(&state, friend, &user_id).await;
+        if presence
+            .online
+            .is_some_and(|online| friend.is_fresh != online)
+        {
+            continue;
+        }
+        friend_locations.push(friend);
+    }
+    // Favorites first; the sort is stable, so each part keeps its order
+    friend_locations.sort_by_key(|friend| !friend.favorite);
+
+    // Distances are measured from the user's own location to the already
+    // privacy-filtered coordinates. Without a location of their own there is
+    // nothing to measure from, so the full list is returned without
+    // `distanceKm` and no radius filtering is applied.
+    let own_location = state
+        .location_store
+        .get_user(&user_id)
+        .await
+        .and_then(|u| u.location);
+    if let Some(own) = &own_location {
+        for friend in &mut friend_locations {
+            friend.distance_km = friend
+                .location
+                .as_ref()
+                .and_then(|loc| loc.latitude.zip(loc.longitude))
+                .map(|(lat, lng)| geo::haversine_km(own.latitude, own.longitude, lat, lng));
+        }
+    }
+
+    // Keep only friends within the radius (friends sharing a city name only
+    // have no coordinates and are skipped). An explicit radius wins over the
+    // user's "nearby only" default.
+    let radius_km = match geo.radius_km {
+        Some(radius_km) => Some(radius_km),
+        None => state
+            .location_store
+            .is_nearby_only(&user_id)
+            .await
+            .then_some(state.config.nearby_radius_km),
+    };
+    if let (Some(radius_km), Some(_)) = (radius_km, &own_location) {
+        friend_locations.retain(|friend| {
+            friend
+                .distance_km
+                .is_some_and(|distance| distance <= radius_km)
+        });
+    }
+
+    let friend_locations: Vec<serde_json::Value> = friend_locations
+        .iter()
+        .map(|friend| select_fields(friend, fields.as_deref()))
+        .collect();
+    (StatusCode::OK, Json(ApiResponse::ok(friend_locations)))
+}
+
+/// Make the friends map show only nearby friends by default
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friends/nearby-only",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = SetNearbyOnlyRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn set_nearby_only(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ApiJson(payload): ApiJson<SetNearbyOnlyRequest>,
+) -> impl IntoResponse {
+    info!("📍 Nearby-only {} for user: {}", payload.enabled, user_id);
+
+    state
+        .location_store
+        .set_nearby_only(&user_id, payload.enabled)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "nearbyOnly": payload.enabled,
+            "radiusKm": state.config.nearby_radius_km
+        }))),
+    )
+}
+
+/// Privacy-filter a friend's location for one viewer, with freshness and,
+/// for City or coarser sharing, an approximate address
+async fn filter_friend(state: &AppState, friend: User, viewer_id: &str) -> FriendLocation {
+    let overrides = state
+        .location_store
+        .sharing_overrides(&friend.id, viewer_id, now_secs())
+        .await;
+    let precision = privacy::shown_precision(
+        &friend,
+        &overrides,
+        state.config.privacy_decay.as_ref(),
+        now_secs(),
+    );
+    let raw_location = friend.location.clone();
+
+    let mut filtered = apply_privacy_filter(
+        friend,
+        &overrides,
+        state.config.privacy_rounding_meters,
+        state.config.privacy_decay.as_ref(),
+        now_secs(),
+    );
+    privacy::annotate_freshness(&mut filtered, state.config.location_ttl_secs, now_secs());
+    filtered.status = filtered
+        .status
+        .filter(|status| status.is_active(now_secs()));
+    filtered.favorite = state
+        .location_store
+        .get_favorites(viewer_id)
+        .await
+        .contains(&filtered.id);
+
+    if let Some(location) = raw_location.filter(|_| privacy::allows_approximate_address(precision))
+    {
+        let reported = location.city.or(location.country);
+        filtered.approximate_address = state
+            .geocoder
+            .approximate_address(location.latitude, location.longitude, reported)
+            .await;
+    }
+    filtered
+}
+
+/// Stream events for a user over a WebSocket
+/// Sends friends' location updates (privacy-filtered, like
+/// `/friends/locations`), SOS alerts, and sharing level changes as JSON text
+/// messages tagged with `type`
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/stream",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses(
+        (status = 101, description = "Switching to the WebSocket protocol"),
+        (status = 429, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn stream_events(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    info!("📡 Opening event stream for user: {}", user_id);
+
+    let permit = match state.streams.try_acquire(&user_id) {
+        Ok(permit) => permit,
+        Err(e) => {
+            warn!("🚫 Refusing stream for {}: {}", user_id, e);
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(ApiResponse::<()>::err(e.to_string())),
+            )
+                .into_response();
+        }
+    };
+    ws.on_upgrade(move |socket| forward_events(socket, state, user_id, permit))
+}
+
+/// Forward events meant for `user_id` until either side hangs up
+/// Holds the stream's slot until then; it's released however the task ends
+async fn forward_events(
+    mut socket: WebSocket,
+    state: AppState,
+    user_id: String,
+    _permit: StreamPermit,
+) {
+    let mut events = state.events.subscribe();
+    let mut throttle = state
+        .config
+        .stream_location_interval_ms
+        .map(|ms| LocationThrottle::new(Duration::from_millis(ms)));
+    loop {
+        let due = throttle.as_ref().and_then(LocationThrottle::next_due);
+        tokio::select! {
+            received = events.recv() => {
+                let event = match received {
+                    Ok(event) => event,
+                    // Events were dropped, so what the client shows may be stale;
+                    // tell it to refetch rather than carry on silently
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("⚠️ Stream for {} missed {} events", user_id, missed);
+                        let resync = serde_json::json!({
+                            "type": "resync_required",
+                            "missed": missed
+                        });
+                        if socket.send(Message::Text(resync.to_string())).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let moved = match &event {
+                    Event::LocationUpdated { user_id } => Some(user_id.clone()),
+                    _ => None,
+                };
+                let Some(message) = render_event(&state, event, &user_id).await else {
+                    continue;
+                };
+                if let (Some(throttle), Some(moved)) = (throttle.as_mut(), moved) {
+                    if !throttle.admit(&moved, tokio::time::Instant::now()) {
+                        continue;
+                    }
+                }
+                if socket.send(Message::Text(message.to_string())).await.is_err() {
+                    break;
+                }
+            }
+            _ = wait_until(due) => {
+                // Send held-back friends' locations as they are now
+                let ready = throttle
+                    .as_mut()
+                    .map(|throttle| throttle.take_due(tokio::time::Instant::now()))
+                    .unwrap_or_default();
+                let mut closed = false;
+                for friend_id in ready {
+                    let event = Event::LocationUpdated { user_id: friend_id };
+                    let Some(message) = render_event(&state, event, &user_id).await else {
+                        continue;
+                    };
+                    if socket.send(Message::Text(message.to_string())).await.is_err() {
+                        closed = true;
+                        break;
+                    }
+                }
+                if closed {
+                    break;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    info!("📡 Closed event stream for user: {}", user_id);
+}
+
+/// Sleep until `due`, or forever when there is nothing to wait for
+async fn wait_until(due: Option<tokio::time::Instant>) {
+    match due {
+        Some(due) => tokio::time::sleep_until(due).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// What `viewer_id` should receive for an event, or `None` if it isn't for them
+/// Location updates are checked against the friend graph at send time rather
+/// than when the stream opened, so friends added or removed since then are
+/// picked up without reconnecting
+async fn render_event(
+    state: &AppState,
+    event: Event,
+    viewer_id: &str,
+) -> Option<serde_json::Value> {
+    match event.recipients() {
+        Some(recipients) if !recipients.iter().any(|r| r == viewer_id) => return None,
+        Some(_) => return serde_json::to_value(&event).ok(),
+        None => {}
+    }
+
+    let Event::LocationUpdated { user_id } = event else {
+        return None;
+    };
+    if user_id == viewer_id {
+        return None;
+    }
+    let is_friend = match state.sapphire_client.get_friends(viewer_id).await {
+        Ok(friends) => friends.contains(&user_id),
+        Err(e) => {
+            warn!("⚠️ Not streaming update from {}: {}", user_id, e);
+            return None;
+        }
+    };
+    if !is_friend && !state.location_store.is_observer(&user_id, viewer_id).await {
+        return None;
+    }
+    if state
+        .location_store
+        .get_muted(viewer_id, now_secs())
+        .await
+        .contains(&user_id)
+    {
+        return None;
+    }
+
+    let friend = state.location_store.get_user(&user_id).await?;
+    let friend = filter_friend(state, friend, viewer_id).await;
+    // Nothing visible (paused, or no level set) means nothing to send
+    friend.location.as_ref()?;
+    Some(serde_json::json!({
+        "type": "location_updated",
+        "friend": friend
+    }))
+}
+
+/// Get specific friend's location (with privacy filtering)
+/// Users who aren't friends (or observed) get 403, and unknown users 404
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends/{friend_id}",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+        FieldsParams,
+    ),
+    responses(
+        (status = 200, body = ApiResponse<FriendLocation>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_friend_location(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+    ValidatedQuery(fields): ValidatedQuery<FieldsParams>,
+) -> impl IntoResponse {
+    info!("👤 Getting location for friend: {} (user: {})", friend_id, user_id);
+
+    let fields = match fields.selection(FriendLocation::FIELDS) {
+        Ok(fields) => fields,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::err(e))),
+    };
+
+    // Check if they are friends, or the friend granted the user observer access
+    let is_friend = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(friends) => friends.contains(&friend_id),
+        Err(e) => return friends_unavailable(e),
+    };
+    let is_observer = state.location_store.is_observer(&friend_id, &user_id).await;
+    if !is_friend && !is_observer {
+        return match user_exists(&state, &friend_id).await {
+            Ok(true) => (
+                StatusCode::FORBIDDEN,
+                Json(ApiResponse::err("Not friends".to_string())),
+            ),
+            Ok(false) => {
+                let e = StoreError::NotFound("User");
+                (e.status(), Json(ApiResponse::err(e.to_string())))
+            }
+            Err(e) => (e.status(), Json(ApiResponse::err(e.to_string()))),
+        };
+    }
+
+    // Get friend's location, or an empty placeholder if they have none yet
+    let friend = match state.location_store.get_user(&friend_id).await {
+        Some(friend) => filter_friend(&state, friend, &user_id).await,
+        None => FriendLocation::hidden(friend_id.clone()),
+    };
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(select_fields(&friend, fields.as_deref()))),
+    )
+}
+
+/// Estimated arrival of a friend at a destination
+/// Computed from the coordinates the viewer is allowed to see, so it is only
+/// as precise as the friend's sharing level
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FriendEta {
+    /// Straight-line distance to the destination; `None` if no coordinates are visible
+    #[serde(rename = "distanceKm")]
+    pub distance_km: Option<f64>,
+    /// Reported speed, or speed derived from the last two history points
+    #[serde(rename = "speedMps")]
+    pub speed_mps: Option<f64>,
+    /// `None` when the distance or a positive speed is unknown
+    #[serde(rename = "etaSecs")]
+    pub eta_secs: Option<i64>,
+}
+
+/// Estimate how long until a friend reaches a destination
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends/{friend_id}/eta",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+        EtaParams,
+    ),
+    responses(
+        (status = 200, body = ApiResponse<FriendEta>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_friend_eta(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+    ValidatedQuery(to): ValidatedQuery<EtaParams>,
+) -> impl IntoResponse {
+    info!("⏱️ Estimating ETA of {} for user: {}", friend_id, user_id);
+
+    let is_friend = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(friends) => friends.contains(&friend_id),
+        Err(e) => return friends_unavailable(e),
+    };
+    if !is_friend && !state.location_store.is_observer(&friend_id, &user_id).await {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::err("Not friends".to_string())),
+        );
+    }
+
+    // Only a realtime-fresh fix says where they are now
+    let visible = match state.location_store.get_user(&friend_id).await {
+        Some(friend)
+            if privacy::fresh_location(
+                &friend,
+                state.config.realtime_freshness_secs,
+                now_secs(),
+            )
+            .is_some() =>
+        {
+            filter_friend(&state, friend, &user_id).await.location
+        }
+        _ => None,
+    };
+    let distance_km = visible
+        .and_then(|loc| loc.latitude.zip(loc.longitude))
+        .map(|(lat, lng)| geo::haversine_km(lat, lng, to.to_lat, to.to_lng));
+    // Speed is only shown alongside visible coordinates
+    let speed_mps = match distance_km {
+        Some(_) => state.location_store.current_speed(&friend_id).await,
+        None => None,
+    };
+    let eta_secs = distance_km
+        .zip(speed_mps)
+        .and_then(|(distance, speed)| geo::eta_secs(distance, speed));
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(FriendEta {
+            distance_km,
+            speed_mps,
+            eta_secs,
+        })),
+    )
+}
+
+/// Export a friend's recent path as GeoJSON, for mapping libraries
+/// History points are filtered like the friend's current location: exact
+/// at Realtime, coarsened at City (with repeats of the same cell collapsed),
+/// and left out entirely at City Name or when nothing is shared. One
+/// `LineString` feature (a `Point` for a single position), or no features
+/// when no points qualify.
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends/{friend_id}/track.geojson",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+        SinceParams,
+    ),
+    responses(
+        (status = 200, description = "GeoJSON FeatureCollection", body = serde_json::Value, content_type = "application/geo+json"),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_friend_track(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+    ValidatedQuery(window): ValidatedQuery<SinceParams>,
+) -> Response {
+    info!("🗺️ Exporting track of {} for user: {}", friend_id, user_id);
+
+    let is_friend = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(friends) => friends.contains(&friend_id),
+        Err(e) => return friends_unavailable::<()>(e).into_response(),
+    };
+    if !is_friend && !state.location_store.is_observer(&friend_id, &user_id).await {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::<()>::err("Not friends".to_string())),
+        )
+            .into_response();
+    }
+
+    let level = match state.location_store.get_user(&friend_id).await {
+        Some(friend) => {
+            let overrides = state
+                .location_store
+                .sharing_overrides(&friend_id, &user_id, now_secs())
+                .await;
+            privacy::effective_level(&friend, &overrides)
+        }
+        None => None,
+    };
+    let mut positions: Vec<(f64, f64)> = Vec::new();
+    let mut timestamps: Vec<Option<i64>> = Vec::new();
+    if let Some(level) = &level {
+        let points = state
+            .location_store
+            .get_history_since(&friend_id, window.since.unwrap_or(i64::MIN))
+            .await;
+        for point in points {
+            let (Some(lat), Some(lng)) = privacy::filter_coordinates(
+                level,
+                point.latitude,
+                point.longitude,
+                state.config.privacy_rounding_meters,
+            ) else {
+                continue;
+            };
+            if positions.last() == Some(&(lng, lat)) {
+                continue;
+            }
+            positions.push((lng, lat));
+            timestamps.push(point.timestamp);
+        }
+    }
+
+    // GeoJSON positions are [longitude, latitude]
+    let coordinates: Vec<[f64; 2]> = positions.iter().map(|&(lng, lat)| [lng, lat]).collect();
+    let geometry = match coordinates.as_slice() {
+        [] => None,
+        [point] => Some(serde_json::json!({ "type": "Point", "coordinates": point })),
+        _ => Some(serde_json::json!({ "type": "LineString", "coordinates": coordinates })),
+    };
+    let features: Vec<serde_json::Value> = geometry
+        .map(|geometry| {
+            serde_json::json!({
+                "type": "Feature",
+                "geometry": geometry,
+                "properties": {
+                    "friendId": friend_id,
+                    "sharingLevel": level,
+                    "timestamps": timestamps,
+                },
+            })
+        })
+        .into_iter()
+        .collect();
+
+    (
+        [(header::CONTENT_TYPE, "application/geo+json")],
+        Json(serde_json::json!({
+            "type": "FeatureCollection",
+            "features": features,
+        })),
+    )
+        .into_response()
+}
+
+/// Default distance at which two friends count as having met
+const CONVERGE_THRESHOLD_METERS: f64 = 100.0;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ConvergeRequest {
+    #[serde(rename = "friendA")]
+    pub friend_a: String,
+    #[serde(rename = "friendB")]
+    pub friend_b: String,
+    /// How close counts as meeting; defaults to 100m
+    #[serde(rename = "thresholdMeters")]
+    pub threshold_meters: Option<f64>,
+}
+
+/// Whether two friends' current tracks bring them together
+/// Assumes both keep their current speed and heading; friends without a
+/// known speed or heading are treated as stationary
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Convergence {
+    /// Distance between them is shrinking
+    pub approaching: bool,
+    #[serde(rename = "distanceKm")]
+    pub distance_km: f64,
+    /// Rate the distance shrinks at; negative when moving apart
+    #[serde(rename = "closingSpeedMps")]
+    pub closing_speed_mps: f64,
+    /// Closest they get if neither changes course
+    #[serde(rename = "closestApproachKm")]
+    pub closest_approach_km: f64,
+    /// Rough time until they're within the threshold (0 if already);
+    /// `None` if their paths never get that close
+    #[serde(rename = "etaSecs")]
+    pub eta_secs: Option<i64>,
+}
+
+/// Current track of a friend whose realtime location the viewer may see
+async fn realtime_track(
+    state: &AppState,
+    friend_id: &str,
+    viewer_id: &str,
+    friends: &[String],
+) -> Result<geo::Track, StoreError> {
+    let store = &state.location_store;
+    if !friends.iter().any(|id| id == friend_id) && !store.is_observer(friend_id, viewer_id).await {
+        return Err(StoreError::Forbidden(format!(
+            "Not friends with {}",
+            friend_id
+        )));
+    }
+    let friend = store
+        .get_user(friend_id)
+        .await
+        .ok_or(StoreError::NotFound("User"))?;
+    let overrides = store
+        .sharing_overrides(friend_id, viewer_id, now_secs())
+        .await;
+    if privacy::effective_level(&friend, &overrides) != Some(SharingLevel::Realtime) {
+        return Err(StoreError::Forbidden(format!(
+            "{} isn't sharing a realtime location with you",
+            friend_id
+        )));
+    }
+    let location =
+        privacy::fresh_location(&friend, state.config.realtime_freshness_secs, now_secs())
+            .ok_or_else(|| StoreError::Invalid(format!("{} has no recent location", friend_id)))?;
+
+    let speed_mps = store.current_speed(friend_id).await.unwrap_or(0.0);
+    let heading = store.current_heading(friend_id).await;
+    Ok(geo::Track {
+        latitude: location.latitude,
+        longitude: location.longitude,
+        // Without a direction there is nothing to project
+        speed_mps: if heading.is_some() { speed_mps } else { 0.0 },
+        heading: heading.unwrap_or(0.0),
+    })
+}
+
+/// Estimate whether two friends are heading towards each other
+/// Both must be sharing their realtime location with the caller
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friends/converge",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = ConvergeRequest,
+    responses(
+        (status = 200, body = ApiResponse<Convergence>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_convergence(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ApiJson(payload): ApiJson<ConvergeRequest>,
+) -> ApiResult<Convergence> {
+    info!(
+        "🧭 Estimating convergence of {} and {} for user: {}",
+        payload.friend_a, payload.friend_b, user_id
+    );
+
+    if payload.friend_a == payload.friend_b {
+        return Err(StoreError::Invalid(
+            "Pick two different friends".to_string(),
+        ));
+    }
+    let threshold_meters = payload
+        .threshold_meters
+        .unwrap_or(CONVERGE_THRESHOLD_METERS);
+    if !threshold_meters.is_finite() || threshold_meters <= 0.0 {
+        return Err(StoreError::Invalid(
+            "thresholdMeters must be positive".to_string(),
+        ));
+    }
+    let friends = state
+        .sapphire_client
+        .get_friends(&user_id)
+        .await
+        .map_err(|e| StoreError::Unavailable(format!("Friend service unavailable: {}", e)))?;
+
+    let a = realtime_track(&state, &payload.friend_a, &user_id, &friends).await?;
+    let b = realtime_track(&state, &payload.friend_b, &user_id, &friends).await?;
+    let approach = geo::approach(&a, &b, threshold_meters / 1000.0);
+
+    Ok(ApiResponse::ok(Convergence {
+        approaching: approach.closing_speed_mps > 0.0,
+        distance_km: geo::haversine_km(a.latitude, a.longitude, b.latitude, b.longitude),
+        closing_speed_mps: approach.closing_speed_mps,
+        closest_approach_km: approach.closest_km,
+        eta_secs: approach.secs_to_within,
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FriendshipStatus {
+    pub friends: bool,
+    #[serde(rename = "pendingRequest")]
+    pub pending_request: Option<RequestDirection>,
+}
+
+/// Check whether two users are friends, or have a pending request between them
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends/{friend_id}/status",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Other user's ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<FriendshipStatus>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_friendship_status(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    info!("🤝 Checking friendship: {} <-> {}", user_id, friend_id);
+
+    let friends = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(friends) => friends.contains(&friend_id),
+        Err(e) => return friends_unavailable(e),
+    };
+    let pending_request = state
+        .location_store
+        .pending_request_direction(&user_id, &friend_id)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(FriendshipStatus {
+            friends,
+            pending_request,
+        })),
+    )
+}
+
+/// List users who have the user as a friend while the user doesn't have them
+/// (a one-sided friendship left behind by a partial failure)
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends/asymmetric",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses(
+        (status = 200, body = ApiResponse<Vec<String>>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_asymmetric_friends(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    info!("🔍 Checking asymmetric friendships for user: {}", user_id);
+
+    let friends = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(f) => f,
+        Err(e) => return friends_unavailable(e),
+    };
+    let friended_by = match state.sapphire_client.get_friended_by(&user_id).await {
+        Ok(f) => f,
+        Err(e) => return friends_unavailable(e),
+    };
+
+    let asymmetric: Vec<String> = friended_by
+        .into_iter()
+        .filter(|other| !friends.contains(other))
+        .collect();
+
+    (StatusCode::OK, Json(ApiResponse::ok(asymmetric)))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FriendVisibility {
+    #[serde(rename = "friendId")]
+    pub friend_id: String,
+    /// Level the friend effectively shares with the user right now, after
+    /// pauses, SOS, temporary shares, schedules and groups
+    #[serde(rename = "sharingLevel")]
+    pub sharing_level: Option<SharingLevel>,
+    /// Whether the user gets coordinates for the friend: a fresh fix, shown
+    /// at Realtime or City precision
+    #[serde(rename = "canSeeLocation")]
+    pub can_see_location: bool,
+}
+
+/// Get the sharing level each friend effectively shares with the user
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friends/visibility",
+    tag = "friends",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses(
+        (status = 200, body = ApiResponse<Vec<FriendVisibility>>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_friends_visibility(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    info!("👁️ Getting friends' visibility for user: {}", user_id);
+
+    let friends = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(f) => f,
+        Err(e) => return friends_unavailable(e),
+    };
+
+    let now = now_secs();
+    let mut visibility = Vec::new();
+    for friend_id in friends {
+        let friend = state.location_store.get_user(&friend_id).await;
+        let overrides = state
+            .location_store
+            .sharing_overrides(&friend_id, &user_id, now)
+            .await;
+        let sharing_level = friend
+            .as_ref()
+            .and_then(|f| privacy::effective_level(f, &overrides));
+        let can_see_location = friend.as_ref().is_some_and(|f| {
+            privacy::shows_coordinates(
+                f,
+                &overrides,
+                state.config.privacy_decay.as_ref(),
+                state.config.location_ttl_secs,
+                now,
+            )
+        });
+
+        visibility.push(FriendVisibility {
+            friend_id,
+            sharing_level,
+            can_see_location,
+        });
+    }
+
+    (StatusCode::OK, Json(ApiResponse::ok(visibility)))
+}
+
+/// Raise an SOS: friends see the user's exact location until it expires or is cleared
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/sos",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn raise_sos(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    warn!("🆘 SOS raised by user: {}", user_id);
+
+    let friends = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(f) => f,
+        Err(e) => return friends_unavailable(e),
+    };
+
+    let until = now_secs() + state.config.sos_duration_secs;
+    state.location_store.set_sos(&user_id, until).await;
+
+    let location = state
+        .location_store
+        .get_user(&user_id)
+        .await
+        .and_then(|u| u.location);
+    state.events.publish(Event::Sos {
+        user_id,
+        active: true,
+        location,
+        recipients: friends,
+    });
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "sos": true,
+            "until": until
+        }))),
+    )
+}
+
+/// Clear an active SOS, restoring the user's normal sharing
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/sos/clear",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn clear_sos(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    info!("✅ SOS cleared by user: {}", user_id);
+
+    let friends = match state.sapphire_client.get_friends(&user_id).await {
+        Ok(f) => f,
+        Err(e) => return friends_unavailable(e),
+    };
+
+    let was_active = state.location_store.clear_sos(&user_id, now_secs()).await;
+    if was_active {
+        state.events.publish(Event::Sos {
+            user_id,
+            active: false,
+            location: None,
+            recipients: friends,
+        });
+    }
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "sos": false,
+            "cleared": was_active
+        }))),
+    )
+}
+
+/// Share a level with one friend until an expiry time, then fall back to the normal level
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friends/{friend_id}/share-until",
+    tag = "sharing",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+    ),
+    request_body = ShareUntilRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn share_until(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+    ApiJson(payload): ApiJson<ShareUntilRequest>,
+) -> impl IntoResponse {
+    info!(
+        "⏳ User {} sharing {:?} with {} until {}",
+        user_id, payload.level, friend_id, payload.until
+    );
+
+    if payload.until <= now_secs() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::err("Expiry must be in the future".to_string())),
+        );
+    }
+
+    match state.sapphire_client.get_friends(&user_id).await {
+        Ok(friends) if friends.contains(&friend_id) => {}
+        Ok(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::err("Not friends".to_string())),
+            )
+        }
+        Err(e) => return friends_unavailable(e),
+    }
+
+    state
+        .location_store
+        .set_temporary_share(&user_id, &friend_id, payload.level, payload.until)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "shared": true,
+            "until": payload.until
+        }))),
+    )
+}
+
+// ============================================================================
+// Favorite Handlers
+// ============================================================================
+
+/// Unpin two former friends from each other's favorites
+async fn unfavorite_each_other(state: &AppState, user_a: &str, user_b: &str) {
+    state
+        .location_store
+        .set_favorite(user_a, user_b, false)
+        .await;
+    state
+        .location_store
+        .set_favorite(user_b, user_a, false)
+        .await;
+}
+
+/// Pin a friend as a favorite, listing them first
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friends/{friend_id}/favorite",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn add_favorite(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+) -> ApiResult<serde_json::Value> {
+    info!("⭐ User {} pinning {} as a favorite", user_id, friend_id);
+
+    if !are_friends(&state, &user_id, &friend_id).await? {
+        return Err(StoreError::Invalid("Not friends".to_string()));
+    }
+    state
+        .location_store
+        .set_favorite(&user_id, &friend_id, true)
+        .await;
+    Ok(ApiResponse::ok(serde_json::json!({ "favorite": true })))
+}
+
+/// Unpin a favorite friend
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/friends/{friend_id}/favorite",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+    ),
+    responses((status = 200, body = ApiResponse<serde_json::Value>)),
+)]
+async fn remove_favorite(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+) -> ApiResult<serde_json::Value> {
+    info!("⭐ User {} unpinning favorite {}", user_id, friend_id);
+
+    let removed = state
+        .location_store
+        .set_favorite(&user_id, &friend_id, false)
+        .await;
+    Ok(ApiResponse::ok(serde_json::json!({
+        "favorite": false,
+        "removed": removed
+    })))
+}
+
+// ============================================================================
+// Mute Handlers
+// ============================================================================
+
+/// Mute a friend: their pin leaves your locations list and stream until
+/// `until` (or until you unmute them). The friendship and what they see of
+/// you are unaffected.
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friends/{friend_id}/mute",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+    ),
+    request_body = MuteFriendRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn mute_friend(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+    ApiJson(payload): ApiJson<MuteFriendRequest>,
+) -> ApiResult<serde_json::Value> {
+    info!(
+        "🔇 User {} muting {} until {:?}",
+        user_id, friend_id, payload.until
+    );
+
+    if payload.until.is_some_and(|until| until <= now_secs()) {
+        return Err(StoreError::Invalid(
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+    if !are_friends(&state, &user_id, &friend_id).await? {
+        return Err(StoreError::Invalid("Not friends".to_string()));
+    }
+    state
+        .location_store
+        .set_mute(&user_id, &friend_id, payload.until)
+        .await;
+    Ok(ApiResponse::ok(serde_json::json!({
+        "muted": true,
+        "until": payload.until
+    })))
+}
+
+/// Unmute a friend
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/friends/{friend_id}/mute",
+    tag = "friends",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+    ),
+    responses((status = 200, body = ApiResponse<serde_json::Value>)),
+)]
+async fn unmute_friend(
+    State(state): State<AppState>,
+    Path((user_id, friend_id)): Path<(String, String)>,
+) -> ApiResult<serde_json::Value> {
+    info!("🔊 User {} unmuting {}", user_id, friend_id);
+
+    let removed = state
+        .location_store
+        .clear_mute(&user_id, &friend_id, now_secs())
+        .await;
+    Ok(ApiResponse::ok(serde_json::json!({
+        "muted": false,
+        "removed": removed
+    })))
+}
+
+// ============================================================================
+// Sharing Group Handlers
+// ============================================================================
+
+/// Longest group name accepted, in characters
+const MAX_GROUP_NAME_CHARS: usize = 40;
+
+/// Clean up a group name, rejecting blank ones
+fn group_name(name: &str) -> Result<String, StoreError> {
+    sanitize_text(name, MAX_GROUP_NAME_CHARS, "Group name")
+        .map_err(StoreError::Invalid)?
+        .ok_or_else(|| StoreError::Invalid("Group name must not be empty".to_string()))
+}
+
+/// Tell the given group members to refetch the owner's location
+fn notify_group_members(state: &AppState, user_id: &str, members: Vec<String>) {
+    if !members.is_empty() {
+        state.events.publish(Event::VisibilityChanged {
+            user_id: user_id.to_string(),
+            recipients: members,
+        });
+    }
+}
+
+/// List the user's sharing groups
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/groups",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses((status = 200, body = ApiResponse<Vec<SharingGroup>>)),
+)]
+async fn get_groups(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> ApiResult<Vec<SharingGroup>> {
+    info!("👪 Listing sharing groups for user: {}", user_id);
+    Ok(ApiResponse::ok(
+        state.location_store.get_groups(&user_id).await,
+    ))
+}
+
+/// Create a sharing group
+/// Friends put in the group see the user at the group's level instead of the
+/// global one; a temporary share, pause or SOS still takes precedence
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/groups",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = CreateGroupRequest,
+    responses(
+        (status = 200, body = ApiResponse<SharingGroup>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn create_group(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ApiJson(payload): ApiJson<CreateGroupRequest>,
+) -> ApiResult<SharingGroup> {
+    info!("👪 User {} creating sharing group", user_id);
+
+    let name = group_name(&payload.name)?;
+    let group = state
+        .location_store
+        .create_group(&user_id, name, payload.level)
+        .await?;
+    Ok(ApiResponse::ok(group))
+}
+
+/// Rename a sharing group or change its level
+#[utoipa::path(
+    put,
+    path = "/users/{user_id}/groups/{group_id}",
+    tag = "sharing",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("group_id" = String, Path, description = "Group ID"),
+    ),
+    request_body = UpdateGroupRequest,
+    responses(
+        (status = 200, body = ApiResponse<SharingGroup>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn update_group(
+    State(state): State<AppState>,
+    Path((user_id, group_id)): Path<(String, String)>,
+    ApiJson(payload): ApiJson<UpdateGroupRequest>,
+) -> ApiResult<SharingGroup> {
+    info!("👪 User {} updating sharing group {}", user_id, group_id);
+
+    let name = payload.name.as_deref().map(group_name).transpose()?;
+    let level_changed = payload.level.is_some();
+    let group = state
+        .location_store
+        .update_group(&user_id, &group_id, name, payload.level)
+        .await?;
+    if level_changed {
+        notify_group_members(&state, &user_id, group.members.clone());
+    }
+    Ok(ApiResponse::ok(group))
+}
+
+/// Delete a sharing group; its members go back to the user's global level
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/groups/{group_id}",
+    tag = "sharing",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("group_id" = String, Path, description = "Group ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn delete_group(
+    State(state): State<AppState>,
+    Path((user_id, group_id)): Path<(String, String)>,
+) -> ApiResult<serde_json::Value> {
+    info!("👪 User {} deleting sharing group {}", user_id, group_id);
+
+    let group = state
+        .location_store
+        .delete_group(&user_id, &group_id)
+        .await?;
+    notify_group_members(&state, &user_id, group.members);
+    Ok(ApiResponse::ok(serde_json::json!({ "deleted": true })))
+}
+
+/// Put a friend in a sharing group
+/// A friend is in at most one of the user's groups, so this moves them out
+/// of any other
+#[utoipa::path(
+    put,
+    path = "/users/{user_id}/groups/{group_id}/members/{friend_id}",
+    tag = "sharing",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("group_id" = String, Path, description = "Group ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<SharingGroup>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn add_group_member(
+    State(state): State<AppState>,
+    Path((user_id, group_id, friend_id)): Path<(String, String, String)>,
+) -> ApiResult<SharingGroup> {
+    info!(
+        "👪 User {} adding {} to sharing group {}",
+        user_id, friend_id, group_id
+    );
+
+    if !are_friends(&state, &user_id, &friend_id).await? {
+        return Err(StoreError::Invalid("Not friends".to_string()));
+    }
+    let group = state
+        .location_store
+        .add_group_member(&user_id, &group_id, &friend_id)
+        .await?;
+    notify_group_members(&state, &user_id, vec![friend_id]);
+    Ok(ApiResponse::ok(group))
+}
+
+/// Take a friend out of a sharing group
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/groups/{group_id}/members/{friend_id}",
+    tag = "sharing",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("group_id" = String, Path, description = "Group ID"),
+        ("friend_id" = String, Path, description = "Friend's user ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn remove_group_member(
+    State(state): State<AppState>,
+    Path((user_id, group_id, friend_id)): Path<(String, String, String)>,
+) -> ApiResult<serde_json::Value> {
+    info!(
+        "👪 User {} removing {} from sharing group {}",
+        user_id, friend_id, group_id
+    );
+
+    let removed = state
+        .location_store
+        .remove_group_member(&user_id, &group_id, &friend_id)
+        .await?;
+    if removed {
+        notify_group_members(&state, &user_id, vec![friend_id]);
+    }
+    Ok(ApiResponse::ok(serde_json::json!({ "removed": removed })))
+}
+
+// ============================================================================
+// Sharing Schedule Handlers
+// ============================================================================
+
+/// Most scheduled sharing rules a user may have
+const MAX_SCHEDULE_RULES: usize = 50;
+
+/// Get the user's scheduled sharing rules
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/schedule",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses((status = 200, body = ApiResponse<SharingSchedule>)),
+)]
+async fn get_schedule(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> ApiResult<SharingSchedule> {
+    info!("🗓️ Getting sharing schedule for user: {}", user_id);
+    Ok(ApiResponse::ok(SharingSchedule {
+        rules: state.location_store.get_schedule(&user_id).await,
+    }))
+}
+
+/// Replace the user's scheduled sharing rules
+/// Times are in the user's time zone (UTC if unset). While a rule covers a
+/// friend it overrides their group and the global level; a temporary share,
+/// pause or SOS still takes precedence. An empty list clears the schedule.
+#[utoipa::path(
+    put,
+    path = "/users/{user_id}/schedule",
+    tag = "sharing",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = SharingSchedule,
+    responses(
+        (status = 200, body = ApiResponse<SharingSchedule>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn set_schedule(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ApiJson(payload): ApiJson<SharingSchedule>,
+) -> ApiResult<SharingSchedule> {
+    info!(
+        "🗓️ User {} setting {} scheduled sharing rules",
+        user_id,
+        payload.rules.len()
+    );
+
+    if payload.rules.len() > MAX_SCHEDULE_RULES {
+        return Err(StoreError::Invalid(format!(
+            "At most {} rules are allowed",
+            MAX_SCHEDULE_RULES
+        )));
+    }
+    let rules = state
+        .location_store
+        .set_schedule(&user_id, payload.rules)
+        .await?;
+
+    // The schedule may change what friends see right away
+    match state.sapphire_client.get_friends(&user_id).await {
+        Ok(friends) => notify_group_members(&state, &user_id, friends),
+        Err(e) => warn!("⚠️ Not notifying friends of schedule change: {}", e),
+    }
+    Ok(ApiResponse::ok(SharingSchedule { rules }))
+}
+
+// ============================================================================
+// Observer Handlers
+// ============================================================================
+
+/// Grant an observer one-directional view access to the user's location
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/observers/{observer_id}",
+    tag = "observers",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("observer_id" = String, Path, description = "Observer's user ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn add_observer(
+    State(state): State<AppState>,
+    Path((user_id, observer_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    info!(
+        "🔭 User {} granting observer access to {}",
+        user_id, observer_id
+    );
+
+    if user_id == observer_id {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::err("Cannot observe yourself".to_string())),
+        );
+    }
+
+    state
+        .location_store
+        .add_observer(&user_id, &observer_id)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "added": true
+        }))),
+    )
+}
+
+/// Revoke an observer's view access
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/observers/{observer_id}",
+    tag = "observers",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("observer_id" = String, Path, description = "Observer's user ID"),
+    ),
+    responses((status = 200, body = ApiResponse<serde_json::Value>)),
+)]
+async fn remove_observer(
+    State(state): State<AppState>,
+    Path((user_id, observer_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    info!(
+        "🔭 User {} revoking observer access from {}",
+        user_id, observer_id
+    );
+
+    let removed = state
+        .location_store
+        .remove_observer(&user_id, &observer_id)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "removed": removed
+        }))),
+    )
+}
+
+/// List who can observe the user's location
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/observers",
+    tag = "observers",
+    params(("user_id" = String, Path, description = "User ID")),
+    responses((status = 200, body = ApiResponse<Vec<String>>)),
+)]
+async fn get_observers(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+) -> impl IntoResponse {
+    info!("🔭 Getting observers for user: {}", user_id);
+
+    let observers = state.location_store.get_observers(&user_id).await;
+    (StatusCode::OK, Json(ApiResponse::ok(observers)))
+}
+
+// ============================================================================
+// Friend Request Handlers
+// ============================================================================
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SendFriendRequestRequest {
+    #[serde(rename = "senderId")]
+    pub sender_id: String,
+    #[serde(rename = "receiverId")]
+    pub receiver_id: String,
+    /// Optional note to the receiver (max 280 characters)
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Longest friend request note accepted, in characters
+const MAX_REQUEST_MESSAGE_CHARS: usize = 280;
+
+/// Strip control characters from a friend request note and enforce the length limit
+/// Blank notes are dropped
+fn sanitize_request_message(message: &str) -> Result<Option<String>, String> {
+    sanitize_text(message, MAX_REQUEST_MESSAGE_CHARS, "Message")
+}
+
+/// Strip control characters and surrounding whitespace from user-supplied
+/// text, rejecting it if longer than `max_chars`
+/// Blank text becomes `None`
+fn sanitize_text(text: &str, max_chars: usize, what: &str) -> Result<Option<String>, String> {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    let text = text.trim();
+    if text.chars().count() > max_chars {
+        return Err(format!("{} too long (max {} characters)", what, max_chars));
+    }
+    Ok((!text.is_empty()).then(|| text.to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RespondFriendRequestRequest {
+    #[serde(rename = "requestId")]
+    pub request_id: String,
+}
+
+/// Result of sending a friend request
+/// Users who are already friends get no new request, rather than an error
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SentFriendRequest {
+    #[serde(flatten)]
+    pub request: Option<FriendRequest>,
+    #[serde(rename = "alreadyFriends")]
+    pub already_friends: bool,
+}
+
+/// Check on Sapphire whether two users are already friends
+async fn are_friends(state: &AppState, user_id: &str, other_id: &str) -> Result<bool, StoreError> {
+    state
+        .sapphire_client
+        .get_friends(user_id)
+        .await
+        .map(|friends| friends.iter().any(|f| f == other_id))
+        .map_err(|e| StoreError::Unavailable(format!("Friend service unavailable: {}", e)))
+}
+
+/// Whether a user exists as far as this server can tell: it has seen them,
+/// or they have friends on Sapphire
+async fn user_exists(state: &AppState, user_id: &str) -> Result<bool, StoreError> {
+    if state.location_store.is_known_user(user_id).await {
+        return Ok(true);
+    }
+    let friend_count = state
+        .sapphire_client
+        .count_friends(user_id)
+        .await
+        .map_err(|e| StoreError::Unavailable(format!("Friend service unavailable: {}", e)))?;
+    Ok(friend_count > 0)
+}
+
+/// Send friend request
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friend-requests",
+    tag = "friend-requests",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = SendFriendRequestRequest,
+    responses(
+        (status = 200, body = ApiResponse<SentFriendRequest>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn send_friend_request(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<SendFriendRequestRequest>,
+) -> ApiResult<SentFriendRequest> {
+    info!(
+        "📨 Sending friend request from {} to {}",
+        payload.sender_id, payload.receiver_id
+    );
+
+    let message = payload
+        .message
+        .as_deref()
+        .map(sanitize_request_message)
+        .transpose()
+        .map_err(StoreError::Invalid)?
+        .flatten();
+
+    if are_friends(&state, &payload.sender_id, &payload.receiver_id).await? {
+        info!(
+            "🤝 {} and {} are already friends",
+            payload.sender_id, payload.receiver_id
+        );
+        return Ok(ApiResponse::ok(SentFriendRequest {
+            request: None,
+            already_friends: true,
+        }));
+    }
+
+    // Lenient by default, since some clients register users elsewhere first
+    if state.config.strict_friend_requests && !user_exists(&state, &payload.receiver_id).await? {
+        return Err(StoreError::NotFound("User"));
+    }
+
+    let request = state
+        .location_store
+        .send_friend_request(
+            &payload.sender_id,
+            &payload.receiver_id,
+            message,
+            &state.config.request_limits,
+        )
+        .await?;
+
+    if state.config.friend_request_digest_secs.is_some() {
+        state
+            .request_digest
+            .record(&request.receiver_id, &request.sender_id);
+    } else {
+        state.events.publish(Event::FriendRequestReceived {
+            request_id: request.id.clone(),
+            sender_id: request.sender_id.clone(),
+            recipients: vec![request.receiver_id.clone()],
+        });
+    }
+
+    Ok(ApiResponse::ok(SentFriendRequest {
+        request: Some(request),
+        already_friends: false,
+    }))
+}
+
+/// Get pending friend requests for a user
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friend-requests",
+    tag = "friend-requests",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        PaginationParams,
+        SortParams,
+    ),
+    responses(
+        (status = 200, body = ApiResponse<Vec<FriendRequest>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_friend_requests(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ValidatedQuery(page): ValidatedQuery<PaginationParams>,
+    ValidatedQuery(order): ValidatedQuery<SortParams>,
+) -> impl IntoResponse {
+    info!("📬 Getting friend requests for user: {}", user_id);
+
+    let mut requests = state.location_store.get_friend_requests(&user_id).await;
+    if order.sort.unwrap_or_default() == SortOrder::Oldest {
+        requests.reverse();
+    }
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(page.paginate(requests))),
+    )
+}
+
+/// Clear out the user's received friend requests
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/friend-requests",
+    tag = "friend-requests",
+    params(("user_id" = String, Path, description = "User ID"), AgeParams),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn clear_friend_requests(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ValidatedQuery(age): ValidatedQuery<AgeParams>,
+) -> impl IntoResponse {
+    info!("🧹 Clearing friend requests for user: {}", user_id);
+
+    // Only requests the user received: sent ones are the receiver's to manage
+    let cutoff = age.older_than_secs.map(|secs| now_secs() - secs);
+    let removed = state
+        .location_store
+        .clear_received_requests(&user_id, cutoff)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "removed": removed
+        }))),
+    )
+}
+
+/// Public profile of the user who accepted a friend request
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AccepterProfile {
+    pub id: String,
+    #[serde(rename = "userName")]
+    pub user_name: Option<String>,
+}
+
+/// The accepted request, plus who accepted it so the sender can show them right away
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AcceptedFriendRequest {
+    #[serde(flatten)]
+    pub request: FriendRequest,
+    pub accepter: AccepterProfile,
+}
+
+/// Accept friend request
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friend-requests/{request_id}/accept",
+    tag = "friend-requests",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("request_id" = String, Path, description = "Friend request ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<AcceptedFriendRequest>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn accept_friend_request(
+    State(state): State<AppState>,
+    Path((user_id, request_id)): Path<(String, String)>,
+) -> ApiResult<AcceptedFriendRequest> {
+    info!(
+        "✅ User {} accepting friend request: {}",
+        user_id, request_id
+    );
+
+    let accepted = accept_request(&state, &user_id, &request_id).await?;
+    Ok(ApiResponse::ok(accepted))
+}
+
+/// Accept a request addressed to `user_id` and record the friendship on Sapphire
+async fn accept_request(
+    state: &AppState,
+    user_id: &str,
+    request_id: &str,
+) -> Result<AcceptedFriendRequest, StoreError> {
+    let (request, newly_accepted) = state
+        .location_store
+        .accept_friend_request(request_id, user_id)
+        .await?;
+
+    // Record the friendship on Sapphire (`add_friend` is bidirectional),
+    // unless it already exists there. Accepting again never re-adds it.
+    let friends = if newly_accepted {
+        are_friends(state, &request.receiver_id, &request.sender_id).await
+    } else {
+        Ok(true)
+    };
+    match friends {
+        Ok(true) => {}
+        Ok(false) => {
+            if let Err(e) = state
+                .sapphire_client
+                .add_friend(&request.sender_id, &request.receiver_id, Some(&request.id))
+                .await
+            {
+                warn!("⚠️ Failed to record friendship on Sapphire: {}", e);
+            }
+        }
+        Err(e) => warn!("⚠️ Not recording friendship: {}", e),
+    }
+
+    let accepter = state.location_store.get_user(&request.receiver_id).await;
+    let accepter = AccepterProfile {
+        id: request.receiver_id.clone(),
+        user_name: accepter.and_then(|u| u.user_name),
+    };
+
+    Ok(AcceptedFriendRequest { request, accepter })
+}
+
+/// Decline friend request
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friend-requests/{request_id}/decline",
+    tag = "friend-requests",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("request_id" = String, Path, description = "Friend request ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 404, body = ApiResponse<serde_json::Value>),
+        (status = 409, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn decline_friend_request(
+    State(state): State<AppState>,
+    Path((user_id, request_id)): Path<(String, String)>,
+) -> ApiResult<serde_json::Value> {
+    info!(
+        "❌ User {} declining friend request: {}",
+        user_id, request_id
+    );
+
+    state
+        .location_store
+        .decline_friend_request(&request_id, &user_id)
+        .await?;
+
+    Ok(ApiResponse::ok(serde_json::json!({"declined": true})))
+}
+
+/// A pending request between the user and someone else
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PendingRequestWith {
+    pub request: FriendRequest,
+    /// `outgoing` if the user sent it, `incoming` if they received it
+    pub direction: RequestDirection,
+}
+
+/// Get the pending friend request between the user and another user, if any
+/// Either direction counts; `null` when there is none
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/friend-requests/with/{other_id}",
+    tag = "friend-requests",
+    params(
+        ("user_id" = String, Path, description = "User ID"),
+        ("other_id" = String, Path, description = "Other user's ID"),
+    ),
+    responses((status = 200, body = ApiResponse<Option<PendingRequestWith>>)),
+)]
+async fn get_request_with(
+    State(state): State<AppState>,
+    Path((user_id, other_id)): Path<(String, String)>,
+) -> ApiResult<Option<PendingRequestWith>> {
+    info!(
+        "🔎 Looking up pending request between {} and {}",
+        user_id, other_id
+    );
+
+    // Same lookup that blocks duplicate requests; it prefers a pending one
+    let pending = state
+        .location_store
+        .request_between(&user_id, &other_id)
+        .await
+        .filter(|request| request.status == FriendRequestStatus::Pending)
+        .map(|request| PendingRequestWith {
+            direction: if request.sender_id == user_id {
+                RequestDirection::Outgoing
+            } else {
+                RequestDirection::Incoming
+            },
+            request,
+        });
+
+    Ok(ApiResponse::ok(pending))
+}
+
+/// Largest number of requests answered in one bulk call
+const MAX_BULK_RESPONSES: usize = 100;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestAction {
+    Accept,
+    Decline,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BulkResponseItem {
+    pub request_id: String,
+    pub action: RequestAction,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RespondBulkRequest {
+    pub responses: Vec<BulkResponseItem>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BulkResponseResult {
+    #[serde(rename = "requestId")]
+    pub request_id: String,
+    pub action: RequestAction,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// Accept or decline several friend requests at once
+/// Each item succeeds or fails on its own; one bad ID doesn't stop the rest
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/friend-requests/respond-bulk",
+    tag = "friend-requests",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = RespondBulkRequest,
+    responses(
+        (status = 200, body = ApiResponse<Vec<BulkResponseResult>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn respond_bulk(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ApiJson(payload): ApiJson<RespondBulkRequest>,
+) -> impl IntoResponse {
+    info!(
+        "📮 User {} responding to {} friend requests",
+        user_id,
+        payload.responses.len()
+    );
+
+    if payload.responses.len() > MAX_BULK_RESPONSES {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::err(format!(
+                "Too many responses (max {})",
+                MAX_BULK_RESPONSES
+            ))),
+        );
+    }
+
+    let mut results = Vec::new();
+    for item in payload.responses {
+        let outcome = match item.action {
+            RequestAction::Accept => accept_request(&state, &user_id, &item.request_id)
+                .await
+                .map(|_| ()),
+            RequestAction::Decline => {
+                state
+                    .location_store
+                    .decline_friend_request(&item.request_id, &user_id)
+                    .await
+            }
+        };
+
+        results.push(BulkResponseResult {
+            request_id: item.request_id,
+            action: item.action,
+            success: outcome.is_ok(),
+            error: outcome.err().map(|e| e.to_string()),
+        });
+    }
+
+    (StatusCode::OK, Json(ApiResponse::ok(results)))
+}
+
+// ============================================================================
+// Discovery Handlers
+// ============================================================================
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DiscoveryHashesRequest {
+    pub hashes: Vec<String>,
+}
+
+/// Normalize a hex-encoded SHA-256 hash, rejecting anything else so raw
+/// phone numbers or emails can't be stored by mistake
+fn normalize_discovery_hash(hash: &str) -> Result<String, String> {
+    let hash = hash.trim().to_lowercase();
+    if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(hash)
+    } else {
+        Err("Discovery hashes must be hex-encoded SHA-256 digests".to_string())
+    }
+}
+
+/// Register salted contact hashes for friend discovery
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/discovery-hashes",
+    tag = "discovery",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = DiscoveryHashesRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn register_discovery_hashes(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ApiJson(payload): ApiJson<DiscoveryHashesRequest>,
+) -> impl IntoResponse {
+    info!("🔎 Registering discovery hashes for user: {}", user_id);
+
+    let hashes = match payload
+        .hashes
+        .iter()
+        .map(|h| normalize_discovery_hash(h))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(hashes) => hashes,
+        Err(e) => return (StatusCode::BAD_REQUEST, Json(ApiResponse::err(e))),
+    };
+
+    let registered = state
+        .location_store
+        .register_discovery_hashes(&user_id, hashes)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "registered": registered
+        }))),
+    )
+}
+
+/// Match contact hashes against registered users
+#[utoipa::path(
+    post,
+    path = "/discovery/match",
+    tag = "discovery",
+    request_body = DiscoveryHashesRequest,
+    responses((status = 200, body = ApiResponse<Vec<String>>)),
+)]
+async fn match_discovery_hashes(
+    State(state): State<AppState>,
+    ApiJson(payload): ApiJson<DiscoveryHashesRequest>,
+) -> impl IntoResponse {
+    info!("🔎 Matching {} discovery hashes", payload.hashes.len());
+
+    // Malformed hashes can never match, so skip them instead of failing
+    let hashes: Vec<String> = payload
+        .hashes
+        .iter()
+        .filter_map(|h| normalize_discovery_hash(h).ok())
+        .collect();
+
+    let matches = state.location_store.match_discovery_hashes(&hashes).await;
+    (StatusCode::OK, Json(ApiResponse::ok(matches)))
+}
+
+// ============================================================================
+// Admin Handlers
+// ============================================================================
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MergeUsersRequest {
+    pub from_id: String,
+    pub into_id: String,
+}
+
+/// Opt in to or out of showing up on nearby strangers' radar
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/discoverable",
+    tag = "discovery",
+    params(("user_id" = String, Path, description = "User ID")),
+    request_body = SetDiscoverableRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn set_discoverable(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ApiJson(payload): ApiJson<SetDiscoverableRequest>,
+) -> impl IntoResponse {
+    info!("📡 Discoverable {} for user: {}", payload.enabled, user_id);
+
+    state
+        .location_store
+        .set_discoverable(&user_id, payload.enabled)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "discoverable": payload.enabled
+        }))),
+    )
+}
+
+/// List discoverable strangers near the user
+///
+/// Only users who opted in appear, and only to users who opted in too.
+/// Distances are measured between City-rounded points and never returned,
+/// so the radar can't be used to pin anyone down. Friends, paused users and
+/// fixes older than the realtime freshness window are left out.
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/radar",
+    tag = "discovery",
+    params(("user_id" = String, Path, description = "User ID"), GeoParams),
+    responses(
+        (status = 200, body = ApiResponse<Vec<RadarEntry>>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 403, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_radar(
+    State(state): State<AppState>,
+    Path(user_id): Path<String>,
+    ValidatedQuery(geo): ValidatedQuery<GeoParams>,
+) -> ApiResult<Vec<RadarEntry>> {
+    info!("📡 Radar for user: {}", user_id);
+
+    let store = &state.location_store;
+    if !store.is_discoverable(&user_id).await {
+        return Err(StoreError::Forbidden(
+            "Turn on discoverable to use the radar".to_string(),
+        ));
+    }
+    let now = now_secs();
+    let freshness = state.config.realtime_freshness_secs;
+    let own_location = store
+        .get_user(&user_id)
+        .await
+        .and_then(|user| privacy::fresh_location(&user, freshness, now))
+        .ok_or_else(|| {
+            StoreError::Invalid("Share a current location to use the radar".to_string())
+        })?;
+    let friends = state
+        .sapphire_client
+        .get_friends(&user_id)
+        .await
+        .map_err(|e| StoreError::Unavailable(format!("Friend service unavailable: {}", e)))?;
+
+    let rounding = state.config.privacy_rounding_meters;
+    let (own_lat, own_lng) =
+        privacy::round_to_city(own_location.latitude, own_location.longitude, rounding);
+    let radius_km = geo.radius_km.unwrap_or(state.config.nearby_radius_km);
+
+    // Candidates are looked up by their exact positions, which rounding can
+    // move by up to one City cell diagonal for each side
+    let rounding_slack_km = privacy::precision_meters(&SharingLevel::City, rounding)
+        * std::f64::consts::SQRT_2
+        / 1000.0;
+    let mut radar: Vec<RadarEntry> = store
+        .get_discoverable_users_near(
+            own_location.latitude,
+            own_location.longitude,
+            radius_km + rounding_slack_km,
+        )
+        .await
+        .into_iter()
+        .filter(|other| other.id != user_id && !friends.contains(&other.id))
+        .filter_map(|other| {
+            let location = privacy::fresh_location(&other, freshness, now)?;
+            let (lat, lng) =
+                privacy::round_to_city(location.latitude, location.longitude, rounding);
+            (geo::haversine_km(own_lat, own_lng, lat, lng) <= radius_km).then_some(RadarEntry {
+                id: other.id,
+                user_name: other.user_name,
+                city: location.city,
+            })
+        })
+        .collect();
+    radar.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Ok(ApiResponse::ok(radar))
+}
+
+/// Merge one account into another (e.g. after re-onboarding with a new ID)
+#[utoipa::path(
+    post,
+    path = "/users/merge",
+    tag = "admin",
+    request_body = MergeUsersRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 400, body = ApiResponse<serde_json::Value>),
+        (status = 401, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn merge_users(
+    State(state): State<AppState>,
+    _admin: AdminAuth,
+    ApiJson(payload): ApiJson<MergeUsersRequest>,
+) -> impl IntoResponse {
+    info!(
+        "🔀 Merging user {} into {}",
+        payload.from_id, payload.into_id
+    );
+
+    if payload.from_id == payload.into_id {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::err(
+                "Cannot merge a user into itself".to_string(),
+            )),
+        );
+    }
+
+    if let Err(e) = state
+        .sapphire_client
+        .merge_friends(&payload.from_id, &payload.into_id)
+        .await
+    {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiResponse::err(format!("Failed to merge friends: {}", e))),
+        );
+    }
+
+    state
+        .location_store
+        .merge_users(&payload.from_id, &payload.into_id)
+        .await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "merged": true
+        }))),
+    )
+}
+
+/// Forcibly sever a friendship (moderation), clearing related requests and observer grants
+#[utoipa::path(
+    delete,
+    path = "/admin/friendships/{user_a}/{user_b}",
+    tag = "admin",
+    params(
+        ("user_a" = String, Path, description = "First user ID"),
+        ("user_b" = String, Path, description = "Second user ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 401, body = ApiResponse<serde_json::Value>),
+        (status = 503, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn admin_revoke_friendship(
+    State(state): State<AppState>,
+    _admin: AdminAuth,
+    ClientIp(ip): ClientIp,
+    Path((user_a, user_b)): Path<(String, String)>,
+) -> impl IntoResponse {
+    warn!(
+        "🛡️ [audit] Admin ({}) revoking friendship: {} <-> {}",
+        ip, user_a, user_b
+    );
+
+    if let Err(e) = state.sapphire_client.remove_friend(&user_a, &user_b).await {
+        return friends_unavailable(e);
+    }
+
+    let requests_removed = state
+        .location_store
+        .remove_requests_between(&user_a, &user_b)
+        .await;
+    let observer_a = state.location_store.remove_observer(&user_a, &user_b).await;
+    let observer_b = state.location_store.remove_observer(&user_b, &user_a).await;
+    state
+        .location_store
+        .clear_temporary_shares(&user_a, &user_b)
+        .await;
+    state.location_store.leave_groups(&user_a, &user_b).await;
+    unfavorite_each_other(&state, &user_a, &user_b).await;
+    state.location_store.clear_mutes(&user_a, &user_b).await;
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "removed": true,
+            "requests_removed": requests_removed,
+            "observers_removed": observer_a as usize + observer_b as usize
+        }))),
+    )
+}
+
+/// Look up the pending or accepted request between two users, in either direction
+/// This is the same lookup that blocks duplicate friend requests
+#[utoipa::path(
+    get,
+    path = "/admin/friend-requests/between/{user_a}/{user_b}",
+    tag = "admin",
+    params(
+        ("user_a" = String, Path, description = "First user ID"),
+        ("user_b" = String, Path, description = "Second user ID"),
+    ),
+    responses(
+        (status = 200, body = ApiResponse<Option<FriendRequest>>),
+        (status = 401, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn admin_request_between(
+    State(state): State<AppState>,
+    _admin: AdminAuth,
+    ClientIp(ip): ClientIp,
+    Path((user_a, user_b)): Path<(String, String)>,
+) -> impl IntoResponse {
+    info!(
+        "🛡️ [audit] Admin ({}) looking up requests between {} and {}",
+        ip, user_a, user_b
+    );
+
+    let request = state.location_store.request_between(&user_a, &user_b).await;
+    (StatusCode::OK, Json(ApiResponse::ok(request)))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetMaintenanceRequest {
+    pub enabled: bool,
+}
+
+/// Show the configuration the server is running with
+/// Secrets (the admin token, the Redis URL) are shown as `***`
+#[utoipa::path(
+    get,
+    path = "/admin/config",
+    tag = "admin",
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 401, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_config(State(state): State<AppState>, _admin: AdminAuth) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(state.config.as_ref().clone())),
+    )
+}
+
+/// Report whether maintenance (read-only) mode is on
+#[utoipa::path(
+    get,
+    path = "/admin/maintenance",
+    tag = "admin",
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 401, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn get_maintenance(State(state): State<AppState>, _admin: AdminAuth) -> impl IntoResponse {
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "enabled": state.maintenance.load(Ordering::Relaxed)
+        }))),
+    )
+}
+
+/// Turn maintenance (read-only) mode on or off
+#[utoipa::path(
+    post,
+    path = "/admin/maintenance",
+    tag = "admin",
+    request_body = SetMaintenanceRequest,
+    responses(
+        (status = 200, body = ApiResponse<serde_json::Value>),
+        (status = 401, body = ApiResponse<serde_json::Value>),
+    ),
+)]
+async fn set_maintenance(
+    State(state): State<AppState>,
+    _admin: AdminAuth,
+    ClientIp(ip): ClientIp,
+    ApiJson(payload): ApiJson<SetMaintenanceRequest>,
+) -> impl IntoResponse {
+    warn!(
+        "🚧 [audit] Admin ({}) set maintenance mode: {}",
+        ip, payload.enabled
+    );
+
+    state.maintenance.store(payload.enabled, Ordering::Relaxed);
+
+    (
+        StatusCode::OK,
+        Json(ApiResponse::ok(serde_json::json!({
+            "enabled": payload.enabled
+        }))),
+    )
+}
+
+// ============================================================================
+// Main Application
+// ============================================================================
+
+/// How often expired guests and location history are swept
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the beta allowlist file is checked for changes
+const ALLOWLIST_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Subscriber writing INFO and up to `writer`, as `format` lines
+fn log_subscriber<W>(format: LogFormat, writer: W) -> Box<dyn tracing::Subscriber + Send + Sync>
+where
+    W: for<'a> tracing_subscriber::fmt::MakeWriter<'a> + Send + Sync + 'static,
+{
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_writer(writer);
+    match format {
+        LogFormat::Pretty => Box::new(subscriber.finish()),
+        LogFormat::Json => Box::new(subscriber.json().finish()),
+    }
+}
+
+/// The whole API: every route, behind the shared middleware
+fn app(state: AppState) -> NormalizePath<Router> {
+    let app = Router::new()
+        .route("/health", get(health))
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/auth/nonce/:user_id", get(get_auth_nonce))
+        .route("/auth/verify", post(verify_self_auth))
+        .route("/guests", post(create_guest))
+        .route("/users/profiles", post(get_profiles))
+        .route("/users/:user_id", get(get_profile).put(update_profile))
+        .route("/users/:user_id/status", post(set_status))
+        .route("/users/:user_id/timezone", post(set_timezone))
+        .route("/users/:user_id/export", get(export_user_data))
+        .route(
+            "/users/:user_id/export/stream",
+            get(export_user_data_stream),
+        )
+        .route("/users/:user_id/location", post(update_location))
+        .route("/users/:user_id/history", get(get_location_history))
+        .route("/users/:user_id/cities", get(get_cities))
+        .route("/users/:user_id/distance", get(get_distance))
+        .route("/users/:user_id/device-key", post(register_device_key))
+        .route("/users/:user_id/sharing-level", post(update_sharing_level))
+        .route("/users/:user_id/sharing/pause", post(pause_sharing))
+        .route("/users/:user_id/sharing/resume", post(resume_sharing))
+        .route("/users/:user_id/sharing/audit", get(get_sharing_audit))
+        .route("/users/:user_id/privacy/simulate", post(simulate_privacy))
+        .route("/users/:user_id/sos", post(raise_sos))
+        .route("/users/:user_id/sos/clear", post(clear_sos))
         .route("/users/:user_id/friends", get(get_friends).post(add_friend))
+        .route("/users/:user_id/friends/count", get(count_friends))
+        .route("/users/:user_id/friends/details", get(get_friend_details))
         .route(
             "/users/:user_id/friends/:friend_id",
             delete(remove_friend).get(get_friend_location),
         )
+        .route(
+            "/users/:user_id/friends/:friend_id/favorite",
+            post(add_favorite).delete(remove_favorite),
+        )
+        .route(
+            "/users/:user_id/friends/:friend_id/mute",
+            post(mute_friend).delete(unmute_friend),
+        )
+        .route(
+            "/users/:user_id/friends/:friend_id/share-until",
+            post(share_until),
+        )
+        .route(
+            "/users/:user_id/friends/:friend_id/eta",
+            get(get_friend_eta),
+        )
+        .route(
+            "/users/:user_id/friends/:friend_id/track.geojson",
+            get(get_friend_track),
+        )
+        .route("/users/:user_id/friends/converge", post(get_convergence))
+        .route(
+            "/users/:user_id/friends/:friend_id/status",
+            get(get_friendship_status),
+        )
         .route(
             "/users/:user_id/friends/locations",
             get(get_friends_locations),
         )
+        .route("/users/:user_id/stream", get(stream_events))
+        .route("/users/:user_id/friends/nearby-only", post(set_nearby_only))
+        .route(
+            "/users/:user_id/friends/asymmetric",
+            get(get_asymmetric_friends),
+        )
+        .route(
+            "/users/:user_id/friends/visibility",
+            get(get_friends_visibility),
+        )
+        .route("/users/:user_id/groups", get(get_groups).post(create_group))
+        .route(
+            "/users/:user_id/schedule",
+            get(get_schedule).put(set_schedule),
+        )
+        .route(
+            "/users/:user_id/groups/:group_id",
+            put(update_group).delete(delete_group),
+        )
+        .route(
+            "/users/:user_id/groups/:group_id/members/:friend_id",
+            put(add_group_member).delete(remove_group_member),
+        )
+        .route("/users/:user_id/observers", get(get_observers))
+        .route(
+            "/users/:user_id/observers/:observer_id",
+            post(add_observer).delete(remove_observer),
+        )
         .route(
             "/users/:user_id/friend-requests",
-            get(get_friend_requests).post(send_friend_request),
+            get(get_friend_requests)
+                .post(send_friend_request)
+                .delete(clear_friend_requests),
+        )
+        .route(
+            "/users/:user_id/friend-requests/respond-bulk",
+            post(respond_bulk),
         )
         .route(
             "/users/:user_id/friend-requests/:request_id/accept",
             post(accept_friend_request),
         )
-        .route(
-            "/users/:user_id/friend-requests/:request_id/decline",
-            post(decline_friend_request),
+        .route(
+            "/users/:user_id/friend-requests/:request_id/decline",
+            post(decline_friend_request),
+        )
+        .route(
+            "/users/:user_id/friend-requests/with/:other_id",
+            get(get_request_with),
+        )
+        .route(
+            "/users/:user_id/discovery-hashes",
+            post(register_discovery_hashes),
+        )
+        .route("/discovery/match", post(match_discovery_hashes))
+        .route("/users/:user_id/discoverable", post(set_discoverable))
+        .route("/users/:user_id/radar", get(get_radar))
+        // Admin routes are merged in below, so only these are put on hold
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::maintenance_guard,
+        ));
+
+    // Everything behind `AdminAuth`, kept open in maintenance mode so
+    // operators can still repair data and switch it off again
+    let admin = Router::new()
+        .route("/auth/verify/batch", post(verify_self_auth_batch))
+        .route("/users/merge", post(merge_users))
+        .route(
+            "/admin/friendships/:user_a/:user_b",
+            delete(admin_revoke_friendship),
+        )
+        .route(
+            "/admin/friend-requests/between/:user_a/:user_b",
+            get(admin_request_between),
+        )
+        .route("/admin/config", get(get_config))
+        .route(
+            "/admin/maintenance",
+            get(get_maintenance).post(set_maintenance),
+        );
+
+    let app = app
+        .merge(admin)
+        // Must come after all routes so it applies to every one of them
+        .method_not_allowed_fallback(method_not_allowed)
+        .fallback(not_found)
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::persist_after_write,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::app_version_gate,
+        ))
+        .layer(CatchPanicLayer::custom(middleware::panic_response))
+        .layer(axum::middleware::from_fn(middleware::request_span))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::request_timeout,
+        ))
+        .layer(CorsLayer::permissive())
+        .with_state(state);
+
+    // Runs before routing, so `/users/x/friends/` matches `/users/x/friends`
+    // (a layer on the router itself would only see already-routed requests)
+    NormalizePathLayer::trim_trailing_slash().layer(app)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let config = Arc::new(Config::from_env()?);
+
+    // Initialize tracing
+    log_subscriber(config.log_format, std::io::stdout).init();
+
+    info!("🚀 Starting Linda ROFL Backend...");
+
+    // Initialize components
+    let maintenance = config.maintenance_mode;
+    let persister = Arc::new(Persister::from_config(
+        &config.store_backend,
+        &config.store_wal,
+    )?);
+    let allowlist = config
+        .allowlist
+        .as_ref()
+        .map(Allowlist::from_source)
+        .transpose()?
+        .map(Arc::new);
+    if let Some(allowlist) = &allowlist {
+        info!("🎫 Beta allowlist on: {} users", allowlist.count());
+    }
+    let location_store = Arc::new(
+        persister
+            .load_store()?
+            .with_history_max_age(config.history_max_age_secs)
+            .with_history_encoding(config.history_encoding)
+            .with_geo_index(config.geo_index)
+            .with_unique_user_names(config.unique_user_names)
+            .with_require_existing_user(config.require_existing_user)
+            .with_allowlist(allowlist.clone()),
+    );
+    info!("💾 Store backend: {:?}", config.store_backend);
+    let sapphire_client = Arc::new(SapphireClient::new().await?);
+    if let Some(path) = &config.seed_file {
+        SeedData::from_file(path, config.country_lenient)?
+            .apply(&location_store, &sapphire_client, &config)
+            .await?;
+        persister.flush(&location_store)?;
+    }
+    let celo_verifier = Arc::new(CeloVerifier::new());
+    let nonces = Arc::new(NonceStore::new(config.auth_nonce_ttl_secs));
+    let geocoder = Arc::new(ReverseGeocoder::new());
+    let events = Arc::new(EventHub::new(config.event_buffer));
+    let streams = Arc::new(StreamLimiter::new(
+        config.max_streams_per_user,
+        config.max_streams,
+    ));
+
+    // Sweep expired guests and history in the background, so data nobody
+    // touches anymore doesn't linger until the next read or write
+    {
+        let store = location_store.clone();
+        let persister = persister.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                let now = now_secs();
+                let guests = store.purge_expired_guests(now).await;
+                let points = store.prune_history(now).await;
+                let statuses = store.clear_expired_statuses(now).await;
+                if guests.is_empty() && points == 0 && statuses == 0 {
+                    continue;
+                }
+                info!(
+                    "🧹 Purged {} expired guests, {} history points and {} statuses",
+                    guests.len(),
+                    points,
+                    statuses
+                );
+                let store = store.clone();
+                let persister = persister.clone();
+                match tokio::task::spawn_blocking(move || persister.flush(&store)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("⚠️ Failed to persist store: {:#}", e),
+                    Err(e) => warn!("⚠️ Store flush task failed: {}", e),
+                }
+            }
+        });
+    }
+
+    // Deliver batched friend request notifications once per window
+    let request_digest = Arc::new(RequestDigest::new());
+    if let Some(secs) = config.friend_request_digest_secs {
+        let digest = request_digest.clone();
+        let events = events.clone();
+        tokio::spawn(async move {
+            let window = Duration::from_secs(secs);
+            let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + window, window);
+            loop {
+                ticker.tick().await;
+                digest.publish(&events);
+            }
+        });
+    }
+
+    // Heal one-sided friendships left behind by partial failures
+    if let Some(secs) = config.friend_reconcile.interval_secs {
+        let client = sapphire_client.clone();
+        let mode = config.friend_reconcile.mode;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(secs));
+            loop {
+                ticker.tick().await;
+                match reconcile::reconcile_friendships(&client, mode).await {
+                    Ok(0) => {}
+                    Ok(count) => info!("🔁 Found {} one-sided friendships ({:?})", count, mode),
+                    Err(e) => warn!("⚠️ Friendship reconciliation failed: {:#}", e),
+                }
+            }
+        });
+    }
+
+    // Pick up edits to the allowlist file without a restart
+    if let Some(allowlist) = allowlist.clone() {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(ALLOWLIST_RELOAD_INTERVAL);
+            loop {
+                ticker.tick().await;
+                match allowlist.reload_if_changed() {
+                    Ok(false) => {}
+                    Ok(true) => info!("🎫 Reloaded beta allowlist: {} users", allowlist.count()),
+                    Err(e) => warn!("⚠️ Failed to reload beta allowlist: {:#}", e),
+                }
+            }
+        });
+    }
+
+    let state = AppState {
+        config,
+        location_store,
+        sapphire_client,
+        celo_verifier,
+        nonces,
+        geocoder,
+        events,
+        request_digest,
+        streams,
+        friends_locations_flight: Arc::new(SingleFlight::new()),
+        persister,
+        maintenance: Arc::new(AtomicBool::new(maintenance)),
+        allowlist,
+    };
+
+    let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
+    let addr = format!("0.0.0.0:{}", port);
+
+    info!("✅ Server listening on {}", addr);
+    info!("📍 Location sharing with in-memory friend storage");
+    info!("🔐 Celo UID verification enabled (dev mode)");
+
+    let app = app(state);
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    axum::serve(
+        listener,
+        ServiceExt::<axum::extract::Request>::into_make_service_with_connect_info::<
+            std::net::SocketAddr,
+        >(app),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{StoreBackend, VersionPolicy};
+    use axum::body::{to_bytes, Body, Bytes};
+    use axum::extract::ConnectInfo;
+    use axum::http::{HeaderMap, Method, Request};
+    use futures_util::StreamExt as _;
+    use serde_json::{json, Value};
+    use std::net::SocketAddr;
+    use tower::ServiceExt as _;
+
+    const ADMIN_TOKEN: &str = "test-admin-token";
+
+    fn test_config() -> Config {
+        Config {
+            admin_token: Some(ADMIN_TOKEN.to_string()),
+            ..Config::from_env().unwrap()
+        }
+    }
+
+    /// Everything `main` sets up, in memory and without background tasks
+    async fn test_state(config: Config) -> AppState {
+        let persister = Persister::from_config(&config.store_backend, &config.store_wal).unwrap();
+        let location_store = LocationStore::new()
+            .with_history_max_age(config.history_max_age_secs)
+            .with_history_encoding(config.history_encoding)
+            .with_geo_index(config.geo_index)
+            .with_unique_user_names(config.unique_user_names)
+            .with_require_existing_user(config.require_existing_user);
+        AppState {
+            nonces: Arc::new(NonceStore::new(config.auth_nonce_ttl_secs)),
+            events: Arc::new(EventHub::new(config.event_buffer)),
+            streams: Arc::new(StreamLimiter::new(
+                config.max_streams_per_user,
+                config.max_streams,
+            )),
+            maintenance: Arc::new(AtomicBool::new(config.maintenance_mode)),
+            config: Arc::new(config),
+            location_store: Arc::new(location_store),
+            sapphire_client: Arc::new(SapphireClient::new().await.unwrap()),
+            celo_verifier: Arc::new(CeloVerifier::new()),
+            geocoder: Arc::new(ReverseGeocoder::new()),
+            request_digest: Arc::new(RequestDigest::new()),
+            friends_locations_flight: Arc::new(SingleFlight::new()),
+            persister: Arc::new(persister),
+            allowlist: None,
+        }
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn send_json(method: Method, uri: &str, body: Value) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn post(uri: &str, body: Value) -> Request<Body> {
+        send_json(Method::POST, uri, body)
+    }
+
+    fn with_header(mut request: Request<Body>, name: &'static str, value: &str) -> Request<Body> {
+        request.headers_mut().insert(name, value.parse().unwrap());
+        request
+    }
+
+    fn as_user(request: Request<Body>, user_id: &str) -> Request<Body> {
+        with_header(request, "x-user-id", user_id)
+    }
+
+    fn as_admin(request: Request<Body>) -> Request<Body> {
+        with_header(request, "authorization", &format!("Bearer {}", ADMIN_TOKEN))
+    }
+
+    /// Run one request through the whole app, as if from 127.0.0.1
+    async fn send_raw(
+        state: &AppState,
+        mut request: Request<Body>,
+    ) -> (StatusCode, HeaderMap, Bytes) {
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+        let response = app(state.clone()).oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, body)
+    }
+
+    /// Run one request, returning its status and JSON body (`null` when empty)
+    async fn send(state: &AppState, request: Request<Body>) -> (StatusCode, Value) {
+        let (status, _, body) = send_raw(state, request).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    type EventStream = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    /// Serve the app on a free local port, for tests that need a real socket
+    async fn serve(state: &AppState) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app(state.clone());
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                ServiceExt::<axum::extract::Request>::into_make_service_with_connect_info::<
+                    SocketAddr,
+                >(app),
+            )
+            .await
+            .unwrap();
+        });
+        addr
+    }
+
+    /// Open `user_id`'s event stream, returning once it is subscribed
+    async fn open_stream(state: &AppState, addr: SocketAddr, user_id: &str) -> EventStream {
+        let subscribed = state.events.subscriber_count();
+        let url = format!("ws://{}/users/{}/stream", addr, user_id);
+        let (stream, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        while state.events.subscriber_count() == subscribed {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        stream
+    }
+
+    /// The next message on a stream, failing the test if none comes soon
+    async fn next_event(stream: &mut EventStream) -> Value {
+        let message = tokio::time::timeout(Duration::from_secs(2), stream.next())
+            .await
+            .expect("no event within 2s")
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    /// Fail the test if a stream receives anything for a little while
+    async fn assert_no_event(stream: &mut EventStream) {
+        let next = tokio::time::timeout(Duration::from_millis(200), stream.next()).await;
+        assert!(next.is_err(), "unexpected message: {:?}", next);
+    }
+
+    async fn locate(state: &AppState, user_id: &str, latitude: f64, longitude: f64) {
+        let body = json!({
+            "user_id": user_id,
+            "location": {"latitude": latitude, "longitude": longitude, "city": "Istanbul", "country": "TR"}
+        });
+        let (status, _) = send(state, post(&format!("/users/{}/location", user_id), body)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    async fn share(state: &AppState, user_id: &str, level: &str) {
+        let body = json!({"user_id": user_id, "level": level});
+        let (status, _) = send(
+            state,
+            post(&format!("/users/{}/sharing-level", user_id), body),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    async fn befriend(state: &AppState, user_a: &str, user_b: &str) {
+        state
+            .sapphire_client
+            .add_friend(user_a, user_b, None)
+            .await
+            .unwrap();
+    }
+
+    /// Send a friend request, returning the `data` of the response
+    async fn request_friend(state: &AppState, sender_id: &str, receiver_id: &str) -> Value {
+        let body = json!({"senderId": sender_id, "receiverId": receiver_id});
+        let uri = format!("/users/{}/friend-requests", sender_id);
+        let (status, body) = send(state, post(&uri, body)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body["data"].clone()
+    }
+
+    /// IDs of the friends listed at `/friends/locations` with `query`, in order
+    async fn friends_listed(state: &AppState, user_id: &str, query: &str) -> Vec<String> {
+        let uri = format!("/users/{}/friends/locations{}", user_id, query);
+        let (status, body) = send(state, get(&uri)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|friend| friend["id"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn export_is_only_for_the_user_or_an_admin() {
+        let state = test_state(test_config()).await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        share(&state, "alice", "realtime").await;
+        locate(&state, "bob", 48.8566, 2.3522).await;
+        share(&state, "bob", "realtime").await;
+        befriend(&state, "alice", "bob").await;
+        state
+            .location_store
+            .register_discovery_hashes("alice", vec!["hash-1".to_string()])
+            .await;
+
+        let (status, body) = send(&state, as_user(get("/users/alice/export"), "alice")).await;
+        assert_eq!(status, StatusCode::OK);
+        let export = &body["data"];
+        assert_eq!(export["profile"]["id"], "alice");
+        assert_eq!(export["profile"]["location"]["latitude"], 41.0082);
+        assert_eq!(export["history"].as_array().unwrap().len(), 1);
+        assert_eq!(export["discoveryHashes"], json!(["hash-1"]));
+        assert_eq!(export["friends"], json!(["bob"]));
+        // Friends by ID only, never where they are
+        assert!(!body.to_string().contains("48.8566"));
+
+        for request in [
+            get("/users/alice/export"),
+            as_user(get("/users/alice/export"), "bob"),
+        ] {
+            let (status, body) = send(&state, request).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert_eq!(body["success"], false);
+        }
+        let (status, _) = send(&state, as_admin(get("/users/alice/export"))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn streamed_export_is_one_record_per_line_in_order() {
+        let state = test_state(test_config()).await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        befriend(&state, "alice", "bob").await;
+        befriend(&state, "alice", "carol").await;
+        request_friend(&state, "alice", "dave").await;
+
+        let (status, _) = send(&state, get("/users/alice/export/stream")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, headers, body) =
+            send_raw(&state, as_user(get("/users/alice/export/stream"), "alice")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/x-ndjson");
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let records: Vec<Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let types: Vec<&str> = records
+            .iter()
+            .map(|record| record["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "profile",
+                "history_point",
+                "city_visit",
+                "friend",
+                "friend",
+                "friend_request",
+                "end"
+            ]
+        );
+        assert_eq!(records[0]["profile"]["id"], "alice");
+        assert_eq!(records[6]["records"], 7);
+    }
+
+    #[tokio::test]
+    async fn slow_handlers_time_out_with_a_json_408() {
+        let state = test_state(Config {
+            request_timeout_secs: 1,
+            ..test_config()
+        })
+        .await;
+        let slow = || async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            "too late"
+        };
+        let app = Router::new()
+            .route("/slow", axum::routing::get(slow))
+            .route("/fast", axum::routing::get(|| async { "in time" }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                middleware::request_timeout,
+            ))
+            .with_state(state);
+
+        let response = app.clone().oneshot(get("/slow")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"], "Request timed out");
+
+        let response = app.oneshot(get("/fast")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn panicking_handlers_get_a_json_500() {
+        async fn boom() -> &'static str {
+            panic!("boom")
+        }
+        let app = Router::new()
+            .route("/boom", axum::routing::get(boom))
+            .layer(CatchPanicLayer::custom(middleware::panic_response));
+
+        let response = app.oneshot(get("/boom")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"success": false, "error": "internal error"}));
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_and_wrong_methods_get_json_errors() {
+        let state = test_state(test_config()).await;
+
+        let (status, body) = send(&state, get("/no/such/route")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, json!({"success": false, "error": "Not found"}));
+
+        let request = Request::delete("/health").body(Body::empty()).unwrap();
+        let (status, body) = send(&state, request).await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            body,
+            json!({"success": false, "error": "Method not allowed"})
+        );
+    }
+
+    #[tokio::test]
+    async fn trailing_slashes_route_like_the_bare_path() {
+        let state = test_state(test_config()).await;
+        befriend(&state, "alice", "bob").await;
+        befriend(&state, "alice", "carol").await;
+
+        let (status, bare) = send(&state, get("/users/alice/friends")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(bare["data"], json!(["bob", "carol"]));
+        let (status, slashed) = send(&state, get("/users/alice/friends/")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(slashed, bare);
+
+        // A friend ID after the slash is still a route of its own
+        for uri in ["/users/alice/friends/bob", "/users/alice/friends/bob/"] {
+            let (status, body) = send(&state, get(uri)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["data"]["id"], "bob");
+        }
+    }
+
+    #[tokio::test]
+    async fn friend_location_tells_strangers_from_unknown_users() {
+        let state = test_state(test_config()).await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        locate(&state, "mallory", 41.01, 28.98).await;
+
+        let (status, body) = send(&state, get("/users/mallory/friends/alice")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, json!({"success": false, "error": "Not friends"}));
+
+        let (status, body) = send(&state, get("/users/mallory/friends/nobody")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, json!({"success": false, "error": "User not found"}));
+    }
+
+    #[tokio::test]
+    async fn maintenance_holds_user_writes_but_not_reads_or_admin_routes() {
+        let state = test_state(test_config()).await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        locate(&state, "alias", 41.0, 29.0).await;
+        state.maintenance.store(true, Ordering::Relaxed);
+
+        let body = json!({"user_id": "alice", "location": {"latitude": 41.1, "longitude": 29.1}});
+        let (status, headers, _) =
+            send_raw(&state, post("/users/alice/location", body.clone())).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(headers[header::RETRY_AFTER], "60");
+
+        let (status, _) = send(&state, get("/users/alice")).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let merge = post(
+            "/users/merge",
+            json!({"from_id": "alias", "into_id": "alice"}),
+        );
+        let (status, _) = send(&state, as_admin(merge)).await;
+        assert_eq!(status, StatusCode::OK);
+        let verify = post("/auth/verify/batch", json!({"items": []}));
+        let (status, _) = send(&state, as_admin(verify)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let off = post("/admin/maintenance", json!({"enabled": false}));
+        let (status, _) = send(&state, as_admin(off)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&state, post("/users/alice/location", body)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn eta_needs_a_realtime_fresh_fix() {
+        let state = test_state(test_config()).await;
+        befriend(&state, "alice", "bob").await;
+        share(&state, "bob", "realtime").await;
+        locate(&state, "bob", 41.0082, 28.9784).await;
+        let uri = "/users/alice/friends/bob/eta?to_lat=41.1&to_lng=29.0";
+
+        let (status, body) = send(&state, get(uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["data"]["distanceKm"].as_f64().unwrap() > 10.0);
+
+        // Within the location TTL, so still shown, but too old to say where
+        // bob is right now
+        state.location_store.backdate("bob", 60);
+        assert!(60 < state.config.location_ttl_secs);
+        assert!(60 > state.config.realtime_freshness_secs);
+        let (status, friend) = send(&state, get("/users/alice/friends/bob")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(friend["data"]["isFresh"], true);
+        let (status, body) = send(&state, get(uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!({"distanceKm": null, "speedMps": null, "etaSecs": null})
+        );
+    }
+
+    #[tokio::test]
+    async fn level_changes_notify_each_subscribed_friend_once() {
+        let state = test_state(test_config()).await;
+        befriend(&state, "alice", "bob").await;
+        befriend(&state, "alice", "carol").await;
+        let addr = serve(&state).await;
+        let mut bob = open_stream(&state, addr, "bob").await;
+        let mut carol = open_stream(&state, addr, "carol").await;
+        let mut dave = open_stream(&state, addr, "dave").await;
+
+        share(&state, "alice", "city").await;
+        for stream in [&mut bob, &mut carol] {
+            let event = next_event(stream).await;
+            assert_eq!(
+                event,
+                json!({"type": "visibility_changed", "userId": "alice"})
+            );
+            assert_no_event(stream).await;
+        }
+        assert_no_event(&mut dave).await;
+
+        // Setting the same level again changes nothing anyone can see
+        share(&state, "alice", "city").await;
+        assert_no_event(&mut bob).await;
+    }
+
+    #[tokio::test]
+    async fn friends_added_while_streaming_are_delivered_without_reconnecting() {
+        let state = test_state(test_config()).await;
+        share(&state, "bob", "realtime").await;
+        let addr = serve(&state).await;
+        let mut alice = open_stream(&state, addr, "alice").await;
+
+        // Not friends yet, so nothing to see
+        locate(&state, "bob", 41.0082, 28.9784).await;
+        assert_no_event(&mut alice).await;
+
+        let body = json!({"user_id": "alice", "friend_id": "bob"});
+        let (status, _) = send(&state, post("/users/alice/friends", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        locate(&state, "bob", 41.02, 28.99).await;
+        let event = next_event(&mut alice).await;
+        assert_eq!(event["type"], "location_updated");
+        assert_eq!(event["friend"]["id"], "bob");
+        assert_eq!(event["friend"]["location"]["latitude"], 41.02);
+    }
+
+    #[tokio::test]
+    async fn lagging_streams_are_told_to_resync() {
+        let state = test_state(Config {
+            event_buffer: 2,
+            ..test_config()
+        })
+        .await;
+        let addr = serve(&state).await;
+        let mut alice = open_stream(&state, addr, "alice").await;
+
+        // Published back to back, before the stream gets a chance to read
+        for i in 0..10 {
+            state.events.publish(Event::VisibilityChanged {
+                user_id: format!("friend-{}", i),
+                recipients: vec!["alice".to_string()],
+            });
+        }
+
+        let resync = next_event(&mut alice).await;
+        assert_eq!(resync, json!({"type": "resync_required", "missed": 8}));
+        // Then it carries on with what is still buffered
+        for i in 8..10 {
+            let event = next_event(&mut alice).await;
+            assert_eq!(event["userId"], format!("friend-{}", i));
+        }
+        assert_no_event(&mut alice).await;
+    }
+
+    #[tokio::test]
+    async fn discovery_matches_registered_hashes_only() {
+        let state = test_state(test_config()).await;
+        let alice_hash = "ab".repeat(32);
+        let bob_hash = "cd".repeat(32);
+        let body = json!({"hashes": [alice_hash.to_uppercase()]});
+        let (status, _) = send(&state, post("/users/alice/discovery-hashes", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        let body = json!({"hashes": [bob_hash]});
+        let (status, _) = send(&state, post("/users/bob/discovery-hashes", body)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let body = json!({"hashes": [alice_hash, "not-a-hash"]});
+        let (status, matched) = send(&state, post("/discovery/match", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(matched["data"], json!(["alice"]));
+
+        let body = json!({"hashes": ["ef".repeat(32)]});
+        let (status, matched) = send(&state, post("/discovery/match", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(matched["data"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn observers_see_the_user_without_becoming_friends() {
+        let state = test_state(test_config()).await;
+        share(&state, "kid", "realtime").await;
+        locate(&state, "kid", 41.0082, 28.9784).await;
+        locate(&state, "parent", 41.0, 29.0).await;
+
+        let (status, _) = send(&state, get("/users/parent/friends/kid")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = send(&state, post("/users/kid/observers/parent", json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&state, get("/users/parent/friends/kid")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["location"]["latitude"], 41.0082);
+
+        // One-directional, and neither side's friend list changes
+        let (status, _) = send(&state, get("/users/kid/friends/parent")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        for user_id in ["kid", "parent"] {
+            let (_, friends) = send(&state, get(&format!("/users/{}/friends", user_id))).await;
+            assert_eq!(friends["data"], json!([]));
+        }
+    }
+
+    #[tokio::test]
+    async fn sharing_audit_reports_each_friends_effective_level() {
+        let state = test_state(test_config()).await;
+        share(&state, "alice", "city").await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        for friend_id in ["bob", "carol", "dave"] {
+            befriend(&state, "alice", friend_id).await;
+        }
+        let store = &state.location_store;
+        store
+            .set_temporary_share("alice", "bob", SharingLevel::Realtime, now_secs() + 3600)
+            .await;
+        let group = store
+            .create_group("alice", "Acquaintances".to_string(), SharingLevel::CityName)
+            .await
+            .unwrap();
+        store
+            .add_group_member("alice", &group.id, "carol")
+            .await
+            .unwrap();
+
+        let (status, body) = send(&state, get("/users/alice/sharing/audit")).await;
+        assert_eq!(status, StatusCode::OK);
+        let rounding = state.config.privacy_rounding_meters;
+        let entry = |friend_id: &str, level: SharingLevel| {
+            json!({
+                "friendId": friend_id,
+                "sharingLevel": level,
+                "precisionMeters": privacy::precision_meters(&level, rounding),
+                "hasFreshFix": true,
+            })
+        };
+        assert_eq!(
+            body["data"],
+            json!([
+                entry("bob", SharingLevel::Realtime),
+                entry("carol", SharingLevel::CityName),
+                entry("dave", SharingLevel::City),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn signed_locations_are_checked_when_required() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let state = test_state(Config {
+            require_signed_location: true,
+            ..test_config()
+        })
+        .await;
+        let device = SigningKey::from_bytes(&[7; 32]);
+        let public_key = hex::encode(device.verifying_key().as_bytes());
+        let body = json!({"public_key": public_key});
+        let (status, _) = send(&state, post("/users/alice/device-key", body)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let location = LocationData {
+            latitude: 41.0082,
+            longitude: 28.9784,
+            city: None,
+            country: None,
+            timestamp: None,
+            speed: None,
+            heading: None,
+        };
+        let message = signing::location_message("alice", &location);
+        let valid = hex::encode(device.sign(message.as_bytes()).to_bytes());
+        let forged = hex::encode(device.sign(b"alice:0:0:").to_bytes());
+        let update = |signature: Option<&str>| {
+            post(
+                "/users/alice/location",
+                json!({"user_id": "alice", "location": location, "signature": signature}),
+            )
+        };
+
+        let (status, body) = send(&state, update(None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "Location updates must be signed");
+        let (status, body) = send(&state, update(Some(&forged))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "Location signature verification failed");
+        assert!(state.location_store.get_user("alice").await.is_none());
+
+        let (status, _) = send(&state, update(Some(&valid))).await;
+        assert_eq!(status, StatusCode::OK);
+        let alice = state.location_store.get_user("alice").await.unwrap();
+        assert_eq!(alice.location.unwrap().latitude, 41.0082);
+    }
+
+    #[tokio::test]
+    async fn revoking_a_friendship_during_an_outage_is_a_503() {
+        let state = test_state(test_config()).await;
+        befriend(&state, "alice", "bob").await;
+        state.sapphire_client.set_outage(true);
+
+        let request = Request::delete("/admin/friendships/alice/bob")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&state, as_admin(request)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["success"], false);
+
+        state.sapphire_client.set_outage(false);
+        let friends = state.sapphire_client.get_friends("alice").await.unwrap();
+        assert_eq!(friends, ["bob"]);
+    }
+
+    #[tokio::test]
+    async fn friend_lists_report_an_outage_instead_of_no_friends() {
+        let state = test_state(test_config()).await;
+        befriend(&state, "alice", "bob").await;
+        state.sapphire_client.set_outage(true);
+
+        for uri in [
+            "/users/alice/friends",
+            "/users/alice/friends/count",
+            "/users/alice/friends/locations",
+            "/users/alice/friends/bob",
+        ] {
+            let (status, body) = send(&state, get(uri)).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", uri);
+            assert_eq!(body["success"], false, "{}", uri);
+            assert!(body.get("data").is_none(), "{}", uri);
+        }
+
+        state.sapphire_client.set_outage(false);
+        let (status, body) = send(&state, get("/users/alice/friends")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"], json!(["bob"]));
+    }
+
+    #[tokio::test]
+    async fn friendship_status_covers_friends_strangers_and_pending() {
+        let state = test_state(test_config()).await;
+        befriend(&state, "alice", "bob").await;
+        request_friend(&state, "alice", "carol").await;
+        let status_of = |user_id: &str, other_id: &str| {
+            get(&format!("/users/{}/friends/{}/status", user_id, other_id))
+        };
+
+        let (_, body) = send(&state, status_of("alice", "bob")).await;
+        assert_eq!(
+            body["data"],
+            json!({"friends": true, "pendingRequest": null})
+        );
+        let (_, body) = send(&state, status_of("alice", "dave")).await;
+        assert_eq!(
+            body["data"],
+            json!({"friends": false, "pendingRequest": null})
+        );
+        let (_, body) = send(&state, status_of("alice", "carol")).await;
+        assert_eq!(
+            body["data"],
+            json!({"friends": false, "pendingRequest": "outgoing"})
+        );
+        let (_, body) = send(&state, status_of("carol", "alice")).await;
+        assert_eq!(
+            body["data"],
+            json!({"friends": false, "pendingRequest": "incoming"})
+        );
+    }
+
+    /// Log output collected in memory
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedLogs {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn json_logs_are_one_object_per_line() {
+        let logs = CapturedLogs::default();
+        let subscriber = log_subscriber(LogFormat::Json, logs.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            info!(user_id = "alice", "📍 Updating location");
+            tracing::debug!("below the level, so left out");
+        });
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["fields"]["message"], "📍 Updating location");
+        assert_eq!(lines[0]["fields"]["user_id"], "alice");
+    }
+
+    #[tokio::test]
+    async fn request_messages_are_cleaned_and_length_checked() {
+        let state = test_state(test_config()).await;
+        let send_with = |receiver_id: &str, message: String| {
+            post(
+                "/users/alice/friend-requests",
+                json!({"senderId": "alice", "receiverId": receiver_id, "message": message}),
+            )
+        };
+
+        let (status, _) = send(&state, send_with("bob", " Hi\u{7} there 👋\n".to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, received) = send(&state, get("/users/bob/friend-requests")).await;
+        assert_eq!(received["data"][0]["message"], "Hi there 👋");
+
+        // The limit counts characters, not bytes
+        let (status, _) = send(&state, send_with("carol", "👋".repeat(280))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&state, send_with("dave", "a".repeat(281))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "Message too long (max 280 characters)");
+    }
+
+    #[tokio::test]
+    async fn accepting_a_request_returns_the_accepters_profile() {
+        let state = test_state(test_config()).await;
+        locate(&state, "bob", 41.0082, 28.9784).await;
+        let body = json!({"userName": "Bob"});
+        let (status, _) = send(&state, send_json(Method::PUT, "/users/bob", body)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let request = request_friend(&state, "alice", "bob").await;
+        let uri = format!(
+            "/users/bob/friend-requests/{}/accept",
+            request["id"].as_str().unwrap()
+        );
+        let (status, body) = send(&state, post(&uri, json!({}))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["status"], "accepted");
+        assert_eq!(
+            body["data"]["accepter"],
+            json!({"id": "bob", "userName": "Bob"})
+        );
+    }
+
+    #[tokio::test]
+    async fn friend_requests_list_newest_first_unless_asked_for_oldest() {
+        let state = test_state(test_config()).await;
+        let mut sent = Vec::new();
+        for (age, sender) in [(300, "alice"), (200, "carol"), (100, "dave")] {
+            let request = request_friend(&state, sender, "bob").await;
+            let id = request["id"].as_str().unwrap().to_string();
+            state.location_store.backdate_request(&id, age);
+            sent.push(sender);
+        }
+        let senders = |body: &Value| -> Vec<String> {
+            body["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|r| r["senderId"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let (status, body) = send(&state, get("/users/bob/friend-requests")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(senders(&body), ["dave", "carol", "alice"]);
+
+        let (status, body) = send(&state, get("/users/bob/friend-requests?sort=oldest")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(senders(&body), sent);
+    }
+
+    #[tokio::test]
+    async fn client_ip_honors_proxy_headers_only_when_trusted() {
+        for (trust_proxy, expected) in [(true, "203.0.113.7"), (false, "127.0.0.1")] {
+            let state = test_state(Config {
+                trust_proxy,
+                ..test_config()
+            })
+            .await;
+            let app = Router::new()
+                .route(
+                    "/ip",
+                    axum::routing::get(|ClientIp(ip): ClientIp| async move { ip.to_string() }),
+                )
+                .with_state(state);
+
+            let mut request = with_header(get("/ip"), "x-forwarded-for", "203.0.113.7, 10.0.0.1");
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+            let response = app.oneshot(request).await.unwrap();
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(body, expected, "trust_proxy = {}", trust_proxy);
+        }
+    }
+
+    #[tokio::test]
+    async fn bulk_responses_succeed_or_fail_one_by_one() {
+        let state = test_state(test_config()).await;
+        let from_alice = request_friend(&state, "alice", "bob").await;
+        let from_carol = request_friend(&state, "carol", "bob").await;
+
+        let body = json!({"responses": [
+            {"request_id": from_alice["id"], "action": "accept"},
+            {"request_id": "no-such-request", "action": "accept"},
+            {"request_id": from_carol["id"], "action": "decline"},
+        ]});
+        let (status, body) = send(
+            &state,
+            post("/users/bob/friend-requests/respond-bulk", body),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!([
+                {"requestId": from_alice["id"], "action": "accept", "success": true, "error": null},
+                {"requestId": "no-such-request", "action": "accept", "success": false, "error": "Friend request not found"},
+                {"requestId": from_carol["id"], "action": "decline", "success": true, "error": null},
+            ])
+        );
+
+        let friends = state.sapphire_client.get_friends("bob").await.unwrap();
+        assert_eq!(friends, ["alice"]);
+        let (_, pending) = send(&state, get("/users/bob/friend-requests")).await;
+        assert_eq!(pending["data"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn cities_are_only_for_the_user_or_an_admin() {
+        let state = test_state(test_config()).await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        befriend(&state, "alice", "bob").await;
+
+        let (status, body) = send(&state, as_user(get("/users/alice/cities"), "bob")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["success"], false);
+        let (status, _) = send(&state, get("/users/alice/cities")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        for request in [
+            as_user(get("/users/alice/cities"), "alice"),
+            as_admin(get("/users/alice/cities")),
+        ] {
+            let (status, body) = send(&state, request).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["data"][0]["city"], "Istanbul");
+        }
+    }
+
+    #[tokio::test]
+    async fn distance_is_only_for_the_user_or_an_admin() {
+        let state = test_state(test_config()).await;
+        locate(&state, "alice", 0.0, 0.0).await;
+        state.location_store.backdate("alice", 60);
+        locate(&state, "alice", 0.0, 1.0).await;
+
+        let (status, _) = send(&state, as_user(get("/users/alice/distance"), "bob")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send(&state, as_user(get("/users/alice/distance"), "alice")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["points"], 2);
+        let km = body["data"]["distance_km"].as_f64().unwrap();
+        assert!((km - 111.19).abs() < 0.01, "{}", km);
+    }
+
+    #[tokio::test]
+    async fn old_app_versions_are_rejected_or_warned() {
+        let state = test_state(Config {
+            version_policy: VersionPolicy {
+                min_version: Some("2.0".parse().unwrap()),
+                deprecated_below: Some("2.5.0".parse().unwrap()),
+                sunset: Some("Wed, 31 Dec 2026 23:59:59 GMT".to_string()),
+            },
+            ..test_config()
+        })
+        .await;
+        let request = |version: &str| with_header(get("/health"), "x-app-version", version);
+
+        let (status, headers, body) = send_raw(&state, request("1.9.9")).await;
+        assert_eq!(status, StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({"success": false, "error": "This app version is no longer supported; please update"})
+        );
+        assert!(headers.get("deprecation").is_none());
+
+        let (status, headers, _) = send_raw(&state, request("2.4")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["deprecation"], "true");
+        assert_eq!(headers["sunset"], "Wed, 31 Dec 2026 23:59:59 GMT");
+
+        for current in ["2.5.0", "not-a-version"] {
+            let (status, headers, _) = send_raw(&state, request(current)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert!(headers.get("deprecation").is_none(), "{}", current);
+            assert!(headers.get("sunset").is_none(), "{}", current);
+        }
+    }
+
+    #[tokio::test]
+    async fn asymmetric_friends_lists_only_one_sided_friendships() {
+        let state = test_state(test_config()).await;
+        befriend(&state, "alice", "bob").await;
+        // Carol lists Bob, but Bob's side was never written
+        state
+            .sapphire_client
+            .add_friend_one_way("carol", "bob")
+            .await
+            .unwrap();
+
+        let (status, body) = send(&state, get("/users/bob/friends/asymmetric")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"], json!(["carol"]));
+
+        let (status, body) = send(&state, get("/users/alice/friends/asymmetric")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn batch_verification_reports_each_item() {
+        let state = test_state(test_config()).await;
+        state.celo_verifier.set_answer("uid-alice", Some(true));
+        state.celo_verifier.set_answer("uid-bob", Some(false));
+        state.celo_verifier.set_answer("uid-carol", None);
+
+        let body = json!({"items": [
+            {"celo_uid": "uid-alice", "user_id": "alice"},
+            {"celo_uid": "uid-bob", "user_id": "bob"},
+            {"celo_uid": "uid-carol", "user_id": "carol"},
+        ]});
+        let (status, _) = send(&state, post("/auth/verify/batch", body.clone())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = send(&state, as_admin(post("/auth/verify/batch", body))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!([
+                {"user_id": "alice", "verified": true, "error": null},
+                {"user_id": "bob", "verified": false, "error": "Celo UID verification failed"},
+                {"user_id": "carol", "verified": false, "error": "Verification error: Celo node unreachable"},
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn nearby_only_hides_distant_friends() {
+        let state = test_state(test_config()).await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        // Bob is across the Bosphorus, Carol is in Ankara
+        for (friend, lat, lng) in [("bob", 41.0190, 29.0090), ("carol", 39.9334, 32.8597)] {
+            locate(&state, friend, lat, lng).await;
+            share(&state, friend, "realtime").await;
+            befriend(&state, "alice", friend).await;
+        }
+        assert_eq!(friends_listed(&state, "alice", "").await, ["bob", "carol"]);
+
+        let body = json!({"enabled": true});
+        let (status, _) = send(&state, post("/users/alice/friends/nearby-only", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(friends_listed(&state, "alice", "").await, ["bob"]);
+        // An explicit radius still wins over the default
+        assert_eq!(
+            friends_listed(&state, "alice", "?radius_km=1000").await,
+            ["bob", "carol"]
+        );
+    }
+
+    #[tokio::test]
+    async fn fields_selects_a_subset_and_rejects_unknown_names() {
+        let state = test_state(test_config()).await;
+        locate(&state, "bob", 41.0082, 28.9784).await;
+        share(&state, "bob", "realtime").await;
+        befriend(&state, "alice", "bob").await;
+
+        let uri = "/users/alice/friends/locations?fields=id,%20sharingLevel";
+        let (status, body) = send(&state, get(uri)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!([{"id": "bob", "sharingLevel": "realtime"}])
+        );
+
+        let (status, body) = send(&state, get("/users/bob?fields=userName,version")).await;
+        assert_eq!(status, StatusCode::OK);
+        let keys: Vec<&String> = body["data"].as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["userName", "version"]);
+
+        let uri = "/users/alice/friends/locations?fields=id,password";
+        let (status, body) = send(&state, get(uri)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error = body["error"].as_str().unwrap();
+        assert!(
+            error.starts_with("Unknown field: password (expected one of id, userName,"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn friends_of_a_user_without_a_location_are_not_radius_filtered() {
+        let state = test_state(test_config()).await;
+        for (friend, lat, lng) in [("bob", 41.0190, 29.0090), ("carol", 39.9334, 32.8597)] {
+            locate(&state, friend, lat, lng).await;
+            share(&state, friend, "realtime").await;
+            befriend(&state, "alice", friend).await;
+        }
+        let body = json!({"enabled": true});
+        let (status, _) = send(&state, post("/users/alice/friends/nearby-only", body)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Alice never reported a location, so there is nothing to measure from
+        for query in ["", "?radius_km=1"] {
+            assert_eq!(
+                friends_listed(&state, "alice", query).await,
+                ["bob", "carol"]
+            );
+        }
+        let (_, body) = send(&state, get("/users/alice/friends/locations")).await;
+        for friend in body["data"].as_array().unwrap() {
+            assert!(
+                friend.get("distanceKm").is_none_or(Value::is_null),
+                "{}",
+                friend
+            );
+            assert!(friend["location"]["latitude"].is_number());
+        }
+    }
+
+    #[tokio::test]
+    async fn privacy_simulation_shows_each_level() {
+        let state = test_state(Config {
+            privacy_rounding_meters: None,
+            ..test_config()
+        })
+        .await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        share(&state, "alice", "realtime").await;
+
+        let expected = [
+            (
+                "realtime",
+                json!({"sharingLevel": "realtime", "latitude": 41.0082, "longitude": 28.9784,
+                       "city": "Istanbul", "country": "TR", "precisionMeters": 0.0}),
+            ),
+            (
+                "city",
+                json!({"sharingLevel": "city", "latitude": 41.01, "longitude": 28.98,
+                       "city": "Istanbul", "country": "TR", "precisionMeters": 1113.2}),
+            ),
+            (
+                "city_name",
+                json!({"sharingLevel": "city_name", "latitude": null, "longitude": null,
+                       "city": "Istanbul", "country": "TR", "precisionMeters": 10000.0}),
+            ),
+        ];
+        for (level, expected) in expected {
+            let body = json!({"level": level});
+            let (status, body) = send(&state, post("/users/alice/privacy/simulate", body)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["data"], expected, "{}", level);
+        }
+
+        // A custom point carries no labels, and nothing was saved
+        let body = json!({"level": "city", "latitude": 39.9334, "longitude": 32.8597});
+        let (_, body) = send(&state, post("/users/alice/privacy/simulate", body)).await;
+        assert_eq!(
+            (
+                &body["data"]["latitude"],
+                &body["data"]["longitude"],
+                &body["data"]["city"]
+            ),
+            (&json!(39.93), &json!(32.86), &Value::Null)
+        );
+        let alice = state.location_store.get_user("alice").await.unwrap();
+        assert_eq!(alice.sharing_level, Some(SharingLevel::Realtime));
+    }
+
+    #[tokio::test]
+    async fn guests_can_share_but_not_befriend_and_expire() {
+        let state = test_state(test_config()).await;
+        let (status, body) = send(&state, post("/guests", json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let guest = body["data"]["id"].as_str().unwrap().to_string();
+        let expires_at = body["data"]["expiresAt"].as_i64().unwrap();
+
+        // There are no share links in this API; granting an observer is how a
+        // location is shared one-off outside the friend graph
+        locate(&state, &guest, 41.0082, 28.9784).await;
+        let uri = format!("/users/{}/observers/alice", guest);
+        let (status, _) = send(&state, post(&uri, json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.location_store.get_observers(&guest).await, ["alice"]);
+
+        let body = json!({"senderId": guest, "receiverId": "alice"});
+        let uri = format!("/users/{}/friend-requests", guest);
+        let (status, body) = send(&state, post(&uri, body)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            body["error"],
+            "Guests can't send or receive friend requests"
+        );
+
+        assert!(state
+            .location_store
+            .purge_expired_guests(expires_at - 1)
+            .await
+            .is_empty());
+        assert_eq!(
+            state.location_store.purge_expired_guests(expires_at).await,
+            [guest.as_str()]
+        );
+        assert!(!state.location_store.is_guest(&guest).await);
+        assert!(state.location_store.get_user(&guest).await.is_none());
+        assert!(state.location_store.get_observers(&guest).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn friends_are_never_added_twice() {
+        let state = test_state(test_config()).await;
+        befriend(&state, "alice", "bob").await;
+        let sent = request_friend(&state, "alice", "bob").await;
+        assert_eq!(sent, json!({"alreadyFriends": true}));
+        let (_, pending) = send(&state, get("/users/bob/friend-requests")).await;
+        assert_eq!(pending["data"], json!([]));
+
+        // Carol's request is still pending when she and Dave become friends
+        // some other way; accepting it, even twice, leaves one friendship
+        let request = request_friend(&state, "carol", "dave").await;
+        befriend(&state, "carol", "dave").await;
+        let uri = format!(
+            "/users/dave/friend-requests/{}/accept",
+            request["id"].as_str().unwrap()
+        );
+        for _ in 0..2 {
+            let (status, body) = send(&state, post(&uri, json!({}))).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+        for (user, friend) in [("carol", "dave"), ("dave", "carol")] {
+            let friends = state.sapphire_client.get_friends(user).await.unwrap();
+            assert_eq!(friends, [friend]);
+        }
+    }
+
+    #[tokio::test]
+    async fn presence_filters_combine() {
+        let state = test_state(test_config()).await;
+        for (friend, level) in [("bob", "realtime"), ("carol", "city"), ("dave", "realtime")] {
+            locate(&state, friend, 41.0082, 28.9784).await;
+            share(&state, friend, level).await;
+            befriend(&state, "alice", friend).await;
+        }
+        // Dave's last fix is two hours old
+        state.location_store.backdate("dave", 7_200);
+
+        let cases = [
+            ("?online=true", vec!["bob", "carol"]),
+            ("?online=false", vec!["dave"]),
+            ("?level=realtime", vec!["bob", "dave"]),
+            ("?level=city", vec!["carol"]),
+            ("?online=true&level=realtime", vec!["bob"]),
+            ("?online=false&level=city", vec![]),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(
+                friends_listed(&state, "alice", query).await,
+                expected,
+                "{}",
+                query
+            );
+        }
+
+        let (status, _) = send(&state, get("/users/alice/friends/locations?level=exact")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn shared_with_count_follows_each_viewers_level() {
+        let state = test_state(test_config()).await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        for friend in ["bob", "carol", "erin"] {
+            befriend(&state, "alice", friend).await;
+        }
+        let (status, _) = send(&state, post("/users/alice/observers/dave", json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        // No global level yet: only the temporary shares reveal anything
+        for (friend, level) in [("bob", "city"), ("erin", "realtime")] {
+            let uri = format!("/users/alice/friends/{}/share-until", friend);
+            let body = json!({"level": level, "until": now_secs() + 3_600});
+            let (status, body) = send(&state, post(&uri, body)).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+        let count = |state: AppState| async move {
+            let (status, body) = send(&state, as_user(get("/users/alice"), "alice")).await;
+            assert_eq!(status, StatusCode::OK);
+            body["data"]["sharedWithCount"].clone()
+        };
+        assert_eq!(count(state.clone()).await, 2);
+
+        // Even names only count as sharing, for friends and observers alike
+        share(&state, "alice", "city_name").await;
+        assert_eq!(count(state.clone()).await, 4);
+
+        let (status, _) = send(&state, post("/users/alice/sharing/pause", json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(count(state.clone()).await, 0);
+    }
+
+    #[tokio::test]
+    async fn clearing_requests_keeps_pending_ones_unless_old() {
+        let state = test_state(test_config()).await;
+        let mut ids = std::collections::HashMap::new();
+        for sender in ["alice", "carol", "dave", "erin"] {
+            let request = request_friend(&state, sender, "bob").await;
+            ids.insert(sender, request["id"].as_str().unwrap().to_string());
+        }
+        let sent = request_friend(&state, "bob", "frank").await;
+        let uri = format!("/users/bob/friend-requests/{}/accept", ids["alice"]);
+        assert_eq!(send(&state, post(&uri, json!({}))).await.0, StatusCode::OK);
+        let uri = format!("/users/bob/friend-requests/{}/decline", ids["carol"]);
+        assert_eq!(send(&state, post(&uri, json!({}))).await.0, StatusCode::OK);
+        state
+            .location_store
+            .backdate_request(&ids["erin"], 2 * 86_400);
+
+        let pending_from = |state: AppState| async move {
+            let (_, body) = send(&state, get("/users/bob/friend-requests?sort=oldest")).await;
+            body["data"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|request| request["senderId"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // By default only resolved requests go
+        let clear = |query: &str| {
+            send_json(
+                Method::DELETE,
+                &format!("/users/bob/friend-requests{}", query),
+                json!(null),
+            )
+        };
+        let (status, body) = send(&state, clear("")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["removed"], 2);
+        assert_eq!(pending_from(state.clone()).await, ["erin", "dave"]);
+
+        // With an age, anything older goes, pending or not
+        let (_, body) = send(&state, clear("?older_than_secs=86400")).await;
+        assert_eq!(body["data"]["removed"], 1);
+        assert_eq!(pending_from(state.clone()).await, ["dave"]);
+
+        // Requests Bob sent and the friendship he accepted are untouched
+        let sent = state
+            .location_store
+            .get_friend_request(sent["id"].as_str().unwrap())
+            .await;
+        assert!(sent.is_some());
+        let friends = state.sapphire_client.get_friends("bob").await.unwrap();
+        assert_eq!(friends, ["alice"]);
+    }
+
+    #[tokio::test]
+    async fn friends_see_a_status_until_it_expires() {
+        let state = test_state(test_config()).await;
+        locate(&state, "bob", 41.0082, 28.9784).await;
+        share(&state, "bob", "city").await;
+        befriend(&state, "alice", "bob").await;
+
+        let body = json!({"text": "At the gym", "hours": 1});
+        let (status, body) = send(&state, post("/users/bob/status", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        let expires_at = body["data"]["expiresAt"].as_i64().unwrap();
+        assert!((expires_at - now_secs() - 3_600).abs() <= 1);
+
+        let (_, body) = send(&state, get("/users/alice/friends/locations")).await;
+        assert_eq!(body["data"][0]["status"]["text"], "At the gym");
+
+        // An hour and a bit later
+        state.location_store.backdate("bob", 3_601);
+        let (_, body) = send(&state, get("/users/alice/friends/locations")).await;
+        assert!(body["data"][0]["status"].is_null(), "{}", body);
+        assert_eq!(
+            state
+                .location_store
+                .clear_expired_statuses(now_secs())
+                .await,
+            1
+        );
+        let bob = state.location_store.get_user("bob").await.unwrap();
+        assert!(bob.status.is_none());
+    }
+
+    #[tokio::test]
+    async fn if_match_guards_against_stale_versions() {
+        let state = test_state(test_config()).await;
+        locate(&state, "alice", 41.0082, 28.9784).await;
+        let (_, body) = send(&state, get("/users/alice")).await;
+        let version = body["data"]["version"].as_u64().unwrap();
+
+        let rename = |name: &str, if_match: &str| {
+            let body = json!({"userName": name});
+            with_header(
+                send_json(Method::PUT, "/users/alice", body),
+                "if-match",
+                if_match,
+            )
+        };
+        let (status, body) = send(&state, rename("Stale", &(version - 1).to_string())).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            body["error"],
+            format!(
+                "User was modified: version is {}, not {}",
+                version,
+                version - 1
+            )
+        );
+
+        let (status, _) = send(&state, rename("Alice", &format!("\"{}\"", version))).await;
+        assert_eq!(status, StatusCode::OK);
+        // That version is now stale too
+        let (status, _) = send(&state, rename("Again", &version.to_string())).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(&state, rename("Anyway", "*")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&state, rename("Bad", "v1")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, body) = send(&state, get("/users/alice")).await;
+        assert_eq!(body["data"]["userName"], "Anyway");
+        assert_eq!(body["data"]["version"], version + 2);
+    }
+
+    #[tokio::test]
+    async fn strict_mode_rejects_requests_to_unknown_users() {
+        for strict in [true, false] {
+            let state = test_state(Config {
+                strict_friend_requests: strict,
+                ..test_config()
+            })
+            .await;
+            // Bob is known from his location; Carol has friends on Sapphire only
+            locate(&state, "bob", 41.0082, 28.9784).await;
+            befriend(&state, "carol", "dave").await;
+            request_friend(&state, "alice", "bob").await;
+            request_friend(&state, "alice", "carol").await;
+
+            let body = json!({"senderId": "alice", "receiverId": "nobody"});
+            let (status, body) = send(&state, post("/users/alice/friend-requests", body)).await;
+            if strict {
+                assert_eq!(status, StatusCode::NOT_FOUND);
+                assert_eq!(body["error"], "User not found");
+            } else {
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(body["data"]["receiverId"], "nobody");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn config_is_shown_with_secrets_redacted() {
+        let redis_url = "redis://:hunter2@cache.internal:6379/0";
+        let state = test_state(Config {
+            store_backend: StoreBackend::Redis(redis_url.to_string()),
+            location_ttl_secs: 1_234,
+            strict_friend_requests: true,
+            ..test_config()
+        })
+        .await;
+
+        let (status, _) = send(&state, get("/admin/config")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _, raw) = send_raw(&state, as_admin(get("/admin/config"))).await;
+        assert_eq!(status, StatusCode::OK);
+        let raw = String::from_utf8(raw.to_vec()).unwrap();
+        assert!(
+            !raw.contains(ADMIN_TOKEN) && !raw.contains("hunter2"),
+            "{}",
+            raw
+        );
+
+        let config: Value = serde_json::from_str(&raw).unwrap();
+        let config = &config["data"];
+        assert_eq!(config["adminToken"], "***");
+        assert_eq!(
+            config["storeBackend"],
+            json!({"type": "redis", "location": "***"})
+        );
+        assert_eq!(config["locationTtlSecs"], 1_234);
+        assert_eq!(config["strictFriendRequests"], true);
+    }
+
+    #[tokio::test]
+    async fn invalid_sharing_level_lists_the_valid_ones() {
+        for level in SharingLevel::ALL {
+            let json = serde_json::to_string(&level).unwrap();
+            assert_eq!(serde_json::from_str::<SharingLevel>(&json).unwrap(), level);
+        }
+        let expected = "unknown sharing level `exact`, valid levels are: city_name, city, realtime";
+        let error = serde_json::from_str::<SharingLevel>("\"exact\"").unwrap_err();
+        assert!(error.to_string().starts_with(expected), "{}", error);
+
+        let state = test_state(test_config()).await;
+        let body = json!({"user_id": "alice", "level": "exact"});
+        let (status, body) = send(&state, post("/users/alice/sharing-level", body)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error = body["error"].as_str().unwrap();
+        assert!(error.contains(expected), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn radar_shows_only_users_who_opted_in() {
+        let state = test_state(test_config()).await;
+        let discoverable = |user: &str, enabled: bool| {
+            post(
+                &format!("/users/{}/discoverable", user),
+                json!({"enabled": enabled}),
+            )
+        };
+        for (user, opted_in) in [
+            ("alice", true),
+            ("bob", true),
+            ("carol", false),
+            ("dave", true),
+        ] {
+            locate(&state, user, 41.0082, 28.9784).await;
+            assert_eq!(
+                send(&state, discoverable(user, opted_in)).await.0,
+                StatusCode::OK
+            );
+        }
+        // Friends are on the regular map instead
+        befriend(&state, "alice", "dave").await;
+
+        let radar = |state: AppState, user: &'static str| async move {
+            send(&state, get(&format!("/users/{}/radar", user))).await
+        };
+        let (status, body) = radar(state.clone(), "alice").await;
+        assert_eq!(status, StatusCode::OK);
+        let ids: Vec<&str> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["bob"]);
+        assert!(body["data"][0].get("latitude").is_none());
+
+        let (status, _) = radar(state.clone(), "carol").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        assert_eq!(
+            send(&state, discoverable("bob", false)).await.0,
+            StatusCode::OK
+        );
+        let (_, body) = radar(state.clone(), "alice").await;
+        assert_eq!(body["data"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn favorites_are_flagged_and_listed_first() {
+        let state = test_state(test_config()).await;
+        for friend in ["bob", "carol", "dave"] {
+            locate(&state, friend, 41.0082, 28.9784).await;
+            share(&state, friend, "city").await;
+            befriend(&state, "alice", friend).await;
+        }
+        let favorite = |method: Method, friend: &str| {
+            let uri = format!("/users/alice/friends/{}/favorite", friend);
+            send_json(method, &uri, json!(null))
+        };
+        assert_eq!(
+            send(&state, favorite(Method::POST, "dave")).await.0,
+            StatusCode::OK
+        );
+
+        let (_, body) = send(&state, get("/users/alice/friends/locations")).await;
+        let listed: Vec<(&str, bool)> = body["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| (f["id"].as_str().unwrap(), f["favorite"].as_bool().unwrap()))
+            .collect();
+        assert_eq!(listed, [("dave", true), ("bob", false), ("carol", false)]);
+
+        // Several favorites keep their usual order among themselves
+        assert_eq!(
+            send(&state, favorite(Method::POST, "carol")).await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            friends_listed(&state, "alice", "").await,
+            ["carol", "dave", "bob"]
+        );
+        assert_eq!(
+            send(&state, favorite(Method::DELETE, "dave")).await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            friends_listed(&state, "alice", "").await,
+            ["carol", "bob", "dave"]
+        );
+
+        let (status, body) = send(&state, favorite(Method::POST, "erin")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "Not friends");
+    }
+
+    #[tokio::test]
+    async fn over_precise_coordinates_are_rounded_on_storage() {
+        let state = test_state(Config {
+            coordinate_decimals: 6,
+            ..test_config()
+        })
+        .await;
+        locate(&state, "alice", 41.008238123456, -28.978359876543).await;
+
+        let alice = state.location_store.get_user("alice").await.unwrap();
+        let location = alice.location.unwrap();
+        assert_eq!(
+            (location.latitude, location.longitude),
+            (41.008238, -28.97836)
+        );
+        let (history, _) = state.location_store.get_history("alice", None, 10).await;
+        assert_eq!(
+            (history[0].latitude, history[0].longitude),
+            (41.008238, -28.97836)
+        );
+    }
+
+    #[tokio::test]
+    async fn profiles_skip_unknown_ids_and_keep_order() {
+        let state = test_state(test_config()).await;
+        for user in ["bob", "carol"] {
+            locate(&state, user, 41.0082, 28.9784).await;
+        }
+        state.location_store.backdate("carol", 7_200);
+
+        let body = json!({"ids": ["carol", "ghost", "bob", "carol"]});
+        let (status, body) = send(&state, post("/users/profiles", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!([
+                {"id": "carol", "userName": null, "online": false},
+                {"id": "bob", "userName": null, "online": true},
+            ])
+        );
+
+        let ids: Vec<String> = (0..=MAX_PROFILE_BATCH)
+            .map(|i| format!("user-{}", i))
+            .collect();
+        let (status, _) = send(&state, post("/users/profiles", json!({"ids": ids}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn user_names_are_length_checked_and_unique_when_required() {
+        for unique in [true, false] {
+            let state = test_state(Config {
+                max_user_name_chars: 8,
+                unique_user_names: unique,
+                ..test_config()
+            })
+            .await;
+            let rename = |user: &str, name: &str| {
+                let uri = format!("/users/{}", user);
+                send_json(Method::PUT, &uri, json!({"userName": name}))
+            };
+
+            let (status, body) = send(&state, rename("alice", "Alexandra")).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["error"], "User name too long (max 8 characters)");
+            assert_eq!(send(&state, rename("alice", "Ali")).await.0, StatusCode::OK);
+
+            let (status, body) = send(&state, rename("bob", "ALI")).await;
+            if unique {
+                assert_eq!(status, StatusCode::CONFLICT);
+                assert_eq!(body["error"], "User name ALI is already taken");
+                // Free again once Alice moves on
+                assert_eq!(
+                    send(&state, rename("alice", "Ally")).await.0,
+                    StatusCode::OK
+                );
+                assert_eq!(send(&state, rename("bob", "ALI")).await.0, StatusCode::OK);
+            } else {
+                assert_eq!(status, StatusCode::OK);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn convergence_of_approaching_and_stationary_friends() {
+        let state = test_state(test_config()).await;
+        // Bob and Carol are on the same parallel, about 840m apart
+        let moving = |user: &str, lng: f64, speed: Option<f64>, heading: Option<f64>| {
+            let location = json!({"latitude": 41.0, "longitude": lng, "city": "Istanbul",
+                                  "country": "TR", "speed": speed, "heading": heading});
+            post(
+                &format!("/users/{}/location", user),
+                json!({"user_id": user, "location": location}),
+            )
+        };
+        for user in ["bob", "carol"] {
+            share(&state, user, "realtime").await;
+            befriend(&state, "alice", user).await;
+        }
+        let converge = || {
+            let body = json!({"friendA": "bob", "friendB": "carol", "thresholdMeters": 100});
+            post("/users/alice/friends/converge", body)
+        };
+
+        // Standing still: no closing in, and they never get within 100m
+        assert_eq!(
+            send(&state, moving("bob", 29.0, None, None)).await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&state, moving("carol", 29.01, None, None)).await.0,
+            StatusCode::OK
+        );
+        let (status, body) = send(&state, converge()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let stationary = &body["data"];
+        let distance_km = stationary["distanceKm"].as_f64().unwrap();
+        assert!((distance_km - 0.84).abs() < 0.01, "{}", distance_km);
+        assert_eq!(stationary["approaching"], false);
+        assert_eq!(stationary["closingSpeedMps"], 0.0);
+        assert!((stationary["closestApproachKm"].as_f64().unwrap() - distance_km).abs() < 0.001);
+        assert!(stationary["etaSecs"].is_null());
+
+        // Walking towards each other at 10 m/s each
+        state.location_store.backdate("bob", 60);
+        state.location_store.backdate("carol", 60);
+        assert_eq!(
+            send(&state, moving("bob", 29.0, Some(10.0), Some(90.0)))
+                .await
+                .0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&state, moving("carol", 29.01, Some(10.0), Some(270.0)))
+                .await
+                .0,
+            StatusCode::OK
+        );
+        let (_, body) = send(&state, converge()).await;
+        let approaching = &body["data"];
+        assert_eq!(approaching["approaching"], true);
+        assert!((approaching["closingSpeedMps"].as_f64().unwrap() - 20.0).abs() < 0.01);
+        assert!(approaching["closestApproachKm"].as_f64().unwrap() < 0.001);
+        let eta = approaching["etaSecs"].as_i64().unwrap();
+        let expected = ((distance_km * 1000.0 - 100.0) / 20.0).round() as i64;
+        assert!((eta - expected).abs() <= 1, "{} vs {}", eta, expected);
+    }
+
+    #[tokio::test]
+    async fn friend_track_is_geojson_in_lng_lat_order() {
+        let state = test_state(test_config()).await;
+        for (friend, level) in [("bob", "realtime"), ("carol", "city_name")] {
+            locate(&state, friend, 41.0, 29.0).await;
+            state.location_store.backdate(friend, 60);
+            locate(&state, friend, 41.1, 29.2).await;
+            share(&state, friend, level).await;
+            befriend(&state, "alice", friend).await;
+        }
+        let (_, body) = send(&state, get("/users/bob/history")).await;
+        let timestamps: Vec<Value> = body["data"]["points"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .rev()
+            .map(|point| point["timestamp"].clone())
+            .collect();
+
+        let (status, headers, body) =
+            send_raw(&state, get("/users/alice/friends/bob/track.geojson")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "application/geo+json");
+        let track: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            track,
+            json!({
+                "type": "FeatureCollection",
+                "features": [{
+                    "type": "Feature",
+                    "geometry": {"type": "LineString", "coordinates": [[29.0, 41.0], [29.2, 41.1]]},
+                    "properties": {"friendId": "bob", "sharingLevel": "realtime", "timestamps": timestamps},
+                }],
+            })
+        );
+
+        // City names only: nothing to draw
+        let (status, body) = send(&state, get("/users/alice/friends/carol/track.geojson")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"type": "FeatureCollection", "features": []}));
+
+        let (status, _) = send(&state, get("/users/dave/friends/bob/track.geojson")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn require_existing_user_decides_whether_unknown_ids_are_created() {
+        let body = json!({
+            "user_id": "ghost",
+            "location": {"latitude": 41.0, "longitude": 29.0, "city": "Istanbul", "country": "TR"}
+        });
+
+        let strict = test_state(Config {
+            require_existing_user: true,
+            ..test_config()
+        })
+        .await;
+        let (status, json) = send(&strict, post("/users/ghost/location", body.clone())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(json["error"], "User not found");
+        assert!(strict.location_store.get_user("ghost").await.is_none());
+
+        // Once they have a profile, their updates go through
+        let (status, _) = send(
+            &strict,
+            send_json(Method::PUT, "/users/ghost", json!({"userName": "Ghost"})),
         )
-        .layer(CorsLayer::permissive())
-        .with_state(state);
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&strict, post("/users/ghost/location", body.clone())).await;
+        assert_eq!(status, StatusCode::OK);
 
-    let port = std::env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let addr = format!("0.0.0.0:{}", port);
+        let lenient = test_state(test_config()).await;
+        let (status, _) = send(&lenient, post("/users/ghost/location", body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(lenient.location_store.get_user("ghost").await.is_some());
+    }
 
-    info!("✅ Server listening on {}", addr);
-    info!("📍 Location sharing with in-memory friend storage");
-    info!("🔐 Celo UID verification enabled (dev mode)");
+    #[tokio::test]
+    async fn request_with_reports_direction_from_either_side() {
+        let state = test_state(test_config()).await;
+        let (status, body) = send(&state, get("/users/alice/friend-requests/with/bob")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"], Value::Null);
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+        let request = request_friend(&state, "alice", "bob").await;
+        let (status, body) = send(&state, get("/users/alice/friend-requests/with/bob")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["direction"], "outgoing");
+        assert_eq!(body["data"]["request"]["id"], request["id"]);
+        let (_, body) = send(&state, get("/users/bob/friend-requests/with/alice")).await;
+        assert_eq!(body["data"]["direction"], "incoming");
+        assert_eq!(body["data"]["request"]["id"], request["id"]);
 
-    Ok(())
+        // Once answered, it's no longer pending
+        let uri = format!(
+            "/users/bob/friend-requests/{}/decline",
+            request["id"].as_str().unwrap()
+        );
+        let (status, _) = send(&state, post(&uri, json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(&state, get("/users/alice/friend-requests/with/bob")).await;
+        assert_eq!(body["data"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn friend_details_link_accepted_requests_but_not_imports() {
+        let state = test_state(test_config()).await;
+        let request = request_friend(&state, "alice", "bob").await;
+        let uri = format!(
+            "/users/bob/friend-requests/{}/accept",
+            request["id"].as_str().unwrap()
+        );
+        let (status, _) = send(&state, post(&uri, json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        befriend(&state, "alice", "carol").await;
+
+        let (status, body) = send(&state, get("/users/alice/friends/details")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body["data"],
+            json!([
+                {"friendId": "bob", "requestId": request["id"]},
+                {"friendId": "carol", "requestId": null},
+            ])
+        );
+        // Both sides of the friendship carry the same request
+        let (_, body) = send(&state, get("/users/bob/friends/details")).await;
+        assert_eq!(
+            body["data"],
+            json!([{"friendId": "alice", "requestId": request["id"]}])
+        );
+    }
+
+    #[tokio::test]
+    async fn muting_hides_the_friend_only_from_the_muter() {
+        let state = test_state(test_config()).await;
+        for user in ["alice", "bob"] {
+            locate(&state, user, 41.0082, 28.9784).await;
+            share(&state, user, "realtime").await;
+        }
+        befriend(&state, "alice", "bob").await;
+
+        let (status, _) = send(&state, post("/users/alice/friends/bob/mute", json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(friends_listed(&state, "alice", "").await.is_empty());
+        assert_eq!(friends_listed(&state, "bob", "").await, ["alice"]);
+
+        let (status, body) = send(
+            &state,
+            send_json(Method::DELETE, "/users/alice/friends/bob/mute", json!({})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["removed"], true);
+        assert_eq!(friends_listed(&state, "alice", "").await, ["bob"]);
+    }
 }
@@ -0,0 +1,178 @@
+use crate::config::AppVersion;
+use crate::{ApiResponse, AppState};
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::any::Any;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tracing::{error, info_span, warn, Instrument};
+
+/// Seconds clients are told to wait before retrying during maintenance
+const MAINTENANCE_RETRY_AFTER_SECS: u64 = 60;
+
+/// Reject writes with `503` while maintenance mode is on
+/// Reads keep working. Only layered on the non-admin routes, so operators can
+/// still use the admin API, including to switch it off.
+pub async fn maintenance_guard(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_read = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+
+    if state.maintenance.load(Ordering::Relaxed) && !is_read {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(
+                header::RETRY_AFTER,
+                MAINTENANCE_RETRY_AFTER_SECS.to_string(),
+            )],
+            Json(ApiResponse::<()>::err(
+                "Server is in maintenance mode; writes are disabled".to_string(),
+            )),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Persist the store after every successful write
+/// Failures are logged rather than returned, since the write itself succeeded
+pub async fn persist_after_write(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_read = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let response = next.run(request).await;
+
+    if !is_read && response.status().is_success() && state.persister.is_enabled() {
+        let store = state.location_store.clone();
+        let persister = state.persister.clone();
+        let flushed = tokio::task::spawn_blocking(move || persister.flush(&store)).await;
+        match flushed {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("⚠️ Failed to persist store: {:#}", e),
+            Err(e) => warn!("⚠️ Store flush task failed: {}", e),
+        }
+    }
+
+    response
+}
+
+/// Reject clients older than `MIN_APP_VERSION` with `426`, and flag clients
+/// older than `DEPRECATED_BELOW_APP_VERSION` with `Deprecation`/`Sunset` headers
+/// Requests without a parseable `X-App-Version` header are let through untouched
+pub async fn app_version_gate(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let policy = &state.config.version_policy;
+    let version = request
+        .headers()
+        .get("x-app-version")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<AppVersion>().ok());
+
+    let Some(version) = version else {
+        return next.run(request).await;
+    };
+
+    if policy
+        .min_version
+        .as_ref()
+        .is_some_and(|min| &version < min)
+    {
+        return (
+            StatusCode::UPGRADE_REQUIRED,
+            Json(ApiResponse::<()>::err(
+                "This app version is no longer supported; please update".to_string(),
+            )),
+        )
+            .into_response();
+    }
+
+    let deprecated = policy
+        .deprecated_below
+        .as_ref()
+        .is_some_and(|below| &version < below);
+
+    let mut response = next.run(request).await;
+    if deprecated {
+        let headers = response.headers_mut();
+        headers.insert("deprecation", HeaderValue::from_static("true"));
+        if let Some(sunset) = policy
+            .sunset
+            .as_deref()
+            .and_then(|sunset| HeaderValue::from_str(sunset).ok())
+        {
+            headers.insert("sunset", sunset);
+        }
+    }
+    response
+}
+
+/// Answer `408` when a handler takes longer than `REQUEST_TIMEOUT_SECS`
+/// This only bounds the time until the response starts: a streamed body or an
+/// upgraded WebSocket carries on for as long as it needs
+pub async fn request_timeout(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limit = Duration::from_secs(state.config.request_timeout_secs);
+    match tokio::time::timeout(limit, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!("⏱️ Request timed out after {}s", limit.as_secs());
+            (
+                StatusCode::REQUEST_TIMEOUT,
+                Json(ApiResponse::<()>::err("Request timed out".to_string())),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Run each request in a span carrying its `X-Request-Id`, so everything
+/// logged while handling it (including panics) can be traced back to it
+pub async fn request_span(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+    let span = info_span!("request", id = %request_id);
+    next.run(request).instrument(span).await
+}
+
+/// Response for a handler that panicked, for `CatchPanicLayer`
+/// Without it axum drops the connection, which clients can't tell apart
+/// from a network error
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    error!("💥 Handler panicked: {}", message);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ApiResponse::<()>::err("internal error".to_string())),
+    )
+        .into_response()
+}
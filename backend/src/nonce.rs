@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use uuid::Uuid;
+
+/// A nonce handed out to a user
+struct IssuedNonce {
+    user_id: String,
+    expires_at: i64,
+}
+
+/// Single-use, short-lived nonces that make `/auth/verify` bodies
+/// impossible to replay
+/// Kept in memory only: a restart simply invalidates outstanding nonces
+pub struct NonceStore {
+    ttl_secs: i64,
+    nonces: RwLock<HashMap<String, IssuedNonce>>,
+}
+
+impl NonceStore {
+    pub fn new(ttl_secs: i64) -> Self {
+        Self {
+            ttl_secs,
+            nonces: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Issue a fresh nonce for a user, returning it with its expiry
+    pub fn issue(&self, user_id: &str, now: i64) -> (String, i64) {
+        let mut nonces = self.nonces.write().unwrap();
+        // Drop expired nonces so abandoned logins don't pile up
+        nonces.retain(|_, issued| issued.expires_at > now);
+
+        let nonce = Uuid::new_v4().simple().to_string();
+        let expires_at = now + self.ttl_secs;
+        nonces.insert(
+            nonce.clone(),
+            IssuedNonce {
+                user_id: user_id.to_string(),
+                expires_at,
+            },
+        );
+        (nonce, expires_at)
+    }
+
+    /// Use up a nonce
+    /// Returns false if it was never issued, was issued to someone else,
+    /// has expired, or was already used. Any presented nonce is removed,
+    /// so it can't be tried twice.
+    pub fn consume(&self, user_id: &str, nonce: &str, now: i64) -> bool {
+        let mut nonces = self.nonces.write().unwrap();
+        nonces
+            .remove(nonce)
+            .is_some_and(|issued| issued.user_id == user_id && issued.expires_at > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn fresh_nonce_is_accepted_once() {
+        let store = NonceStore::new(60);
+        let (nonce, expires_at) = store.issue("alice", NOW);
+        assert_eq!(expires_at, NOW + 60);
+        assert!(store.consume("alice", &nonce, NOW + 1));
+        assert!(!store.consume("alice", &nonce, NOW + 2));
+    }
+
+    #[test]
+    fn expired_nonce_is_rejected() {
+        let store = NonceStore::new(60);
+        let (nonce, _) = store.issue("alice", NOW);
+        assert!(!store.consume("alice", &nonce, NOW + 60));
+    }
+
+    #[test]
+    fn nonce_only_works_for_its_user() {
+        let store = NonceStore::new(60);
+        let (nonce, _) = store.issue("alice", NOW);
+        assert!(!store.consume("mallory", &nonce, NOW + 1));
+        // Presenting it burned it, even for the wrong user
+        assert!(!store.consume("alice", &nonce, NOW + 1));
+        assert!(!store.consume("alice", "never-issued", NOW + 1));
+    }
+}
@@ -0,0 +1,225 @@
+use crate::extractors::SortOrder;
+use crate::location_store::{
+    CityVisit, FriendRequest, FriendRequestStatus, RequestDirection, SharingGroup,
+};
+use crate::sapphire_client::Friendship;
+use crate::schedule::{ScheduleRule, ScheduledLevel, TimeOfDay, Weekday};
+use crate::{
+    AcceptedFriendRequest, AccepterProfile, AddFriendRequest, AuthNonce, BulkResponseItem,
+    BulkResponseResult, ConvergeRequest, Convergence, CreateGroupRequest, DiscoveryHashesRequest,
+    ExportProfile, ExportRecord, FriendEta, FriendLocation, FriendVisibility, FriendshipStatus,
+    GuestUser, HiddenReason, LocationData, LocationHistoryPage, MergeUsersRequest,
+    MuteFriendRequest, PendingRequestWith, PrivacySimulation, Profile, ProfilesRequest,
+    PublicProfile, RadarEntry, RegisterDeviceKeyRequest, RequestAction, RespondBulkRequest,
+    SendFriendRequestRequest, SentFriendRequest, SetDiscoverableRequest, SetMaintenanceRequest,
+    SetNearbyOnlyRequest, SetStatusRequest, SetTimezoneRequest, ShareUntilRequest, SharedLocation,
+    SharingAuditEntry, SharingLevel, SharingSchedule, SimulatePrivacyRequest, UpdateGroupRequest,
+    UpdateLocationRequest, UpdateProfileRequest, UpdateSharingLevelRequest, User, UserDataExport,
+    UserStatus, VerifyBatchItem, VerifyBatchRequest, VerifyBatchResult, VerifySelfAuthRequest,
+};
+use axum::Json;
+use utoipa::OpenApi;
+
+/// OpenAPI description of the REST API, generated from the handler and type
+/// definitions so serde renames (`userName`, `sharingLevel`, ...) stay accurate
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Linda ROFL Backend"),
+    paths(
+        crate::health,
+        crate::get_auth_nonce,
+        crate::verify_self_auth,
+        crate::verify_self_auth_batch,
+        crate::create_guest,
+        crate::get_profiles,
+        crate::get_profile,
+        crate::update_profile,
+        crate::set_status,
+        crate::set_timezone,
+        crate::export_user_data,
+        crate::export_user_data_stream,
+        crate::update_location,
+        crate::get_location_history,
+        crate::get_cities,
+        crate::get_distance,
+        crate::register_device_key,
+        crate::update_sharing_level,
+        crate::pause_sharing,
+        crate::resume_sharing,
+        crate::get_sharing_audit,
+        crate::simulate_privacy,
+        crate::raise_sos,
+        crate::clear_sos,
+        crate::get_friends,
+        crate::count_friends,
+        crate::get_friend_details,
+        crate::add_friend,
+        crate::remove_friend,
+        crate::get_friends_locations,
+        crate::set_nearby_only,
+        crate::stream_events,
+        crate::get_friend_location,
+        crate::get_friend_eta,
+        crate::get_friend_track,
+        crate::get_convergence,
+        crate::get_friendship_status,
+        crate::get_asymmetric_friends,
+        crate::get_friends_visibility,
+        crate::add_favorite,
+        crate::remove_favorite,
+        crate::mute_friend,
+        crate::unmute_friend,
+        crate::share_until,
+        crate::get_groups,
+        crate::create_group,
+        crate::update_group,
+        crate::delete_group,
+        crate::add_group_member,
+        crate::remove_group_member,
+        crate::get_schedule,
+        crate::set_schedule,
+        crate::add_observer,
+        crate::remove_observer,
+        crate::get_observers,
+        crate::send_friend_request,
+        crate::get_friend_requests,
+        crate::clear_friend_requests,
+        crate::accept_friend_request,
+        crate::decline_friend_request,
+        crate::get_request_with,
+        crate::respond_bulk,
+        crate::register_discovery_hashes,
+        crate::match_discovery_hashes,
+        crate::set_discoverable,
+        crate::get_radar,
+        crate::merge_users,
+        crate::admin_revoke_friendship,
+        crate::admin_request_between,
+        crate::get_config,
+        crate::get_maintenance,
+        crate::set_maintenance,
+    ),
+    components(schemas(
+        SharingLevel,
+        Friendship,
+        MuteFriendRequest,
+        LocationData,
+        User,
+        UserStatus,
+        SetStatusRequest,
+        SetTimezoneRequest,
+        Profile,
+        ProfilesRequest,
+        PublicProfile,
+        GuestUser,
+        SharedLocation,
+        FriendLocation,
+        HiddenReason,
+        FriendRequest,
+        FriendRequestStatus,
+        SortOrder,
+        AcceptedFriendRequest,
+        AccepterProfile,
+        AuthNonce,
+        VerifySelfAuthRequest,
+        VerifyBatchItem,
+        VerifyBatchRequest,
+        VerifyBatchResult,
+        UpdateLocationRequest,
+        LocationHistoryPage,
+        CityVisit,
+        RegisterDeviceKeyRequest,
+        UpdateSharingLevelRequest,
+        ShareUntilRequest,
+        SharingGroup,
+        CreateGroupRequest,
+        UpdateGroupRequest,
+        SharingSchedule,
+        ScheduleRule,
+        ScheduledLevel,
+        Weekday,
+        TimeOfDay,
+        UpdateProfileRequest,
+        UserDataExport,
+        ExportProfile,
+        ExportRecord,
+        SetNearbyOnlyRequest,
+        SetDiscoverableRequest,
+        RadarEntry,
+        AddFriendRequest,
+        SendFriendRequestRequest,
+        SentFriendRequest,
+        RequestAction,
+        BulkResponseItem,
+        RespondBulkRequest,
+        BulkResponseResult,
+        DiscoveryHashesRequest,
+        SharingAuditEntry,
+        SimulatePrivacyRequest,
+        PrivacySimulation,
+        FriendVisibility,
+        FriendEta,
+        ConvergeRequest,
+        Convergence,
+        FriendshipStatus,
+        PendingRequestWith,
+        RequestDirection,
+        MergeUsersRequest,
+        SetMaintenanceRequest,
+    ))
+)]
+pub struct ApiDoc;
+
+/// Serve the OpenAPI spec
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn spec() -> Value {
+        serde_json::to_value(ApiDoc::openapi()).unwrap()
+    }
+
+    #[test]
+    fn key_routes_are_documented() {
+        let spec = spec();
+        for (path, method) in [
+            ("/health", "get"),
+            ("/users/{user_id}/location", "post"),
+            ("/users/{user_id}/sharing-level", "post"),
+            ("/users/{user_id}/friends", "get"),
+            ("/users/{user_id}/friends/locations", "get"),
+            ("/users/{user_id}/friends/{friend_id}", "get"),
+            ("/users/{user_id}/friend-requests", "post"),
+            (
+                "/users/{user_id}/friend-requests/{request_id}/accept",
+                "post",
+            ),
+            ("/users/{user_id}/export", "get"),
+        ] {
+            assert!(
+                spec["paths"][path][method].is_object(),
+                "{} {} is missing",
+                method,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn schemas_use_the_renamed_fields() {
+        let spec = spec();
+        let schemas = &spec["components"]["schemas"];
+        for schema in ["User", "FriendLocation"] {
+            let properties = &schemas[schema]["properties"];
+            assert!(properties["userName"].is_object(), "{}", schema);
+            assert!(properties["sharingLevel"].is_object(), "{}", schema);
+            assert!(properties.get("user_name").is_none(), "{}", schema);
+            assert!(properties.get("sharing_level").is_none(), "{}", schema);
+        }
+    }
+}
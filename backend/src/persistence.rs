@@ -0,0 +1,179 @@
+use crate::config::{StoreBackend, WalConfig};
+use crate::location_store::{LocationStore, StoreSnapshot};
+use crate::wal::WalPersistence;
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Redis key the store snapshot is kept under
+const REDIS_SNAPSHOT_KEY: &str = "linda:store";
+
+/// Somewhere a store snapshot can be saved and loaded from
+pub trait Persistence: Send + Sync {
+    /// Load the last saved snapshot, or `None` if nothing was saved yet
+    fn load(&self) -> Result<Option<StoreSnapshot>>;
+    fn save(&self, snapshot: &StoreSnapshot) -> Result<()>;
+}
+
+/// Snapshot kept as a JSON file, replaced atomically on each save
+pub struct FilePersistence {
+    path: PathBuf,
+}
+
+impl FilePersistence {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl Persistence for FilePersistence {
+    fn load(&self) -> Result<Option<StoreSnapshot>> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .with_context(|| format!("Corrupt store file {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Can't read {}", self.path.display())),
+        }
+    }
+
+    fn save(&self, snapshot: &StoreSnapshot) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Write then rename, so a crash mid-write never leaves a torn file
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(snapshot)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Snapshot kept as a JSON string in Redis
+pub struct RedisPersistence {
+    client: redis::Client,
+}
+
+impl RedisPersistence {
+    pub fn new(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).context("Invalid REDIS_URL")?;
+        Ok(Self { client })
+    }
+}
+
+impl Persistence for RedisPersistence {
+    fn load(&self) -> Result<Option<StoreSnapshot>> {
+        let mut conn = self.client.get_connection()?;
+        let json: Option<String> = redis::cmd("GET").arg(REDIS_SNAPSHOT_KEY).query(&mut conn)?;
+        json.map(|json| serde_json::from_str(&json).context("Corrupt store snapshot in Redis"))
+            .transpose()
+    }
+
+    fn save(&self, snapshot: &StoreSnapshot) -> Result<()> {
+        let mut conn = self.client.get_connection()?;
+        redis::cmd("SET")
+            .arg(REDIS_SNAPSHOT_KEY)
+            .arg(serde_json::to_string(snapshot)?)
+            .query::<()>(&mut conn)?;
+        Ok(())
+    }
+}
+
+/// Flushes the store to the configured backend
+/// With the `memory` backend nothing is persisted and flushing is a no-op
+pub struct Persister {
+    backend: Option<Box<dyn Persistence>>,
+    // Held while snapshotting and saving, so an older snapshot can never
+    // overwrite a newer one
+    flush_lock: Mutex<()>,
+}
+
+impl Persister {
+    pub fn from_config(backend: &StoreBackend, wal: &WalConfig) -> Result<Self> {
+        let backend: Option<Box<dyn Persistence>> = match backend {
+            StoreBackend::Memory => None,
+            StoreBackend::File(path) if wal.enabled => Some(Box::new(WalPersistence::new(
+                path.clone(),
+                wal.compact_bytes,
+            ))),
+            StoreBackend::File(path) => Some(Box::new(FilePersistence::new(path.clone()))),
+            StoreBackend::Redis(url) => Some(Box::new(RedisPersistence::new(url)?)),
+        };
+        Ok(Self {
+            backend,
+            flush_lock: Mutex::new(()),
+        })
+    }
+
+    /// Build the store from the last saved snapshot, or empty if there is none
+    pub fn load_store(&self) -> Result<LocationStore> {
+        let snapshot = match &self.backend {
+            Some(backend) => backend.load()?,
+            None => None,
+        };
+        Ok(snapshot
+            .map(LocationStore::from_snapshot)
+            .unwrap_or_else(LocationStore::new))
+    }
+
+    /// Save the current state of the store
+    pub fn flush(&self, store: &LocationStore) -> Result<()> {
+        let Some(backend) = &self.backend else {
+            return Ok(());
+        };
+        let _guard = self.flush_lock.lock().unwrap();
+        backend.save(&store.snapshot())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.backend.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RequestLimits;
+    use crate::location_store::UserPatch;
+
+    #[tokio::test]
+    async fn file_backend_survives_a_restart() {
+        let path = std::env::temp_dir().join(format!("store-{}.json", uuid::Uuid::new_v4()));
+        let backend = StoreBackend::File(path.clone());
+        let wal = WalConfig {
+            enabled: false,
+            compact_bytes: 0,
+        };
+
+        let persister = Persister::from_config(&backend, &wal).unwrap();
+        let store = persister.load_store().unwrap();
+        let patch = UserPatch {
+            user_name: Some(Some("Alice".to_string())),
+            ..Default::default()
+        };
+        store.update_user("alice", patch, None).await.unwrap();
+        let limits = RequestLimits {
+            max_outgoing_pending: 10,
+            max_incoming_pending: 10,
+        };
+        let request = store
+            .send_friend_request("alice", "bob", None, &limits)
+            .await
+            .unwrap();
+        persister.flush(&store).unwrap();
+        drop((store, persister));
+
+        // As if the server had restarted
+        let restored = Persister::from_config(&backend, &wal)
+            .unwrap()
+            .load_store()
+            .unwrap();
+        let alice = restored.get_user("alice").await.unwrap();
+        assert_eq!(alice.user_name.as_deref(), Some("Alice"));
+        let pending = restored.get_friend_requests("bob").await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, request.id);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
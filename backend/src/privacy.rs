@@ -0,0 +1,650 @@
+use crate::config::PrivacyDecay;
+use crate::schedule::ScheduledLevel;
+use crate::timezone;
+use crate::{FriendLocation, HiddenReason, LocationData, SharedLocation, SharingLevel, User};
+
+/// Meters per degree of latitude
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Decimal places kept at City level (~1km)
+const CITY_DECIMALS: i32 = 2;
+
+/// Rough radius a city name narrows a location down to
+const CITY_NAME_PRECISION_METERS: f64 = 10_000.0;
+
+/// Per-viewer state layered on top of a user's global sharing level
+#[derive(Debug, Clone, Default)]
+pub struct SharingOverrides {
+    /// The user paused all sharing
+    pub paused: bool,
+    /// The user has an unexpired SOS
+    pub sos: bool,
+    /// Unexpired temporary level the user granted this viewer
+    pub temporary: Option<SharingLevel>,
+    /// Level the user's schedule puts in force toward this viewer right now
+    pub scheduled: Option<ScheduledLevel>,
+    /// Level of the sharing group the user put this viewer in
+    pub group: Option<SharingLevel>,
+}
+
+/// The level a user effectively shares with one viewer, or `None` if nothing
+///
+/// Layers are resolved in this order, and the first that applies wins:
+/// 1. Paused: nothing at all. The most restrictive choice always wins, even
+///    over an SOS, so pausing is a guaranteed off switch
+/// 2. SOS: Realtime, until it expires or is cleared
+/// 3. Temporary share with this viewer: its level, until it expires
+/// 4. A scheduled rule covering this viewer right now: the most restrictive
+///    one's level, which may hide the location altogether
+/// 5. The sharing group the user put this viewer in: the group's level
+/// 6. The user's global level (`None` if never set)
+pub fn effective_level(user: &User, overrides: &SharingOverrides) -> Option<SharingLevel> {
+    if overrides.paused {
+        return None;
+    }
+    if overrides.sos {
+        return Some(SharingLevel::Realtime);
+    }
+    if let Some(level) = &overrides.temporary {
+        return Some(level.clone());
+    }
+    if let Some(scheduled) = overrides.scheduled {
+        return scheduled.sharing_level();
+    }
+    overrides
+        .group
+        .clone()
+        .or_else(|| user.sharing_level.clone())
+}
+
+/// Whether a precision is coarse enough to show an approximate address
+/// Realtime viewers already get the exact pin, so the label is only added
+/// where it is no more precise than what the viewer may see
+pub fn allows_approximate_address(precision: Option<Precision>) -> bool {
+    matches!(precision, Some(Precision::City | Precision::CityName))
+}
+
+/// Smallest cosine used when scaling longitude, so cells stay finite near the poles
+const MIN_LATITUDE_COSINE: f64 = 0.01;
+
+/// Approximate precision of shared coordinates in meters (0 means exact)
+/// `rounding_meters` is the configured City ground resolution, if any
+pub fn precision_meters(level: &SharingLevel, rounding_meters: Option<f64>) -> f64 {
+    match level {
+        SharingLevel::CityName => CITY_NAME_PRECISION_METERS,
+        SharingLevel::City => {
+            rounding_meters.unwrap_or(METERS_PER_DEGREE / 10f64.powi(CITY_DECIMALS))
+        }
+        SharingLevel::Realtime => 0.0,
+    }
+}
+
+/// Coarsen coordinates to City level
+///
+/// By default this rounds to 2 decimal places, which covers less ground
+/// east-west the further a point is from the equator. With `rounding_meters`
+/// set, the longitude step is scaled by `cos(latitude)` so every cell spans
+/// roughly the same ground distance in both directions.
+pub fn round_to_city(latitude: f64, longitude: f64, rounding_meters: Option<f64>) -> (f64, f64) {
+    match rounding_meters {
+        None => {
+            let factor = 10f64.powi(CITY_DECIMALS);
+            (
+                (latitude * factor).round() / factor,
+                (longitude * factor).round() / factor,
+            )
+        }
+        Some(meters) => {
+            let lat_step = meters / METERS_PER_DEGREE;
+            let rounded_lat = (latitude / lat_step).round() * lat_step;
+
+            // Scale by the rounded latitude so all points in a cell share one step
+            let cosine = rounded_lat.to_radians().cos().max(MIN_LATITUDE_COSINE);
+            let lng_step = meters / (METERS_PER_DEGREE * cosine);
+            let rounded_lng = (longitude / lng_step).round() * lng_step;
+
+            (
+                rounded_lat.clamp(-90.0, 90.0),
+                rounded_lng.clamp(-180.0, 180.0),
+            )
+        }
+    }
+}
+
+/// Coordinates shown at `level`, or `None`s when it shares labels only
+pub fn filter_coordinates(
+    level: &SharingLevel,
+    latitude: f64,
+    longitude: f64,
+    rounding_meters: Option<f64>,
+) -> (Option<f64>, Option<f64>) {
+    match level {
+        SharingLevel::CityName => {
+            // Labels only, no map pin
+            (None, None)
+        }
+        SharingLevel::City => {
+            // Round to city level (~1km)
+            let (lat, lng) = round_to_city(latitude, longitude, rounding_meters);
+            (Some(lat), Some(lng))
+        }
+        SharingLevel::Realtime => {
+            // Keep exact coordinates
+            (Some(latitude), Some(longitude))
+        }
+    }
+}
+
+/// Whether a location fix is recent enough to be considered current
+pub fn is_fresh(location: &LocationData, ttl_secs: i64, now: i64) -> bool {
+    location
+        .timestamp
+        .is_some_and(|timestamp| now - timestamp <= ttl_secs)
+}
+
+/// The user's location, if the fix is at most `max_age_secs` old
+/// For realtime features (ETA, convergence, radar), which need to know
+/// where someone is now rather than where they last were
+pub fn fresh_location(user: &User, max_age_secs: i64, now: i64) -> Option<LocationData> {
+    user.location
+        .clone()
+        .filter(|location| is_fresh(location, max_age_secs, now))
+}
+
+/// How much of a location is shown, from most to least precise
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precision {
+    Realtime,
+    City,
+    CityName,
+    /// Country label only
+    Country,
+}
+
+impl Precision {
+    const COARSEST: Self = Self::Country;
+
+    fn of(level: &SharingLevel) -> Self {
+        match level {
+            SharingLevel::Realtime => Self::Realtime,
+            SharingLevel::City => Self::City,
+            SharingLevel::CityName => Self::CityName,
+        }
+    }
+
+    fn coarser_by(self, steps: usize) -> Self {
+        match (self as usize + steps).min(Self::COARSEST as usize) {
+            0 => Self::Realtime,
+            1 => Self::City,
+            2 => Self::CityName,
+            _ => Self::Country,
+        }
+    }
+}
+
+/// How much of a fix `age_secs` old is shown at `level`, or `None` once
+/// it has faded out entirely
+///
+/// Up to `start_secs` the level applies as is. Between the thresholds the
+/// steps from the level down to country-only are spread evenly, so a
+/// Realtime fix goes City, city name, then country as it ages, and a
+/// CityName fix goes straight to country. Past `hide_secs` it's hidden.
+/// Fixes of unknown age are left alone.
+pub fn decayed_precision(
+    level: &SharingLevel,
+    age_secs: Option<i64>,
+    decay: Option<&PrivacyDecay>,
+) -> Option<Precision> {
+    let precision = Precision::of(level);
+    let (Some(age), Some(decay)) = (age_secs, decay) else {
+        return Some(precision);
+    };
+    if age <= decay.start_secs {
+        return Some(precision);
+    }
+    if age > decay.hide_secs {
+        return None;
+    }
+    let faded = (age - decay.start_secs) as f64 / (decay.hide_secs - decay.start_secs) as f64;
+    let steps = Precision::COARSEST as usize - precision as usize;
+    Some(precision.coarser_by((faded * steps as f64).ceil() as usize))
+}
+
+/// How much of a user's location one viewer gets right now, or `None` if
+/// nothing: their effective level (see `effective_level`), faded by age
+/// when `decay` is set. An SOS always shows the last fix exactly, however old.
+pub fn shown_precision(
+    user: &User,
+    overrides: &SharingOverrides,
+    decay: Option<&PrivacyDecay>,
+    now: i64,
+) -> Option<Precision> {
+    let level = effective_level(user, overrides)?;
+    let age_secs = user
+        .location
+        .as_ref()
+        .and_then(|location| location.timestamp)
+        .map(|timestamp| now - timestamp);
+    decayed_precision(&level, age_secs, decay.filter(|_| !overrides.sos))
+}
+
+/// Whether one viewer currently gets coordinates for a user: a fresh fix,
+/// shown at Realtime or City precision once decay is applied
+pub fn shows_coordinates(
+    user: &User,
+    overrides: &SharingOverrides,
+    decay: Option<&PrivacyDecay>,
+    ttl_secs: i64,
+    now: i64,
+) -> bool {
+    let fresh = user
+        .location
+        .as_ref()
+        .is_some_and(|location| is_fresh(location, ttl_secs, now));
+    fresh
+        && matches!(
+            shown_precision(user, overrides, decay, now),
+            Some(Precision::Realtime | Precision::City)
+        )
+}
+
+/// Apply privacy filtering to a friend's location for one viewer, at the
+/// precision `shown_precision` allows
+pub fn apply_privacy_filter(
+    friend: User,
+    overrides: &SharingOverrides,
+    rounding_meters: Option<f64>,
+    decay: Option<&PrivacyDecay>,
+    now: i64,
+) -> FriendLocation {
+    let level = effective_level(&friend, overrides);
+    let precision = shown_precision(&friend, overrides, decay, now);
+    let hidden_reason = hidden_reason(
+        overrides,
+        level.as_ref(),
+        friend.location.is_some(),
+        precision.is_none(),
+    );
+
+    let location = friend.location.and_then(|location| {
+        // No sharing level set (or paused), or faded out: hide location
+        let precision = precision?;
+        let (latitude, longitude) = match precision {
+            Precision::Realtime => (Some(location.latitude), Some(location.longitude)),
+            Precision::City => {
+                let (lat, lng) =
+                    round_to_city(location.latitude, location.longitude, rounding_meters);
+                (Some(lat), Some(lng))
+            }
+            Precision::CityName | Precision::Country => (None, None),
+        };
+
+        Some(SharedLocation {
+            latitude,
+            longitude,
+            city: location.city.filter(|_| precision < Precision::Country),
+            country: location.country,
+            timestamp: location.timestamp,
+        })
+    });
+
+    // Only alongside at least a city: a local time gives away the zone
+    let local_time = precision.filter(|p| *p < Precision::Country).and(
+        friend
+            .last_updated
+            .zip(friend.timezone.as_deref())
+            .and_then(|(timestamp, tz)| timezone::local_time(timestamp, tz)),
+    );
+    FriendLocation {
+        id: friend.id,
+        user_name: friend.user_name,
+        sharing_level: friend.sharing_level,
+        location,
+        last_updated: friend.last_updated,
+        age_secs: None,
+        is_fresh: false,
+        sos: overrides.sos && !overrides.paused,
+        approximate_address: None,
+        distance_km: None,
+        hidden_reason,
+        status: level.and(friend.status),
+        version: friend.version,
+        favorite: false,
+        local_time,
+    }
+}
+
+/// Why a friend's location is hidden from a viewer, or `None` if it's shown
+/// Checked in the same order as `effective_level`, so the reason given is
+/// the one that actually hid it
+fn hidden_reason(
+    overrides: &SharingOverrides,
+    level: Option<&SharingLevel>,
+    has_location: bool,
+    hidden: bool,
+) -> Option<HiddenReason> {
+    if overrides.paused {
+        Some(HiddenReason::Paused)
+    } else if level.is_none() && overrides.scheduled == Some(ScheduledLevel::Hidden) {
+        Some(HiddenReason::Scheduled)
+    } else if level.is_none() {
+        Some(HiddenReason::LevelNone)
+    } else if !has_location {
+        Some(HiddenReason::NoLocation)
+    } else if hidden {
+        // Shared and present, so only its age can have hidden it
+        Some(HiddenReason::Expired)
+    } else {
+        None
+    }
+}
+
+/// Fill in how old the visible location is and whether it's still fresh,
+/// so clients don't have to do the staleness math themselves
+pub fn annotate_freshness(friend: &mut FriendLocation, ttl_secs: i64, now: i64) {
+    friend.age_secs = friend
+        .location
+        .as_ref()
+        .and_then(|loc| loc.timestamp)
+        .map(|timestamp| (now - timestamp).max(0));
+    friend.is_fresh = friend.age_secs.is_some_and(|age| age <= ttl_secs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+    const TTL_SECS: i64 = 300;
+
+    fn user(level: Option<SharingLevel>, fix_age_secs: Option<i64>) -> User {
+        User {
+            id: "friend".to_string(),
+            user_name: None,
+            sharing_level: level,
+            location: fix_age_secs.map(|age| LocationData {
+                latitude: 41.0082,
+                longitude: 28.9784,
+                city: Some("Istanbul".to_string()),
+                country: Some("TR".to_string()),
+                timestamp: Some(NOW - age),
+                speed: None,
+                heading: None,
+            }),
+            last_updated: fix_age_secs.map(|age| NOW - age),
+            status: None,
+            timezone: None,
+            version: 0,
+        }
+    }
+
+    fn overrides() -> SharingOverrides {
+        SharingOverrides::default()
+    }
+
+    #[test]
+    fn global_level_applies_without_overrides() {
+        let friend = user(Some(SharingLevel::City), Some(10));
+        assert_eq!(
+            effective_level(&friend, &overrides()),
+            Some(SharingLevel::City)
+        );
+        let unset = user(None, Some(10));
+        assert_eq!(effective_level(&unset, &overrides()), None);
+    }
+
+    #[test]
+    fn paused_wins_over_sos() {
+        let friend = user(Some(SharingLevel::Realtime), Some(10));
+        let layers = SharingOverrides {
+            paused: true,
+            sos: true,
+            temporary: Some(SharingLevel::Realtime),
+            scheduled: Some(ScheduledLevel::Realtime),
+            group: Some(SharingLevel::Realtime),
+        };
+        assert_eq!(effective_level(&friend, &layers), None);
+    }
+
+    #[test]
+    fn sos_wins_over_temporary() {
+        let friend = user(Some(SharingLevel::CityName), Some(10));
+        let layers = SharingOverrides {
+            sos: true,
+            temporary: Some(SharingLevel::CityName),
+            scheduled: Some(ScheduledLevel::Hidden),
+            ..overrides()
+        };
+        assert_eq!(
+            effective_level(&friend, &layers),
+            Some(SharingLevel::Realtime)
+        );
+    }
+
+    #[test]
+    fn temporary_wins_over_scheduled() {
+        let friend = user(Some(SharingLevel::City), Some(10));
+        let layers = SharingOverrides {
+            temporary: Some(SharingLevel::Realtime),
+            scheduled: Some(ScheduledLevel::Hidden),
+            ..overrides()
+        };
+        assert_eq!(
+            effective_level(&friend, &layers),
+            Some(SharingLevel::Realtime)
+        );
+    }
+
+    #[test]
+    fn scheduled_hidden_wins_over_group() {
+        let friend = user(Some(SharingLevel::City), Some(10));
+        let layers = SharingOverrides {
+            scheduled: Some(ScheduledLevel::Hidden),
+            group: Some(SharingLevel::Realtime),
+            ..overrides()
+        };
+        assert_eq!(effective_level(&friend, &layers), None);
+
+        // More permissive than the group is fine too: the schedule decides
+        let layers = SharingOverrides {
+            scheduled: Some(ScheduledLevel::Realtime),
+            group: Some(SharingLevel::CityName),
+            ..overrides()
+        };
+        assert_eq!(
+            effective_level(&friend, &layers),
+            Some(SharingLevel::Realtime)
+        );
+    }
+
+    #[test]
+    fn group_wins_over_global() {
+        let friend = user(Some(SharingLevel::City), Some(10));
+        let layers = SharingOverrides {
+            group: Some(SharingLevel::CityName),
+            ..overrides()
+        };
+        assert_eq!(
+            effective_level(&friend, &layers),
+            Some(SharingLevel::CityName)
+        );
+        // A group shares even if no global level was ever set
+        let unset = user(None, Some(10));
+        assert_eq!(
+            effective_level(&unset, &layers),
+            Some(SharingLevel::CityName)
+        );
+    }
+
+    fn reason(
+        friend: User,
+        layers: &SharingOverrides,
+        decay: Option<&PrivacyDecay>,
+    ) -> Option<HiddenReason> {
+        let filtered = apply_privacy_filter(friend, layers, None, decay, NOW);
+        assert_eq!(
+            filtered.location.is_none(),
+            filtered.hidden_reason.is_some()
+        );
+        filtered.hidden_reason
+    }
+
+    #[test]
+    fn hidden_reason_names_what_hid_the_location() {
+        let shared = || user(Some(SharingLevel::Realtime), Some(10));
+        assert_eq!(reason(shared(), &overrides(), None), None);
+
+        let paused = SharingOverrides {
+            paused: true,
+            ..overrides()
+        };
+        assert_eq!(reason(shared(), &paused, None), Some(HiddenReason::Paused));
+
+        let scheduled = SharingOverrides {
+            scheduled: Some(ScheduledLevel::Hidden),
+            ..overrides()
+        };
+        assert_eq!(
+            reason(shared(), &scheduled, None),
+            Some(HiddenReason::Scheduled)
+        );
+
+        let unset = user(None, Some(10));
+        assert_eq!(
+            reason(unset, &overrides(), None),
+            Some(HiddenReason::LevelNone)
+        );
+
+        let never_moved = user(Some(SharingLevel::Realtime), None);
+        assert_eq!(
+            reason(never_moved, &overrides(), None),
+            Some(HiddenReason::NoLocation)
+        );
+
+        let decay = PrivacyDecay {
+            start_secs: 60,
+            hide_secs: 120,
+        };
+        let old = user(Some(SharingLevel::Realtime), Some(121));
+        assert_eq!(
+            reason(old, &overrides(), Some(&decay)),
+            Some(HiddenReason::Expired)
+        );
+
+        // Stale but not decayed away: still shown
+        let stale = user(Some(SharingLevel::Realtime), Some(10 * TTL_SECS));
+        assert_eq!(reason(stale, &overrides(), None), None);
+    }
+
+    #[test]
+    fn local_time_needs_at_least_a_city() {
+        let decay = PrivacyDecay {
+            start_secs: 60,
+            hide_secs: 120,
+        };
+        let local_time = |age_secs| {
+            let friend = User {
+                timezone: Some("Europe/Istanbul".to_string()),
+                ..user(Some(SharingLevel::Realtime), Some(age_secs))
+            };
+            apply_privacy_filter(friend, &overrides(), None, Some(&decay), NOW).local_time
+        };
+
+        assert!(local_time(10).is_some());
+        // Faded to City
+        assert!(local_time(61).is_some());
+        // Faded to country only, then hidden
+        assert!(local_time(119).is_none());
+        assert!(local_time(121).is_none());
+    }
+
+    #[test]
+    fn per_friend_realtime_beats_global_city() {
+        let friend = user(Some(SharingLevel::City), Some(10));
+        let overrides = SharingOverrides {
+            temporary: Some(SharingLevel::Realtime),
+            ..Default::default()
+        };
+        assert_eq!(
+            effective_level(&friend, &overrides),
+            Some(SharingLevel::Realtime)
+        );
+        assert!(shows_coordinates(&friend, &overrides, None, TTL_SECS, NOW));
+    }
+
+    #[test]
+    fn city_name_shows_no_coordinates() {
+        let friend = user(Some(SharingLevel::CityName), Some(10));
+        let overrides = SharingOverrides::default();
+        assert!(!shows_coordinates(&friend, &overrides, None, TTL_SECS, NOW));
+    }
+
+    #[test]
+    fn stale_or_decayed_fix_shows_no_coordinates() {
+        let overrides = SharingOverrides::default();
+        let stale = user(Some(SharingLevel::Realtime), Some(TTL_SECS + 1));
+        assert!(!shows_coordinates(&stale, &overrides, None, TTL_SECS, NOW));
+
+        // Fresh, but decayed past City
+        let decay = PrivacyDecay {
+            start_secs: 0,
+            hide_secs: 90,
+        };
+        let faded = user(Some(SharingLevel::Realtime), Some(80));
+        assert!(!shows_coordinates(
+            &faded,
+            &overrides,
+            Some(&decay),
+            TTL_SECS,
+            NOW
+        ));
+    }
+
+    #[test]
+    fn freshness_ends_exactly_at_the_ttl() {
+        let annotated = |fix_age_secs| {
+            let friend = user(Some(SharingLevel::Realtime), Some(fix_age_secs));
+            let mut shown = apply_privacy_filter(friend, &overrides(), None, None, NOW);
+            annotate_freshness(&mut shown, TTL_SECS, NOW);
+            (shown.age_secs, shown.is_fresh)
+        };
+        assert_eq!(annotated(0), (Some(0), true));
+        assert_eq!(annotated(TTL_SECS), (Some(TTL_SECS), true));
+        assert_eq!(annotated(TTL_SECS + 1), (Some(TTL_SECS + 1), false));
+        // A clock running slightly ahead on the device doesn't give a negative age
+        assert_eq!(annotated(-5), (Some(0), true));
+
+        let hidden = user(None, Some(10));
+        let mut shown = apply_privacy_filter(hidden, &overrides(), None, None, NOW);
+        annotate_freshness(&mut shown, TTL_SECS, NOW);
+        assert_eq!((shown.age_secs, shown.is_fresh), (None, false));
+    }
+
+    /// Ground width east-west of the rounding cell at `latitude`, in meters
+    fn cell_width_meters(latitude: f64, rounding_meters: Option<f64>) -> f64 {
+        let (lat, first) = round_to_city(latitude, 0.0, rounding_meters);
+        let next = (1..)
+            .map(|i| round_to_city(latitude, i as f64 * 0.0001, rounding_meters).1)
+            .find(|lng| *lng != first)
+            .unwrap();
+        crate::geo::haversine_km(lat, first, lat, next) * 1000.0
+    }
+
+    #[test]
+    fn ground_rounding_keeps_cells_the_same_width_away_from_the_equator() {
+        let equator = cell_width_meters(0.0, Some(1000.0));
+        let north = cell_width_meters(60.0, Some(1000.0));
+        assert!((equator - 1000.0).abs() < 10.0, "{}", equator);
+        assert!((north - 1000.0).abs() < 10.0, "{}", north);
+
+        // Plain decimal rounding narrows to about half at 60°
+        let equator = cell_width_meters(0.0, None);
+        let north = cell_width_meters(60.0, None);
+        assert!(
+            (north / equator - 0.5).abs() < 0.01,
+            "{} vs {}",
+            north,
+            equator
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use crate::config::ReconcileMode;
+use crate::sapphire_client::SapphireClient;
+use anyhow::Result;
+use tracing::{info, warn};
+
+/// Find one-sided friendships (A lists B, B doesn't list A) and handle them
+/// according to `mode`
+/// Returns how many were found; partial failures (e.g. accept ignoring an
+/// `add_friend` error) are what leave them behind
+pub async fn reconcile_friendships(client: &SapphireClient, mode: ReconcileMode) -> Result<usize> {
+    let edges = client.get_asymmetric_edges().await?;
+    for (user_id, friend_id) in &edges {
+        match mode {
+            ReconcileMode::Repair => {
+                client.add_friend_one_way(friend_id, user_id).await?;
+                info!(
+                    "🔧 Repaired one-sided friendship: {} -> {}",
+                    user_id, friend_id
+                );
+            }
+            ReconcileMode::Log => {
+                warn!("⚠️ One-sided friendship: {} -> {}", user_id, friend_id);
+            }
+        }
+    }
+    Ok(edges.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn repair_symmetrizes_one_sided_friendships() {
+        let client = SapphireClient::new().await.unwrap();
+        client
+            .add_friend("alice", "bob", Some("request-1"))
+            .await
+            .unwrap();
+        // A failure halfway left only Carol's side behind
+        client.add_friend_one_way("carol", "alice").await.unwrap();
+
+        // Logging leaves it as it is
+        assert_eq!(
+            reconcile_friendships(&client, ReconcileMode::Log)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(client.get_friends("alice").await.unwrap(), ["bob"]);
+
+        assert_eq!(
+            reconcile_friendships(&client, ReconcileMode::Repair)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(client.get_friends("alice").await.unwrap(), ["bob", "carol"]);
+        assert!(client.get_asymmetric_edges().await.unwrap().is_empty());
+        assert_eq!(
+            reconcile_friendships(&client, ReconcileMode::Repair)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -1,46 +1,190 @@
 use anyhow::Result;
+use serde::Serialize;
 use std::collections::HashMap;
+#[cfg(test)]
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::RwLock;
+use utoipa::ToSchema;
+
+/// One side of a friendship, as stored for the user listing it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Friendship {
+    #[serde(rename = "friendId")]
+    pub friend_id: String,
+    /// The accepted friend request that made it; `None` for friendships
+    /// added directly, seeded, or otherwise imported
+    #[serde(rename = "requestId")]
+    pub request_id: Option<String>,
+}
+
+impl Friendship {
+    fn new(friend_id: &str, request_id: Option<&str>) -> Self {
+        Self {
+            friend_id: friend_id.to_string(),
+            request_id: request_id.map(str::to_string),
+        }
+    }
+}
 
 /// Sapphire client for managing friendships on-chain
 /// This interacts with the FriendManager contract on Sapphire
 /// For MVP: Using in-memory storage instead of blockchain
 pub struct SapphireClient {
     // In-memory friendships for MVP testing
-    friendships: RwLock<HashMap<String, Vec<String>>>,
+    friendships: RwLock<HashMap<String, Vec<Friendship>>>,
+    /// While set, every call fails as if the node were unreachable
+    #[cfg(test)]
+    outage: AtomicBool,
 }
 
 impl SapphireClient {
     pub async fn new() -> Result<Self> {
         Ok(Self {
             friendships: RwLock::new(HashMap::new()),
+            #[cfg(test)]
+            outage: AtomicBool::new(false),
         })
     }
 
+    /// Simulate the node going down (or coming back)
+    #[cfg(test)]
+    pub fn set_outage(&self, down: bool) {
+        self.outage.store(down, Ordering::Relaxed);
+    }
+
+    fn check_reachable(&self) -> Result<()> {
+        #[cfg(test)]
+        if self.outage.load(Ordering::Relaxed) {
+            anyhow::bail!("Sapphire node unreachable");
+        }
+        Ok(())
+    }
+
     /// Get user's friends
     pub async fn get_friends(&self, user_id: &str) -> Result<Vec<String>> {
+        self.check_reachable()?;
         let friendships = self.friendships.read().unwrap();
         Ok(friendships
             .get(user_id)
-            .cloned()
-            .unwrap_or_else(Vec::new))
+            .map(|friends| friends.iter().map(|f| f.friend_id.clone()).collect())
+            .unwrap_or_default())
+    }
+
+    /// Get user's friends with how each friendship came about
+    pub async fn get_friendships(&self, user_id: &str) -> Result<Vec<Friendship>> {
+        self.check_reachable()?;
+        let friendships = self.friendships.read().unwrap();
+        Ok(friendships.get(user_id).cloned().unwrap_or_default())
+    }
+
+    /// Get one page of a user's friends
+    /// Only the requested window is cloned, so large friend lists stay cheap
+    /// to page through
+    pub async fn get_friends_paged(
+        &self,
+        user_id: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        self.check_reachable()?;
+        let friendships = self.friendships.read().unwrap();
+        Ok(friendships
+            .get(user_id)
+            .map(|friends| {
+                friends
+                    .iter()
+                    .skip(offset)
+                    .take(limit)
+                    .map(|f| f.friend_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Count a user's friends without copying the list
+    pub async fn count_friends(&self, user_id: &str) -> Result<usize> {
+        self.check_reachable()?;
+        let friendships = self.friendships.read().unwrap();
+        Ok(friendships.get(user_id).map_or(0, Vec::len))
+    }
+
+    /// Get everyone who lists `user_id` as a friend
+    /// Normally the same as `get_friends`, but partial failures can leave
+    /// one-sided entries behind
+    pub async fn get_friended_by(&self, user_id: &str) -> Result<Vec<String>> {
+        self.check_reachable()?;
+        let friendships = self.friendships.read().unwrap();
+        let mut friended_by: Vec<String> = friendships
+            .iter()
+            .filter(|(_, friends)| friends.iter().any(|f| f.friend_id == user_id))
+            .map(|(other, _)| other.clone())
+            .collect();
+        friended_by.sort();
+        Ok(friended_by)
+    }
+
+    /// Every one-sided friendship, as (user, friend) pairs where the user
+    /// lists the friend but not the other way around, sorted
+    pub async fn get_asymmetric_edges(&self) -> Result<Vec<(String, String)>> {
+        self.check_reachable()?;
+        let friendships = &*self.friendships.read().unwrap();
+        let mut edges: Vec<(String, String)> = friendships
+            .iter()
+            .flat_map(|(user_id, friends)| {
+                friends
+                    .iter()
+                    .filter(move |friend| {
+                        !friendships
+                            .get(&friend.friend_id)
+                            .is_some_and(|theirs| theirs.iter().any(|f| &f.friend_id == user_id))
+                    })
+                    .map(move |friend| (user_id.clone(), friend.friend_id.clone()))
+            })
+            .collect();
+        edges.sort();
+        edges.dedup();
+        Ok(edges)
+    }
+
+    /// Add `friend_id` to `user_id`'s friends only, unless already there
+    /// For repairing one-sided friendships; `add_friend` adds both sides.
+    /// The repaired side gets the same originating request as the other.
+    pub async fn add_friend_one_way(&self, user_id: &str, friend_id: &str) -> Result<()> {
+        self.check_reachable()?;
+        let mut friendships = self.friendships.write().unwrap();
+        let request_id = friendships
+            .get(friend_id)
+            .and_then(|theirs| theirs.iter().find(|f| f.friend_id == user_id))
+            .and_then(|f| f.request_id.clone());
+        let friends = friendships.entry(user_id.to_string()).or_default();
+        if !friends.iter().any(|f| f.friend_id == friend_id) {
+            friends.push(Friendship::new(friend_id, request_id.as_deref()));
+        }
+        Ok(())
     }
 
     /// Add friend (bidirectional)
-    pub async fn add_friend(&self, user_id: &str, friend_id: &str) -> Result<()> {
+    /// `request_id` is the accepted friend request it came from, if any
+    pub async fn add_friend(
+        &self,
+        user_id: &str,
+        friend_id: &str,
+        request_id: Option<&str>,
+    ) -> Result<()> {
+        self.check_reachable()?;
         let mut friendships = self.friendships.write().unwrap();
 
         // Add friend_id to user's friends
         friendships
             .entry(user_id.to_string())
-            .or_insert_with(Vec::new)
-            .push(friend_id.to_string());
+            .or_default()
+            .push(Friendship::new(friend_id, request_id));
 
         // Add user_id to friend's friends (bidirectional)
         friendships
             .entry(friend_id.to_string())
-            .or_insert_with(Vec::new)
-            .push(user_id.to_string());
+            .or_default()
+            .push(Friendship::new(user_id, request_id));
 
         tracing::info!("✅ Added friendship: {} <-> {}", user_id, friend_id);
         Ok(())
@@ -48,19 +192,125 @@ impl SapphireClient {
 
     /// Remove friend (bidirectional)
     pub async fn remove_friend(&self, user_id: &str, friend_id: &str) -> Result<()> {
+        self.check_reachable()?;
         let mut friendships = self.friendships.write().unwrap();
 
         // Remove friend_id from user's friends
         if let Some(friends) = friendships.get_mut(user_id) {
-            friends.retain(|f| f != friend_id);
+            friends.retain(|f| f.friend_id != friend_id);
         }
 
         // Remove user_id from friend's friends (bidirectional)
         if let Some(friends) = friendships.get_mut(friend_id) {
-            friends.retain(|f| f != user_id);
+            friends.retain(|f| f.friend_id != user_id);
         }
 
         tracing::info!("✅ Removed friendship: {} <-> {}", user_id, friend_id);
         Ok(())
     }
+
+    /// Move all of `from_id`'s friendships to `into_id`
+    /// Friends shared by both accounts end up listed once, and the two
+    /// accounts are never made friends with each other
+    pub async fn merge_friends(&self, from_id: &str, into_id: &str) -> Result<()> {
+        self.check_reachable()?;
+        let mut friendships = self.friendships.write().unwrap();
+
+        let from_friends = friendships.remove(from_id).unwrap_or_default();
+        let into_friends = friendships.entry(into_id.to_string()).or_default();
+        for friend in from_friends {
+            if friend.friend_id != into_id
+                && !into_friends.iter().any(|f| f.friend_id == friend.friend_id)
+            {
+                into_friends.push(friend);
+            }
+        }
+        into_friends.retain(|f| f.friend_id != from_id);
+
+        // Point third parties at the surviving account
+        for (user_id, friends) in friendships.iter_mut() {
+            if user_id == into_id {
+                continue;
+            }
+            let mut seen: Vec<Friendship> = Vec::with_capacity(friends.len());
+            for mut friend in friends.drain(..) {
+                if friend.friend_id == from_id {
+                    friend.friend_id = into_id.to_string();
+                }
+                if !seen.iter().any(|f| f.friend_id == friend.friend_id) {
+                    seen.push(friend);
+                }
+            }
+            *friends = seen;
+        }
+
+        tracing::info!("✅ Merged friendships: {} -> {}", from_id, into_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    /// Counts the bytes each thread allocates, so tests can check how much
+    /// a call copies
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + layout.size()));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocated() -> usize {
+        ALLOCATED.with(Cell::get)
+    }
+
+    #[tokio::test]
+    async fn paging_copies_only_the_page() {
+        let client = SapphireClient::new().await.unwrap();
+        for i in 0..10_000 {
+            let friend = format!("friend-{:05}", i);
+            client.add_friend("alice", &friend, None).await.unwrap();
+        }
+
+        let before = allocated();
+        let page = client.get_friends_paged("alice", 5_000, 10).await.unwrap();
+        let paged_bytes = allocated() - before;
+        assert_eq!(page.first().map(String::as_str), Some("friend-05000"));
+        assert_eq!(page.len(), 10);
+
+        let before = allocated();
+        let all = client.get_friends("alice").await.unwrap();
+        let full_bytes = allocated() - before;
+        assert_eq!(all.len(), 10_000);
+
+        // A page of 10 copies 10 IDs, however long the list is
+        assert!(
+            paged_bytes < 1_024,
+            "paging allocated {} bytes",
+            paged_bytes
+        );
+        assert!(
+            full_bytes > 100 * paged_bytes,
+            "{} vs {}",
+            full_bytes,
+            paged_bytes
+        );
+    }
 }
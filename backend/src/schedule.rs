@@ -0,0 +1,363 @@
+use crate::SharingLevel;
+use chrono::{DateTime, Datelike, Timelike};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+/// Level a schedule rule shares, from most to least restrictive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledLevel {
+    /// Share nothing
+    Hidden,
+    CityName,
+    City,
+    Realtime,
+}
+
+impl ScheduledLevel {
+    /// The sharing level this amounts to (`None` when hidden)
+    pub fn sharing_level(self) -> Option<SharingLevel> {
+        match self {
+            Self::Hidden => None,
+            Self::CityName => Some(SharingLevel::CityName),
+            Self::City => Some(SharingLevel::City),
+            Self::Realtime => Some(SharingLevel::Realtime),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    fn from_chrono(day: chrono::Weekday) -> Self {
+        match day {
+            chrono::Weekday::Mon => Self::Mon,
+            chrono::Weekday::Tue => Self::Tue,
+            chrono::Weekday::Wed => Self::Wed,
+            chrono::Weekday::Thu => Self::Thu,
+            chrono::Weekday::Fri => Self::Fri,
+            chrono::Weekday::Sat => Self::Sat,
+            chrono::Weekday::Sun => Self::Sun,
+        }
+    }
+}
+
+/// Time of day as minutes since midnight, written "HH:MM" in JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[schema(value_type = String, example = "22:30")]
+pub struct TimeOfDay(u16);
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected a time like `22:30`, got `{}`", s);
+        let (hours, minutes) = s.split_once(':').ok_or_else(invalid)?;
+        if hours.len() != 2 || minutes.len() != 2 {
+            return Err(invalid());
+        }
+        let hours: u16 = hours.parse().map_err(|_| invalid())?;
+        let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+        Ok(Self(hours * 60 + minutes))
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.0 / 60, self.0 % 60)
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = String::deserialize(deserializer)?;
+        raw.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A sharing level that applies on some days between two times of day, in
+/// the user's time zone (UTC if they haven't set one)
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScheduleRule {
+    /// Days the rule starts on; every day when empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Start time, inclusive
+    pub start: TimeOfDay,
+    /// End time, exclusive. An end before the start runs past midnight into
+    /// the next day, and an end equal to the start covers the whole day
+    pub end: TimeOfDay,
+    pub level: ScheduledLevel,
+    /// Only applies toward this friend
+    #[serde(rename = "friendId", default, skip_serializing_if = "Option::is_none")]
+    pub friend_id: Option<String>,
+    /// Only applies toward members of this sharing group
+    #[serde(rename = "groupId", default, skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<String>,
+}
+
+impl ScheduleRule {
+    /// Whether the rule covers `minute` past midnight on `day`, whose
+    /// previous day is `yesterday`
+    fn covers(&self, day: Weekday, yesterday: Weekday, minute: u16) -> bool {
+        let on = |day: Weekday| self.days.is_empty() || self.days.contains(&day);
+        let (start, end) = (self.start.0, self.end.0);
+        if start < end {
+            on(day) && (start..end).contains(&minute)
+        } else if start > end {
+            (on(day) && minute >= start) || (on(yesterday) && minute < end)
+        } else {
+            on(day)
+        }
+    }
+
+    fn applies_to(&self, viewer_id: &str, viewer_groups: &HashSet<String>) -> bool {
+        self.friend_id.as_ref().is_none_or(|id| id == viewer_id)
+            && self
+                .group_id
+                .as_ref()
+                .is_none_or(|id| viewer_groups.contains(id))
+    }
+}
+
+/// The level `rules` put in force toward a viewer at `now`, or `None` if
+/// no rule covers it. Overlapping rules resolve to the most restrictive.
+/// `timezone` is the owner's IANA zone (UTC when unset or unknown), and
+/// `viewer_groups` the IDs of the owner's groups the viewer is in.
+pub fn active_level(
+    rules: &[ScheduleRule],
+    viewer_id: &str,
+    viewer_groups: &HashSet<String>,
+    timezone: Option<&str>,
+    now: i64,
+) -> Option<ScheduledLevel> {
+    let utc = DateTime::from_timestamp(now, 0)?;
+    let local = utc.with_timezone(&timezone.and_then(|tz| tz.parse().ok()).unwrap_or(Tz::UTC));
+    let day = Weekday::from_chrono(local.weekday());
+    let yesterday = Weekday::from_chrono(local.weekday().pred());
+    let minute = (local.hour() * 60 + local.minute()) as u16;
+
+    rules
+        .iter()
+        .filter(|rule| rule.applies_to(viewer_id, viewer_groups))
+        .filter(|rule| rule.covers(day, yesterday, minute))
+        .map(|rule| rule.level)
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    /// Unix time at `hour:minute` UTC on a day of the week starting Monday
+    /// 2024-01-01 (0 = Monday, 6 = Sunday, 7 = the Monday after)
+    fn at(day: u32, hour: u32, minute: u32) -> i64 {
+        Utc.with_ymd_and_hms(2024, 1, 1 + day, hour, minute, 0)
+            .unwrap()
+            .timestamp()
+    }
+
+    fn rule(days: &[Weekday], start: &str, end: &str, level: ScheduledLevel) -> ScheduleRule {
+        ScheduleRule {
+            days: days.to_vec(),
+            start: start.parse().unwrap(),
+            end: end.parse().unwrap(),
+            level,
+            friend_id: None,
+            group_id: None,
+        }
+    }
+
+    fn level_at(
+        rules: &[ScheduleRule],
+        timezone: Option<&str>,
+        now: i64,
+    ) -> Option<ScheduledLevel> {
+        active_level(rules, "viewer", &HashSet::new(), timezone, now)
+    }
+
+    #[test]
+    fn daytime_rule_covers_start_but_not_end() {
+        let rules = [rule(&[], "09:00", "17:00", ScheduledLevel::Realtime)];
+        assert_eq!(level_at(&rules, None, at(0, 8, 59)), None);
+        assert_eq!(
+            level_at(&rules, None, at(0, 9, 0)),
+            Some(ScheduledLevel::Realtime)
+        );
+        assert_eq!(
+            level_at(&rules, None, at(0, 16, 59)),
+            Some(ScheduledLevel::Realtime)
+        );
+        assert_eq!(level_at(&rules, None, at(0, 17, 0)), None);
+    }
+
+    #[test]
+    fn overnight_rule_runs_past_midnight() {
+        let rules = [rule(&[], "22:00", "07:00", ScheduledLevel::Hidden)];
+        assert_eq!(
+            level_at(&rules, None, at(0, 23, 0)),
+            Some(ScheduledLevel::Hidden)
+        );
+        assert_eq!(
+            level_at(&rules, None, at(1, 6, 0)),
+            Some(ScheduledLevel::Hidden)
+        );
+        assert_eq!(level_at(&rules, None, at(1, 7, 0)), None);
+        assert_eq!(level_at(&rules, None, at(1, 12, 0)), None);
+    }
+
+    #[test]
+    fn overnight_rule_belongs_to_the_day_it_starts() {
+        // Friday night into Saturday morning, not Friday morning
+        let rules = [rule(
+            &[Weekday::Fri],
+            "22:00",
+            "07:00",
+            ScheduledLevel::Hidden,
+        )];
+        assert_eq!(level_at(&rules, None, at(4, 6, 0)), None);
+        assert_eq!(
+            level_at(&rules, None, at(4, 23, 0)),
+            Some(ScheduledLevel::Hidden)
+        );
+        assert_eq!(
+            level_at(&rules, None, at(5, 6, 0)),
+            Some(ScheduledLevel::Hidden)
+        );
+        assert_eq!(level_at(&rules, None, at(5, 23, 0)), None);
+        // Sunday night wraps into the next week's Monday
+        let rules = [rule(
+            &[Weekday::Sun],
+            "22:00",
+            "07:00",
+            ScheduledLevel::Hidden,
+        )];
+        assert_eq!(
+            level_at(&rules, None, at(7, 6, 0)),
+            Some(ScheduledLevel::Hidden)
+        );
+    }
+
+    #[test]
+    fn equal_start_and_end_cover_the_whole_day() {
+        let rules = [rule(
+            &[Weekday::Sat],
+            "00:00",
+            "00:00",
+            ScheduledLevel::CityName,
+        )];
+        assert_eq!(
+            level_at(&rules, None, at(5, 0, 0)),
+            Some(ScheduledLevel::CityName)
+        );
+        assert_eq!(
+            level_at(&rules, None, at(5, 23, 59)),
+            Some(ScheduledLevel::CityName)
+        );
+        assert_eq!(level_at(&rules, None, at(6, 0, 0)), None);
+    }
+
+    #[test]
+    fn rules_follow_the_owners_time_zone() {
+        let rules = [rule(&[], "22:00", "07:00", ScheduledLevel::Hidden)];
+        // 20:00 UTC is 23:00 in Istanbul (UTC+3)
+        assert_eq!(level_at(&rules, None, at(0, 20, 0)), None);
+        assert_eq!(
+            level_at(&rules, Some("Europe/Istanbul"), at(0, 20, 0)),
+            Some(ScheduledLevel::Hidden)
+        );
+        // 05:00 UTC is 08:00 in Istanbul
+        assert_eq!(level_at(&rules, Some("Europe/Istanbul"), at(1, 5, 0)), None);
+        // Unknown zones fall back to UTC
+        assert_eq!(
+            level_at(&rules, Some("Mars/Olympus_Mons"), at(0, 23, 0)),
+            Some(ScheduledLevel::Hidden)
+        );
+    }
+
+    #[test]
+    fn overlapping_rules_resolve_to_the_most_restrictive() {
+        let rules = [
+            rule(&[], "09:00", "17:00", ScheduledLevel::Realtime),
+            rule(&[], "12:00", "13:00", ScheduledLevel::City),
+            rule(&[], "12:30", "14:00", ScheduledLevel::CityName),
+        ];
+        assert_eq!(
+            level_at(&rules, None, at(0, 10, 0)),
+            Some(ScheduledLevel::Realtime)
+        );
+        assert_eq!(
+            level_at(&rules, None, at(0, 12, 15)),
+            Some(ScheduledLevel::City)
+        );
+        assert_eq!(
+            level_at(&rules, None, at(0, 12, 45)),
+            Some(ScheduledLevel::CityName)
+        );
+        assert_eq!(
+            level_at(&rules, None, at(0, 13, 30)),
+            Some(ScheduledLevel::CityName)
+        );
+    }
+
+    #[test]
+    fn scoped_rules_only_apply_to_their_viewers() {
+        let rules = [
+            ScheduleRule {
+                friend_id: Some("boss".to_string()),
+                ..rule(&[], "18:00", "09:00", ScheduledLevel::Hidden)
+            },
+            ScheduleRule {
+                group_id: Some("family".to_string()),
+                ..rule(&[], "00:00", "00:00", ScheduledLevel::Realtime)
+            },
+        ];
+        let family = HashSet::from(["family".to_string()]);
+        let evening = at(0, 20, 0);
+
+        assert_eq!(
+            active_level(&rules, "boss", &HashSet::new(), None, evening),
+            Some(ScheduledLevel::Hidden)
+        );
+        assert_eq!(
+            active_level(&rules, "mom", &family, None, evening),
+            Some(ScheduledLevel::Realtime)
+        );
+        assert_eq!(
+            active_level(&rules, "stranger", &HashSet::new(), None, evening),
+            None
+        );
+    }
+
+    #[test]
+    fn times_of_day_must_be_hh_mm() {
+        assert_eq!("07:05".parse::<TimeOfDay>().unwrap().to_string(), "07:05");
+        for bad in ["7:05", "24:00", "12:60", "noon", "12-30"] {
+            assert!(bad.parse::<TimeOfDay>().is_err(), "{}", bad);
+        }
+    }
+}
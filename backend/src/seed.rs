@@ -0,0 +1,280 @@
+use crate::config::Config;
+use crate::country;
+use crate::geo;
+use crate::location_store::{LocationStore, UserPatch};
+use crate::sapphire_client::SapphireClient;
+use crate::{LocationData, SharingLevel};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Development data loaded at startup from `SEED_FILE`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedData {
+    #[serde(default)]
+    pub users: Vec<SeedUser>,
+    /// Pairs of user IDs to make friends
+    #[serde(default)]
+    pub friendships: Vec<(String, String)>,
+    /// Pending friend requests
+    #[serde(default)]
+    pub requests: Vec<SeedRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedUser {
+    pub id: String,
+    #[serde(rename = "userName")]
+    pub user_name: Option<String>,
+    #[serde(rename = "sharingLevel")]
+    pub sharing_level: Option<SharingLevel>,
+    /// Recorded as a fresh fix at load time (any timestamp is ignored)
+    pub location: Option<LocationData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedRequest {
+    pub from: String,
+    pub to: String,
+    pub message: Option<String>,
+}
+
+impl SeedData {
+    /// Read and validate a seed file
+    pub fn from_file(path: &Path, country_lenient: bool) -> Result<Self> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Can't read seed file {}", path.display()))?;
+        let mut seed: Self = serde_json::from_slice(&bytes)
+            .with_context(|| format!("Malformed seed file {}", path.display()))?;
+        seed.validate(country_lenient)
+            .with_context(|| format!("Invalid seed file {}", path.display()))?;
+        Ok(seed)
+    }
+
+    /// Check the whole file up front, so a bad seed never half-loads
+    /// Countries are stored canonically, as for location updates, and
+    /// friendships and requests may only refer to users listed in the file
+    pub fn validate(&mut self, country_lenient: bool) -> Result<()> {
+        for user in &mut self.users {
+            let Some(location) = &mut user.location else {
+                continue;
+            };
+            if let Some(raw) = location.country.take() {
+                location.country = match country::normalize_country(&raw) {
+                    Some(code) => Some(code.to_string()),
+                    None if country_lenient => Some(raw),
+                    None => bail!("Unrecognized country for {}: {}", user.id, raw),
+                };
+            }
+        }
+
+        let mut ids = HashSet::new();
+        for user in &self.users {
+            if user.id.is_empty() {
+                bail!("User IDs must not be empty");
+            }
+            if !ids.insert(user.id.as_str()) {
+                bail!("Duplicate user: {}", user.id);
+            }
+            if let Some(location) = &user.location {
+                if !(-90.0..=90.0).contains(&location.latitude)
+                    || !(-180.0..=180.0).contains(&location.longitude)
+                {
+                    bail!("Location of {} is out of range", user.id);
+                }
+            }
+        }
+
+        let known = |id: &str| {
+            if ids.contains(id) {
+                Ok(())
+            } else {
+                Err(anyhow!("Unknown user: {}", id))
+            }
+        };
+        let mut pairs = HashSet::new();
+        for (a, b) in &self.friendships {
+            known(a)?;
+            known(b)?;
+            if a == b {
+                bail!("{} can't be friends with themselves", a);
+            }
+            if !pairs.insert(sorted_pair(a, b)) {
+                bail!("Duplicate friendship: {} <-> {}", a, b);
+            }
+        }
+        for request in &self.requests {
+            known(&request.from)?;
+            known(&request.to)?;
+            if request.from == request.to {
+                bail!("{} can't send a friend request to themselves", request.from);
+            }
+            if !pairs.insert(sorted_pair(&request.from, &request.to)) {
+                bail!(
+                    "Request {} -> {} duplicates another friendship or request",
+                    request.from,
+                    request.to
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the data into the stores
+    /// Friendships and requests that already exist (e.g. from a persisted
+    /// store) are skipped, so seeding the same file twice is harmless
+    pub async fn apply(
+        self,
+        store: &LocationStore,
+        sapphire: &SapphireClient,
+        config: &Config,
+    ) -> Result<()> {
+        let (users, friendships, requests) = (
+            self.users.len(),
+            self.friendships.len(),
+            self.requests.len(),
+        );
+
+        for user in self.users {
+            store
+                .update_user(
+                    &user.id,
+                    UserPatch {
+                        user_name: Some(user.user_name),
+                        sharing_level: user.sharing_level,
+                        status: None,
+                        timezone: None,
+                    },
+                    None,
+                )
+                .await?;
+            if let Some(mut location) = user.location {
+                geo::round_coordinates(&mut location, config.coordinate_decimals);
+                store
+                    .update_location(&user.id, location, &config.movement, None)
+                    .await?;
+            }
+        }
+
+        for (a, b) in self.friendships {
+            if !sapphire.get_friends(&a).await?.contains(&b) {
+                sapphire.add_friend(&a, &b, None).await?;
+            }
+        }
+
+        for request in self.requests {
+            if store
+                .request_between(&request.from, &request.to)
+                .await
+                .is_some()
+            {
+                continue;
+            }
+            store
+                .send_friend_request(
+                    &request.from,
+                    &request.to,
+                    request.message,
+                    &config.request_limits,
+                )
+                .await
+                .map_err(|e| {
+                    anyhow!(
+                        "Can't seed request {} -> {}: {}",
+                        request.from,
+                        request.to,
+                        e
+                    )
+                })?;
+        }
+
+        tracing::info!(
+            "🌱 Seeded {} users, {} friendships and {} requests",
+            users,
+            friendships,
+            requests
+        );
+        Ok(())
+    }
+}
+
+fn sorted_pair<'a>(a: &'a str, b: &'a str) -> (&'a str, &'a str) {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed_file(contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("seed-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn small_seed_file_loads() {
+        let path = seed_file(
+            r#"{
+                "users": [
+                    {"id": "alice", "userName": "Alice", "sharingLevel": "realtime",
+                     "location": {"latitude": 41.0082, "longitude": 28.9784,
+                                  "city": "Istanbul", "country": "Turkey"}},
+                    {"id": "bob"},
+                    {"id": "carol"}
+                ],
+                "friendships": [["alice", "bob"]],
+                "requests": [{"from": "carol", "to": "alice", "message": "Hi"}]
+            }"#,
+        );
+        let seed = SeedData::from_file(&path, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let store = LocationStore::new();
+        let sapphire = SapphireClient::new().await.unwrap();
+        let config = Config::from_env().unwrap();
+        seed.apply(&store, &sapphire, &config).await.unwrap();
+
+        let alice = store.get_user("alice").await.unwrap();
+        assert_eq!(alice.user_name.as_deref(), Some("Alice"));
+        assert_eq!(alice.sharing_level, Some(SharingLevel::Realtime));
+        let location = alice.location.unwrap();
+        assert_eq!(location.country.as_deref(), Some("TR"));
+        assert!(location.timestamp.is_some());
+        assert_eq!(sapphire.get_friends("bob").await.unwrap(), ["alice"]);
+        let requests = store.get_friend_requests("alice").await;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].sender_id, "carol");
+    }
+
+    #[test]
+    fn malformed_seed_files_are_rejected() {
+        let cases = [
+            (r#"{"users": [{"id": "alice"}"#, "Malformed seed file"),
+            (r#"{"users": [], "groups": []}"#, "Malformed seed file"),
+            (
+                r#"{"users": [{"id": "alice"}], "friendships": [["alice", "bob"]]}"#,
+                "Unknown user: bob",
+            ),
+            (
+                r#"{"users": [{"id": "alice", "location": {"latitude": 91, "longitude": 0}}]}"#,
+                "Location of alice is out of range",
+            ),
+        ];
+        for (contents, expected) in cases {
+            let path = seed_file(contents);
+            let error = SeedData::from_file(&path, false).unwrap_err();
+            std::fs::remove_file(&path).unwrap();
+            let error = format!("{:#}", error);
+            assert!(error.contains(expected), "{}", error);
+        }
+    }
+}
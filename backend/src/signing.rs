@@ -0,0 +1,80 @@
+use crate::LocationData;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+/// Parse a hex-encoded Ed25519 public key
+pub fn parse_public_key(public_key_hex: &str) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = hex::decode(public_key_hex.trim())
+        .map_err(|e| format!("Invalid public key hex: {}", e))?
+        .try_into()
+        .map_err(|_| "Public key must be 32 bytes".to_string())?;
+
+    VerifyingKey::from_bytes(&bytes).map_err(|e| format!("Invalid public key: {}", e))
+}
+
+/// Canonical message a device signs for a location update:
+/// `"{user_id}:{latitude}:{longitude}:{timestamp}"`, with an empty timestamp
+/// when the client doesn't send one
+pub fn location_message(user_id: &str, location: &LocationData) -> String {
+    let timestamp = location
+        .timestamp
+        .map(|t| t.to_string())
+        .unwrap_or_default();
+    format!(
+        "{}:{}:{}:{}",
+        user_id, location.latitude, location.longitude, timestamp
+    )
+}
+
+/// Canonical message a device signs to log in: `"auth:{user_id}:{celo_uid}:{nonce}"`
+pub fn auth_message(user_id: &str, celo_uid: &str, nonce: &str) -> String {
+    format!("auth:{}:{}:{}", user_id, celo_uid, nonce)
+}
+
+/// Verify a hex-encoded Ed25519 signature over a location update
+pub fn verify_location_signature(
+    key: &VerifyingKey,
+    user_id: &str,
+    location: &LocationData,
+    signature_hex: &str,
+) -> Result<(), String> {
+    verify_signature(
+        key,
+        &location_message(user_id, location),
+        signature_hex,
+        "Location signature verification failed",
+    )
+}
+
+/// Verify a hex-encoded Ed25519 signature over a login nonce
+pub fn verify_auth_signature(
+    key: &VerifyingKey,
+    user_id: &str,
+    celo_uid: &str,
+    nonce: &str,
+    signature_hex: &str,
+) -> Result<(), String> {
+    verify_signature(
+        key,
+        &auth_message(user_id, celo_uid, nonce),
+        signature_hex,
+        "Auth signature verification failed",
+    )
+}
+
+/// Verify a hex-encoded Ed25519 signature over `message`, failing with
+/// `mismatch` when it is well-formed but doesn't match
+fn verify_signature(
+    key: &VerifyingKey,
+    message: &str,
+    signature_hex: &str,
+    mismatch: &str,
+) -> Result<(), String> {
+    let bytes: [u8; 64] = hex::decode(signature_hex.trim())
+        .map_err(|e| format!("Invalid signature hex: {}", e))?
+        .try_into()
+        .map_err(|_| "Signature must be 64 bytes".to_string())?;
+    let signature = Signature::from_bytes(&bytes);
+
+    key.verify(message.as_bytes(), &signature)
+        .map_err(|_| mismatch.to_string())
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Coalesces concurrent identical computations
+/// While one is running for a key, later callers with the same key wait for
+/// it and share its result instead of running their own. Nothing is cached:
+/// once it finishes, the next caller computes afresh.
+pub struct SingleFlight<K, V> {
+    in_flight: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run `compute` for `key`, or join the run already in flight
+    /// If the caller running it goes away mid-way, one of the waiters takes over
+    pub async fn run<F, Fut>(&self, key: K, compute: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let cell = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        let value = cell.get_or_init(compute).await.clone();
+
+        // Only the first caller to get here finds its own cell still in the map
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, &cell))
+        {
+            in_flight.remove(&key);
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_calls_share_one_computation() {
+        let flight = Arc::new(SingleFlight::new());
+        let computations = Arc::new(AtomicUsize::new(0));
+        let calls = 64;
+
+        let runs: Vec<_> = (0..calls)
+            .map(|_| {
+                let flight = flight.clone();
+                let computations = computations.clone();
+                tokio::spawn(async move {
+                    flight
+                        .run("alice", || async move {
+                            computations.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            42
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for run in runs {
+            assert_eq!(run.await.unwrap(), 42);
+        }
+
+        let computed = computations.load(Ordering::SeqCst);
+        assert!(computed >= 1);
+        assert!(
+            computed < calls / 8,
+            "{} computations for {} calls",
+            computed,
+            calls
+        );
+    }
+
+    #[tokio::test]
+    async fn finished_results_are_not_cached() {
+        let flight = SingleFlight::new();
+        assert_eq!(flight.run("alice", || async { 1 }).await, 1);
+        assert_eq!(flight.run("alice", || async { 2 }).await, 2);
+        assert!(flight.in_flight.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+/// Open event streams, per user and in total
+#[derive(Default)]
+struct StreamCounts {
+    total: usize,
+    per_user: HashMap<String, usize>,
+}
+
+/// Caps how many event streams can be open at once
+pub struct StreamLimiter {
+    max_per_user: usize,
+    max_total: usize,
+    counts: Mutex<StreamCounts>,
+}
+
+/// Why a stream was refused
+#[derive(Debug, Error)]
+pub enum StreamLimitExceeded {
+    #[error("Too many open streams for this user (max {0})")]
+    PerUser(usize),
+    #[error("Too many open streams (max {0})")]
+    Total(usize),
+}
+
+impl StreamLimiter {
+    pub fn new(max_per_user: usize, max_total: usize) -> Self {
+        Self {
+            max_per_user,
+            max_total,
+            counts: Mutex::new(StreamCounts::default()),
+        }
+    }
+
+    /// Reserve a slot for a new stream of `user_id`
+    /// The slot is given back when the returned permit is dropped, so it is
+    /// released however the connection ends
+    pub fn try_acquire(
+        self: &Arc<Self>,
+        user_id: &str,
+    ) -> Result<StreamPermit, StreamLimitExceeded> {
+        let mut counts = self.counts.lock().unwrap();
+        if counts.total >= self.max_total {
+            return Err(StreamLimitExceeded::Total(self.max_total));
+        }
+        let open = counts.per_user.entry(user_id.to_string()).or_default();
+        if *open >= self.max_per_user {
+            return Err(StreamLimitExceeded::PerUser(self.max_per_user));
+        }
+        *open += 1;
+        counts.total += 1;
+
+        Ok(StreamPermit {
+            limiter: self.clone(),
+            user_id: user_id.to_string(),
+        })
+    }
+
+    fn release(&self, user_id: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        counts.total = counts.total.saturating_sub(1);
+        if let Some(open) = counts.per_user.get_mut(user_id) {
+            *open -= 1;
+            if *open == 0 {
+                counts.per_user.remove(user_id);
+            }
+        }
+    }
+}
+
+/// An open stream's slot, released on drop
+pub struct StreamPermit {
+    limiter: Arc<StreamLimiter>,
+    user_id: String,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        self.limiter.release(&self.user_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_user_cap_frees_up_when_a_permit_drops() {
+        let limiter = Arc::new(StreamLimiter::new(2, 10));
+        let first = limiter.try_acquire("alice").unwrap();
+        let _second = limiter.try_acquire("alice").unwrap();
+        assert!(matches!(
+            limiter.try_acquire("alice"),
+            Err(StreamLimitExceeded::PerUser(2))
+        ));
+        // Other users have their own allowance
+        let _bob = limiter.try_acquire("bob").unwrap();
+
+        drop(first);
+        limiter.try_acquire("alice").unwrap();
+    }
+
+    #[test]
+    fn total_cap_applies_across_users() {
+        let limiter = Arc::new(StreamLimiter::new(2, 2));
+        let alice = limiter.try_acquire("alice").unwrap();
+        let _bob = limiter.try_acquire("bob").unwrap();
+        assert!(matches!(
+            limiter.try_acquire("carol"),
+            Err(StreamLimitExceeded::Total(2))
+        ));
+
+        drop(alice);
+        limiter.try_acquire("carol").unwrap();
+        assert!(!limiter
+            .counts
+            .lock()
+            .unwrap()
+            .per_user
+            .contains_key("alice"));
+    }
+}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, SecondsFormat};
+use chrono_tz::Tz;
+
+/// Check that `name` is an IANA time zone like "Europe/Istanbul"
+pub fn validate(name: &str) -> Result<(), String> {
+    name.parse::<Tz>().map(|_| ()).map_err(|_| {
+        format!(
+            "unknown time zone `{}`, expected an IANA name like Europe/Istanbul",
+            name
+        )
+    })
+}
+
+/// A Unix timestamp as ISO 8601 local time in time zone `name`, with its
+/// UTC offset (e.g. "2024-01-01T03:00:00+03:00")
+/// `None` if the zone is unknown or the timestamp is out of range
+pub fn local_time(timestamp: i64, name: &str) -> Option<String> {
+    let tz: Tz = name.parse().ok()?;
+    let utc = DateTime::from_timestamp(timestamp, 0)?;
+    Some(
+        utc.with_timezone(&tz)
+            .to_rfc3339_opts(SecondsFormat::Secs, false),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01T00:00:00Z and 2024-07-01T12:00:00Z
+    const NEW_YEAR: i64 = 1_704_067_200;
+    const MIDSUMMER: i64 = 1_719_835_200;
+
+    #[test]
+    fn utc_timestamps_convert_to_local_time() {
+        let cases = [
+            (NEW_YEAR, "Europe/Istanbul", "2024-01-01T03:00:00+03:00"),
+            (NEW_YEAR, "America/New_York", "2023-12-31T19:00:00-05:00"),
+            (MIDSUMMER, "America/New_York", "2024-07-01T08:00:00-04:00"),
+            (MIDSUMMER, "UTC", "2024-07-01T12:00:00+00:00"),
+        ];
+        for (timestamp, zone, expected) in cases {
+            assert_eq!(
+                local_time(timestamp, zone).as_deref(),
+                Some(expected),
+                "{}",
+                zone
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_zones_are_rejected() {
+        assert_eq!(local_time(NEW_YEAR, "Mars/Olympus"), None);
+        assert!(validate("Europe/Istanbul").is_ok());
+        assert_eq!(
+            validate("GMT+3 Istanbul").unwrap_err(),
+            "unknown time zone `GMT+3 Istanbul`, expected an IANA name like Europe/Istanbul"
+        );
+    }
+}
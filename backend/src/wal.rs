@@ -0,0 +1,533 @@
+use crate::location_store::StoreSnapshot;
+use crate::persistence::{FilePersistence, Persistence};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// Snapshot file plus a log of the changes made since it was written
+///
+/// Each save appends only what changed since the previous one, as a single
+/// fsynced line, instead of rewriting the whole snapshot. Changes are kept
+/// per entry (a user, a friend request, one user's history, ...), so a
+/// location update costs about one user's worth of JSON. Once the log
+/// passes `compact_bytes`, the snapshot is rewritten and the log emptied.
+///
+/// Loading replays the log over the snapshot. Every line records the
+/// resulting values rather than operations, so replaying a log that was
+/// already folded into the snapshot (a crash between the two steps of a
+/// compaction) ends in the same state. A torn last line from a crash
+/// mid-append is dropped.
+pub struct WalPersistence {
+    snapshot: FilePersistence,
+    snapshot_path: PathBuf,
+    log_path: PathBuf,
+    compact_bytes: u64,
+    state: Mutex<LogState>,
+}
+
+#[derive(Default)]
+struct LogState {
+    /// What the snapshot and log add up to (`None` until loaded or compacted)
+    persisted: Option<KeyedSnapshot>,
+    /// Current log size in bytes
+    log_bytes: u64,
+}
+
+impl WalPersistence {
+    pub fn new(path: PathBuf, compact_bytes: u64) -> Self {
+        Self {
+            snapshot: FilePersistence::new(path.clone()),
+            log_path: path.with_extension("wal"),
+            snapshot_path: path,
+            compact_bytes,
+            state: Mutex::new(LogState::default()),
+        }
+    }
+
+    /// Rewrite the snapshot durably, then empty the log
+    fn compact(&self, snapshot: &StoreSnapshot, state: &mut LogState) -> Result<()> {
+        std::fs::create_dir_all(parent_dir(&self.snapshot_path))?;
+        let tmp = self.snapshot_path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&serde_json::to_vec(snapshot)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &self.snapshot_path)?;
+        sync_parent(&self.snapshot_path);
+
+        let log = File::create(&self.log_path)?;
+        log.sync_all()?;
+        state.persisted = Some(KeyedSnapshot::from_snapshot(snapshot)?);
+        state.log_bytes = 0;
+        Ok(())
+    }
+
+    /// Append one record and fsync it, returning the bytes written
+    fn append(&self, record: &WalRecord) -> Result<u64> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        std::fs::create_dir_all(parent_dir(&self.log_path))?;
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_path)
+            .with_context(|| format!("Can't open {}", self.log_path.display()))?;
+        log.write_all(&line)?;
+        log.sync_data()?;
+        Ok(line.len() as u64)
+    }
+}
+
+impl Persistence for WalPersistence {
+    fn load(&self) -> Result<Option<StoreSnapshot>> {
+        let base = self.snapshot.load()?;
+        let had_snapshot = base.is_some();
+        let mut keyed = KeyedSnapshot::from_snapshot(&base.unwrap_or_default())?;
+
+        let (records, good_bytes, torn) = read_log(&self.log_path, |record| keyed.apply(record))?;
+        if torn {
+            // Cut the partial line off, so new records don't follow garbage
+            warn!("⚠️ Dropped a torn record at the end of the store log");
+            let log = OpenOptions::new().write(true).open(&self.log_path)?;
+            log.set_len(good_bytes)?;
+            log.sync_all()?;
+        }
+        if records > 0 {
+            info!("💾 Replayed {} store log records", records);
+        }
+
+        let snapshot: StoreSnapshot = serde_json::from_value(keyed.to_value())
+            .context("Store log doesn't apply to the snapshot")?;
+        let mut state = self.state.lock().unwrap();
+        state.persisted = Some(keyed);
+        state.log_bytes = good_bytes;
+        Ok((had_snapshot || records > 0).then_some(snapshot))
+    }
+
+    fn save(&self, snapshot: &StoreSnapshot) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let Some(persisted) = &state.persisted else {
+            // Nothing to diff against yet
+            return self.compact(snapshot, &mut state);
+        };
+        let current = KeyedSnapshot::from_snapshot(snapshot)?;
+        let record = persisted.diff(&current);
+        if record.is_empty() {
+            return Ok(());
+        }
+        state.log_bytes += self.append(&record)?;
+        state.persisted = Some(current);
+        if state.log_bytes >= self.compact_bytes {
+            self.compact(snapshot, &mut state)?;
+        }
+        Ok(())
+    }
+}
+
+/// Apply every complete record in the log, returning how many there were,
+/// the length of the log up to the last good one, and whether a torn
+/// record followed it
+fn read_log(
+    path: &Path,
+    mut apply: impl FnMut(WalRecord) -> Result<()>,
+) -> Result<(usize, u64, bool)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0, false)),
+        Err(e) => return Err(e).with_context(|| format!("Can't read {}", path.display())),
+    };
+    let mut reader = BufReader::new(file);
+    let (mut records, mut good_bytes) = (0, 0);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok((records, good_bytes, false));
+        }
+        // Only the last line can be torn; a bad line with more after it
+        // means the log itself is corrupt
+        let complete = line.ends_with(b"\n");
+        match serde_json::from_slice::<WalRecord>(&line) {
+            Ok(record) if complete => {
+                apply(record)
+                    .with_context(|| format!("Bad record {} in store log", records + 1))?;
+                records += 1;
+                good_bytes += read as u64;
+            }
+            _ if reader.fill_buf()?.is_empty() => return Ok((records, good_bytes, true)),
+            _ => return Err(anyhow!("Corrupt record {} in store log", records + 1)),
+        }
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Make a rename durable by syncing its directory (best effort)
+fn sync_parent(path: &Path) {
+    if let Ok(dir) = File::open(parent_dir(path)) {
+        let _ = dir.sync_all();
+    }
+}
+
+/// Changes to a snapshot: new values for entries that were added or
+/// changed, and the keys of entries that were removed
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct WalRecord {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    set: BTreeMap<String, BTreeMap<String, Value>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    remove: BTreeMap<String, Vec<String>>,
+    /// Whole new values for fields that aren't collections
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    replace: BTreeMap<String, Value>,
+}
+
+impl WalRecord {
+    fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.remove.is_empty() && self.replace.is_empty()
+    }
+}
+
+/// A snapshot's JSON with every collection keyed by entry, so two can be
+/// diffed entry by entry
+#[derive(Debug, Clone)]
+struct KeyedSnapshot(BTreeMap<String, Field>);
+
+#[derive(Debug, Clone)]
+enum Field {
+    /// A JSON object, keyed by its own keys
+    Map(BTreeMap<String, Value>),
+    /// A JSON array, keyed by each element's `id`, or the element itself
+    List(BTreeMap<String, Value>),
+    Other(Value),
+}
+
+impl KeyedSnapshot {
+    /// Key a snapshot; going through `StoreSnapshot` means every field is
+    /// present, with the right shape, even for snapshots older than it
+    fn from_snapshot(snapshot: &StoreSnapshot) -> Result<Self> {
+        let Value::Object(fields) = serde_json::to_value(snapshot)? else {
+            return Err(anyhow!("Store snapshot isn't a JSON object"));
+        };
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| (name, Field::from_value(value)))
+            .collect();
+        Ok(Self(fields))
+    }
+
+    fn to_value(&self) -> Value {
+        let fields = self
+            .0
+            .iter()
+            .map(|(name, field)| (name.clone(), field.to_value()))
+            .collect();
+        Value::Object(fields)
+    }
+
+    /// What turns `self` into `newer`
+    fn diff(&self, newer: &Self) -> WalRecord {
+        let mut record = WalRecord::default();
+        for (name, field) in &newer.0 {
+            match (self.0.get(name), field) {
+                (Some(Field::Map(old) | Field::List(old)), Field::Map(new) | Field::List(new)) => {
+                    let set: BTreeMap<String, Value> = new
+                        .iter()
+                        .filter(|(key, value)| old.get(*key) != Some(*value))
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect();
+                    let removed: Vec<String> = old
+                        .keys()
+                        .filter(|key| !new.contains_key(*key))
+                        .cloned()
+                        .collect();
+                    if !set.is_empty() {
+                        record.set.insert(name.clone(), set);
+                    }
+                    if !removed.is_empty() {
+                        record.remove.insert(name.clone(), removed);
+                    }
+                }
+                (Some(Field::Other(old)), Field::Other(new)) if old == new => {}
+                _ => {
+                    record.replace.insert(name.clone(), field.to_value());
+                }
+            }
+        }
+        record
+    }
+
+    fn apply(&mut self, record: WalRecord) -> Result<()> {
+        for (name, value) in record.replace {
+            self.0.insert(name, Field::from_value(value));
+        }
+        for (name, set) in record.set {
+            match self.0.get_mut(&name) {
+                Some(Field::Map(entries) | Field::List(entries)) => entries.extend(set),
+                _ => return Err(anyhow!("`{}` isn't a collection", name)),
+            }
+        }
+        for (name, keys) in record.remove {
+            match self.0.get_mut(&name) {
+                Some(Field::Map(entries) | Field::List(entries)) => {
+                    for key in keys {
+                        entries.remove(&key);
+                    }
+                }
+                _ => return Err(anyhow!("`{}` isn't a collection", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Field {
+    fn from_value(value: Value) -> Self {
+        match value {
+            Value::Object(entries) => Self::Map(entries.into_iter().collect()),
+            Value::Array(items) => Self::List(
+                items
+                    .into_iter()
+                    .map(|item| (element_key(&item), item))
+                    .collect(),
+            ),
+            other => Self::Other(other),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Self::Map(entries) => Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect(),
+            ),
+            Self::List(entries) => Value::Array(entries.values().cloned().collect()),
+            Self::Other(value) => value.clone(),
+        }
+    }
+}
+
+/// Key of an array element: its `id` if it has one, the string itself for
+/// sets of IDs, and its JSON otherwise
+fn element_key(item: &Value) -> String {
+    match item {
+        Value::Object(fields) => match fields.get("id") {
+            Some(Value::String(id)) => id.clone(),
+            _ => item.to_string(),
+        },
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RequestLimits;
+    use crate::location_store::LocationStore;
+
+    /// Snapshot path in a directory of its own, removed when dropped
+    struct TempStore(PathBuf);
+
+    impl TempStore {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("wal-test-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir.join("store.json"))
+        }
+
+        fn log(&self) -> PathBuf {
+            self.0.with_extension("wal")
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(self.0.parent().unwrap());
+        }
+    }
+
+    /// A snapshot's JSON with collections in a stable order
+    fn normalized(snapshot: &StoreSnapshot) -> Value {
+        KeyedSnapshot::from_snapshot(snapshot).unwrap().to_value()
+    }
+
+    /// Snapshots of a store after each of a few changes: entries added,
+    /// changed and removed
+    async fn history_of_changes() -> Vec<StoreSnapshot> {
+        let limits = RequestLimits {
+            max_outgoing_pending: 10,
+            max_incoming_pending: 10,
+        };
+        let store = LocationStore::new();
+        let mut snapshots = vec![store.snapshot()];
+
+        store
+            .register_discovery_hashes("alice", vec!["h1".to_string(), "h2".to_string()])
+            .await;
+        store.set_discoverable("alice", true).await;
+        let request = store
+            .send_friend_request("alice", "bob", None, &limits)
+            .await
+            .unwrap();
+        snapshots.push(store.snapshot());
+
+        store
+            .decline_friend_request(&request.id, "bob")
+            .await
+            .unwrap();
+        store
+            .register_discovery_hashes("carol", vec!["h3".to_string()])
+            .await;
+        snapshots.push(store.snapshot());
+
+        store.delete_user("alice").await;
+        snapshots.push(store.snapshot());
+        snapshots
+    }
+
+    fn record_line(record: &WalRecord) -> String {
+        format!("{}\n", serde_json::to_string(record).unwrap())
+    }
+
+    fn set_record(key: &str) -> WalRecord {
+        let mut record = WalRecord::default();
+        record.set.insert(
+            "discovery_hashes".to_string(),
+            BTreeMap::from([(key.to_string(), Value::String("alice".to_string()))]),
+        );
+        record
+    }
+
+    #[test]
+    fn torn_last_line_is_dropped() {
+        let dir = TempStore::new();
+        let good = record_line(&set_record("h1")) + &record_line(&set_record("h2"));
+        std::fs::write(dir.log(), format!("{}{{\"set\":{{\"disc", good)).unwrap();
+
+        let mut applied = Vec::new();
+        let (records, good_bytes, torn) = read_log(&dir.log(), |record| {
+            applied.push(record);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(records, 2);
+        assert_eq!(applied.len(), 2);
+        assert_eq!(good_bytes, good.len() as u64);
+        assert!(torn);
+    }
+
+    #[test]
+    fn corrupt_line_before_the_end_is_an_error() {
+        let dir = TempStore::new();
+        let log = record_line(&set_record("h1")) + "not json\n" + &record_line(&set_record("h2"));
+        std::fs::write(dir.log(), log).unwrap();
+
+        let err = read_log(&dir.log(), |_| Ok(())).unwrap_err();
+        assert_eq!(err.to_string(), "Corrupt record 2 in store log");
+    }
+
+    #[test]
+    fn missing_log_is_empty() {
+        let dir = TempStore::new();
+        assert_eq!(read_log(&dir.log(), |_| Ok(())).unwrap(), (0, 0, false));
+    }
+
+    #[tokio::test]
+    async fn diff_applied_to_the_old_snapshot_gives_the_new_one() {
+        let snapshots = history_of_changes().await;
+        for pair in snapshots.windows(2) {
+            let mut older = KeyedSnapshot::from_snapshot(&pair[0]).unwrap();
+            let newer = KeyedSnapshot::from_snapshot(&pair[1]).unwrap();
+            let record = older.diff(&newer);
+            assert!(!record.is_empty());
+
+            // Through JSON, as it goes in the log
+            let record = serde_json::from_str(&record_line(&record)).unwrap();
+            older.apply(record).unwrap();
+            assert_eq!(older.to_value(), newer.to_value());
+            assert!(older.diff(&newer).is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn snapshot_and_partial_log_recover_every_complete_save() {
+        let dir = TempStore::new();
+        let snapshots = history_of_changes().await;
+        let wal = WalPersistence::new(dir.0.clone(), u64::MAX);
+        for snapshot in &snapshots {
+            wal.save(snapshot).unwrap();
+        }
+        let log_len = std::fs::metadata(dir.log()).unwrap().len();
+        assert!(log_len > 0);
+
+        // Crash partway through appending the next record
+        let mut log = OpenOptions::new().append(true).open(dir.log()).unwrap();
+        log.write_all(b"{\"set\":{\"users\"").unwrap();
+        drop(log);
+
+        let restarted = WalPersistence::new(dir.0.clone(), u64::MAX);
+        let loaded = restarted.load().unwrap().unwrap();
+        assert_eq!(normalized(&loaded), normalized(snapshots.last().unwrap()));
+        assert_eq!(std::fs::metadata(dir.log()).unwrap().len(), log_len);
+
+        // New records follow the good ones and replay too
+        restarted.save(&snapshots[1]).unwrap();
+        let loaded = WalPersistence::new(dir.0.clone(), u64::MAX)
+            .load()
+            .unwrap()
+            .unwrap();
+        assert_eq!(normalized(&loaded), normalized(&snapshots[1]));
+    }
+
+    #[tokio::test]
+    async fn log_already_in_the_snapshot_replays_to_the_same_state() {
+        let dir = TempStore::new();
+        let snapshots = history_of_changes().await;
+        let wal = WalPersistence::new(dir.0.clone(), u64::MAX);
+        for snapshot in &snapshots {
+            wal.save(snapshot).unwrap();
+        }
+
+        // Crash during compaction: the new snapshot is in place, but the log
+        // it folded in wasn't emptied yet
+        let latest = snapshots.last().unwrap();
+        std::fs::write(&dir.0, serde_json::to_vec(latest).unwrap()).unwrap();
+        assert!(std::fs::metadata(dir.log()).unwrap().len() > 0);
+
+        let loaded = WalPersistence::new(dir.0.clone(), u64::MAX)
+            .load()
+            .unwrap()
+            .unwrap();
+        assert_eq!(normalized(&loaded), normalized(latest));
+    }
+
+    #[tokio::test]
+    async fn compaction_empties_the_log() {
+        let dir = TempStore::new();
+        let snapshots = history_of_changes().await;
+        let wal = WalPersistence::new(dir.0.clone(), 1);
+        for snapshot in &snapshots {
+            wal.save(snapshot).unwrap();
+        }
+        assert_eq!(std::fs::metadata(dir.log()).unwrap().len(), 0);
+
+        let loaded = WalPersistence::new(dir.0.clone(), 1)
+            .load()
+            .unwrap()
+            .unwrap();
+        assert_eq!(normalized(&loaded), normalized(snapshots.last().unwrap()));
+    }
+}